@@ -0,0 +1,101 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::mesh_functions
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::pbr_ambient
+#import bevy_pbr::shadows
+#import bevy_pbr::fog
+#import bevy_pbr::pbr_functions
+
+@group(1) @binding(0)
+var block_textures: texture_2d_array<f32>;
+@group(1) @binding(1)
+var block_textures_sampler: sampler;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) texture_layer: u32,
+    @location(4) color: vec4<f32>,
+    @location(5) ambient_occlusion: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) @interpolate(flat) texture_layer: u32,
+    @location(4) color: vec4<f32>,
+    @location(5) ambient_occlusion: f32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
+    out.clip_position = mesh_position_world_to_clip(out.world_position);
+    out.world_normal = mesh_normal_local_to_world(vertex.normal);
+    out.uv = vertex.uv;
+    out.texture_layer = vertex.texture_layer;
+    out.color = vertex.color;
+    out.ambient_occlusion = vertex.ambient_occlusion;
+    return out;
+}
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) @interpolate(flat) texture_layer: u32,
+    @location(4) color: vec4<f32>,
+    @location(5) ambient_occlusion: f32,
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    var pbr_input: PbrInput = pbr_input_new();
+    pbr_input.material.base_color = textureSample(
+        block_textures,
+        block_textures_sampler,
+        in.uv,
+        i32(in.texture_layer),
+    ) * vec4<f32>(in.color.rgb * in.ambient_occlusion, in.color.a);
+#ifdef TRANSLUCENT
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_ALPHA_MODE_PREMULTIPLIED;
+#else
+    // cutout blocks like leaves and plants
+    if (pbr_input.material.base_color.a < 0.5) {
+        discard;
+    }
+#endif
+
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normalize(pbr_input.world_normal);
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh.flags;
+
+    var output_color = pbr(pbr_input);
+
+    if (fog.mode != FOG_MODE_OFF) {
+        output_color = apply_fog(output_color, in.world_position.xyz, view.world_position.xyz);
+    }
+#ifdef TONEMAP_IN_SHADER
+    output_color = tone_mapping(output_color);
+#endif
+#ifdef TRANSLUCENT
+    // blended with AlphaMode::Premultiplied
+    output_color = vec4<f32>(output_color.rgb * output_color.a, output_color.a);
+#endif
+    return output_color;
+}
@@ -0,0 +1,89 @@
+use bevy::prelude::IVec3;
+use criterion::{
+    black_box, criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, BenchmarkId,
+    Criterion,
+};
+
+use voxel_engine_prototype_lib::voxels::{
+    chunk::{Chunk, ChunkPosition},
+    palette_chunk::PaletteChunk,
+    terrain_generation::{ProceduralGenerator, VoxelGenerator},
+};
+
+/// Chunks of a 8x4x8 area around the terrain surface
+fn generate_terrain<const N: usize>() -> Vec<Chunk<N>> {
+    let gen = ProceduralGenerator::<N>::new(42);
+    let mut chunks = Vec::new();
+    for x in -4..4 {
+        for y in -2..2 {
+            for z in -4..4 {
+                let mut chunk = Chunk::<N>::new();
+                gen.fill_random(&ChunkPosition::new(IVec3::new(x, y, z)), chunk.data_mut());
+                chunks.push(chunk);
+            }
+        }
+    }
+    chunks
+}
+
+fn memory_report<const N: usize>(chunks: &[Chunk<N>]) {
+    let paletted = chunks.iter().map(PaletteChunk::from).collect::<Vec<_>>();
+    let dense_bytes: usize = chunks.iter().map(|c| c.memory_usage()).sum();
+    let light_bytes: usize = chunks.iter().map(|c| c.light_memory_usage()).sum();
+    // palette chunks don't store light
+    let dense_voxel_bytes = dense_bytes - light_bytes;
+    let palette_bytes: usize = paletted.iter().map(|c| c.memory_usage()).sum();
+    let uniform = paletted.iter().filter(|c| c.is_uniform()).count();
+
+    println!(
+        "terrain/{N}: {} chunks, {uniform} uniform, dense {} KiB ({} KiB voxels, {} KiB light), \
+         palette voxels {} KiB ({:.1}% of dense voxels)",
+        chunks.len(),
+        dense_bytes / 1024,
+        dense_voxel_bytes / 1024,
+        light_bytes / 1024,
+        palette_bytes / 1024,
+        palette_bytes as f64 / dense_voxel_bytes as f64 * 100.
+    );
+}
+
+pub fn chunk_storage(c: &mut Criterion) {
+    fn bench_const<const N: usize>(group: &mut BenchmarkGroup<WallTime>) {
+        let chunks = generate_terrain::<N>();
+        memory_report(&chunks);
+        let paletted = chunks.iter().map(PaletteChunk::from).collect::<Vec<_>>();
+
+        group.bench_function(BenchmarkId::new("pack", N), |b| {
+            b.iter(|| {
+                chunks
+                    .iter()
+                    .map(PaletteChunk::from)
+                    .filter(|c| c.is_uniform())
+                    .count()
+            })
+        });
+        group.bench_function(BenchmarkId::new("unpack", N), |b| {
+            b.iter(|| paletted.iter().map(|c| c.data().len()).sum::<usize>())
+        });
+        group.bench_function(BenchmarkId::new("get", N), |b| {
+            b.iter(|| {
+                paletted
+                    .iter()
+                    .map(|c| c.get(black_box([N / 2, N / 3, N - 1])).id as usize)
+                    .sum::<usize>()
+            })
+        });
+    }
+
+    let mut group = c.benchmark_group("chunk_storage");
+
+    group.noise_threshold(0.1);
+
+    bench_const::<16>(&mut group);
+    bench_const::<32>(&mut group);
+
+    group.finish();
+}
+
+criterion_group!(benches, chunk_storage);
+criterion_main!(benches);
@@ -5,17 +5,32 @@ use criterion::{
 };
 
 use voxel_engine_prototype_lib::voxels::{
+    caves::CaveConfig,
     chunk::{Chunk, ChunkPosition},
     terrain_generation::{ProceduralGenerator, VoxelGenerator},
 };
 
 pub fn generation(c: &mut Criterion) {
-    fn bench_const<const N: usize>(group: &mut BenchmarkGroup<WallTime>, id: BenchmarkId) {
+    fn bench_const<const N: usize>(
+        group: &mut BenchmarkGroup<WallTime>,
+        id: BenchmarkId,
+        caves: Option<CaveConfig>,
+        pos: IVec3,
+    ) {
         group.bench_function(id, |b| {
             b.iter_batched(
-                || (ProceduralGenerator::<N>::new(42), Chunk::<N>::new()),
+                || {
+                    let gen = ProceduralGenerator::<N>::new(42);
+                    let gen = match caves.clone() {
+                        Some(caves) => gen.with_caves(caves),
+                        None => gen,
+                    };
+                    (gen, Chunk::<N>::new())
+                },
                 |(gen, mut ch)| {
-                    gen.fill_random(&ChunkPosition::new(IVec3::from([0, 0, 0])), ch.data_mut())
+                    let pos = ChunkPosition::new(pos);
+                    gen.fill_random(&pos, ch.data_mut());
+                    gen.carve(&pos, ch.data_mut());
                 },
                 BatchSize::SmallInput,
             )
@@ -26,9 +41,64 @@ pub fn generation(c: &mut Criterion) {
 
     group.noise_threshold(0.1);
 
-    bench_const::<16>(&mut group, BenchmarkId::new("generate", 16));
-    bench_const::<32>(&mut group, BenchmarkId::new("generate", 32));
-    bench_const::<64>(&mut group, BenchmarkId::new("generate", 64));
+    bench_const::<16>(
+        &mut group,
+        BenchmarkId::new("generate", 16),
+        None,
+        IVec3::ZERO,
+    );
+    bench_const::<32>(
+        &mut group,
+        BenchmarkId::new("generate", 32),
+        None,
+        IVec3::ZERO,
+    );
+    bench_const::<64>(
+        &mut group,
+        BenchmarkId::new("generate", 64),
+        None,
+        IVec3::ZERO,
+    );
+
+    // underground chunks where every voxel is in the cave depth range
+    let caves = Some(CaveConfig::default());
+    let underground = IVec3::new(0, -1, 0);
+    bench_const::<16>(
+        &mut group,
+        BenchmarkId::new("generate_underground", 16),
+        None,
+        underground * 8,
+    );
+    bench_const::<16>(
+        &mut group,
+        BenchmarkId::new("generate_caves", 16),
+        caves.clone(),
+        underground * 8,
+    );
+    bench_const::<32>(
+        &mut group,
+        BenchmarkId::new("generate_underground", 32),
+        None,
+        underground * 4,
+    );
+    bench_const::<32>(
+        &mut group,
+        BenchmarkId::new("generate_caves", 32),
+        caves.clone(),
+        underground * 4,
+    );
+    bench_const::<64>(
+        &mut group,
+        BenchmarkId::new("generate_underground", 64),
+        None,
+        underground * 2,
+    );
+    bench_const::<64>(
+        &mut group,
+        BenchmarkId::new("generate_caves", 64),
+        caves,
+        underground * 2,
+    );
 
     group.finish();
 }
@@ -11,7 +11,11 @@ use rand::prelude::*;
 use voxel_engine_prototype_lib::{
     directions::Directions,
     voxels::{
-        chunk::ChunkPosition, terrain_generation::VoxelGenerator, voxel::Voxel, world::VoxelWorld,
+        chunk::ChunkPosition,
+        chunk_mesh::MeshingMode,
+        terrain_generation::{ProceduralGenerator, VoxelGenerator},
+        voxel::Voxel,
+        world::VoxelWorld,
     },
 };
 
@@ -33,35 +37,51 @@ impl<const N: usize> VoxelGenerator<N> for RandomGenerator<N> {
     }
 }
 
-fn setup<const N: usize>() -> VoxelWorld<RandomGenerator<N>, N> {
-    let world = VoxelWorld::new(RandomGenerator::new(42));
+fn setup<G: VoxelGenerator<N> + Send + Sync, const N: usize>(generator: G) -> VoxelWorld<G, N> {
+    let mut world = VoxelWorld::new(generator, Default::default());
     let pos = IVec3::new(0, 0, 0);
-    world.gen_chunk(&ChunkPosition::new(pos));
-    for dir in Directions::all().into_iter() {
-        let dir_vec = dir.to_ivec();
-        world.gen_chunk(&ChunkPosition::new(pos + dir_vec));
+    for pos in std::iter::once(pos).chain(Directions::all().into_iter().map(|d| pos + d.to_ivec()))
+    {
+        let pos = ChunkPosition::new(pos);
+        let chunk = world.gen_chunk(&pos);
+        world.insert_at(&pos, chunk);
     }
     world
 }
 
 pub fn meshing(c: &mut Criterion) {
-    fn bench_const<const N: usize>(group: &mut BenchmarkGroup<WallTime>, id: BenchmarkId) {
-        group.bench_function(id, |b| {
-            b.iter_batched(
-                setup::<N>,
-                |world| world.mesh(&ChunkPosition::new([0, 0, 0].into())),
-                BatchSize::SmallInput,
-            )
-        });
+    fn bench_const<G: VoxelGenerator<N> + Send + Sync, const N: usize>(
+        group: &mut BenchmarkGroup<WallTime>,
+        name: &str,
+        generator: fn() -> G,
+    ) {
+        for mode in [MeshingMode::Naive, MeshingMode::Greedy] {
+            let vertices = setup::<G, N>(generator())
+                .mesh_with(&ChunkPosition::new([0, 0, 0].into()), mode)
+                .vertex_count();
+            println!("{name}/{mode:?}/{N}: {vertices} vertices");
+
+            group.bench_function(BenchmarkId::new(format!("{name}/{mode:?}"), N), |b| {
+                b.iter_batched(
+                    || setup::<G, N>(generator()),
+                    |world| world.mesh_with(&ChunkPosition::new([0, 0, 0].into()), mode),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
     }
 
     let mut group = c.benchmark_group("meshing");
 
     group.noise_threshold(0.1);
 
-    bench_const::<16>(&mut group, BenchmarkId::new("mesh", 16));
-    bench_const::<32>(&mut group, BenchmarkId::new("mesh", 32));
-    bench_const::<64>(&mut group, BenchmarkId::new("mesh", 64));
+    bench_const::<_, 16>(&mut group, "random", || RandomGenerator::new(42));
+    bench_const::<_, 32>(&mut group, "random", || RandomGenerator::new(42));
+    bench_const::<_, 64>(&mut group, "random", || RandomGenerator::new(42));
+
+    bench_const::<_, 16>(&mut group, "terrain", || ProceduralGenerator::new(42));
+    bench_const::<_, 32>(&mut group, "terrain", || ProceduralGenerator::new(42));
+    bench_const::<_, 64>(&mut group, "terrain", || ProceduralGenerator::new(42));
 
     group.finish();
 }
@@ -1,21 +1,22 @@
 use std::{error::Error, path::Path};
 
-use bevy::{
-    log::Level,
-    prelude::*,
-    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
-};
+use bevy::{log::Level, prelude::*};
 use bevy_prototype_debug_lines::DebugLinesPlugin;
 use voxel_engine_prototype_lib::{
     camera_move_system::{camera_move_system, CameraMoveSensitivity},
     game_config::{GameConfig, GameConfigPlugin},
     ui::bundle::DebugUiBundle,
     voxels::{
+        biomes::BiomeRegistry,
+        block_registry::BlockRegistry,
+        block_texture_array::load_block_texture_array,
         bundle::VoxelBundle,
-        systems::{
-            components::{DestroyVoxOnTouch, GenerateMapAround, RenderAround},
-            materials::Materials,
+        caves::CaveConfig,
+        systems::components::{
+            BlockInteraction, DestroyVoxOnTouch, GenerateMapAround, RenderAround,
         },
+        underground::Underground,
+        voxel::Voxel,
     },
 };
 
@@ -25,6 +26,17 @@ fn main() -> Result<(), Box<dyn Error>> {
     //     .add_bundle(LoaderBundle)
     //     .add_bundle(VoxelBundle::default())
     let config_path = Path::new("config");
+    let block_registry = BlockRegistry::from_file_ron(config_path.join("blocks.ron"))?;
+    let biomes = BiomeRegistry::from_file_ron(config_path.join("biomes.ron"), &block_registry)?;
+    let caves = CaveConfig::from_file_ron(config_path.join("caves.ron"))?;
+    let underground =
+        Underground::from_file_ron(config_path.join("underground.ron"), &block_registry)?;
+    let block_textures = load_block_texture_array(Path::new("assets/blocks"), &block_registry)?;
+    let first_block = block_registry
+        .blocks()
+        .find(|b| b.is_rendered())
+        .map(|b| Voxel { id: b.id })
+        .unwrap_or_default();
 
     App::new()
         .add_plugins(DefaultPlugins.set(bevy::log::LogPlugin {
@@ -35,9 +47,15 @@ fn main() -> Result<(), Box<dyn Error>> {
             config_path.join("game_configs.ron"),
         )?))
         .add_plugin(DebugLinesPlugin::with_depth_test(true))
-        .add_plugin(VoxelBundle)
+        .add_plugin(VoxelBundle::new(
+            block_registry,
+            biomes,
+            caves,
+            underground,
+            block_textures,
+        ))
         .add_plugin(DebugUiBundle)
-        .add_startup_system(startup)
+        .add_startup_system(move |commands: Commands| startup(commands, first_block))
         .add_startup_system(add_camera_settings)
         .add_system(camera_move_system)
         .run();
@@ -49,20 +67,7 @@ fn add_camera_settings(mut commands: Commands) {
     commands.insert_resource(CameraMoveSensitivity::default());
 }
 
-fn startup(
-    mut commands: Commands,
-    mut images: ResMut<Assets<Image>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    let debug_material = materials.add(StandardMaterial {
-        base_color_texture: Some(images.add(uv_debug_texture())),
-        ..default()
-    });
-
-    commands.insert_resource(Materials {
-        material: debug_material,
-    });
-
+fn startup(mut commands: Commands, selected_block: Voxel) {
     commands.spawn(bevy::pbr::DirectionalLightBundle {
         directional_light: default(),
         transform: Transform::default().looking_to(Vec3::NEG_Y, Vec3::NEG_Z),
@@ -75,33 +80,10 @@ fn startup(
                 .looking_at(Vec3::new(0., 1., 0.), Vec3::Y),
             ..default()
         })
-        .insert((RenderAround, GenerateMapAround, DestroyVoxOnTouch));
-}
-
-/// Creates a colorful test pattern
-fn uv_debug_texture() -> Image {
-    const TEXTURE_SIZE: usize = 8;
-
-    let mut palette: [u8; 32] = [
-        255, 102, 159, 255, 255, 159, 102, 255, 236, 255, 102, 255, 121, 255, 102, 255, 102, 255,
-        198, 255, 102, 198, 255, 255, 121, 102, 255, 255, 236, 102, 255, 255,
-    ];
-
-    let mut texture_data = [0; TEXTURE_SIZE * TEXTURE_SIZE * 4];
-    for y in 0..TEXTURE_SIZE {
-        let offset = TEXTURE_SIZE * y * 4;
-        texture_data[offset..(offset + TEXTURE_SIZE * 4)].copy_from_slice(&palette);
-        palette.rotate_right(4);
-    }
-
-    Image::new_fill(
-        Extent3d {
-            width: TEXTURE_SIZE as u32,
-            height: TEXTURE_SIZE as u32,
-            depth_or_array_layers: 1,
-        },
-        TextureDimension::D2,
-        &texture_data,
-        TextureFormat::Rgba8UnormSrgb,
-    )
+        .insert((
+            RenderAround,
+            GenerateMapAround,
+            DestroyVoxOnTouch,
+            BlockInteraction::new(selected_block),
+        ));
 }
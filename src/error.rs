@@ -8,6 +8,18 @@ pub enum Error {
     SerializationRon(#[from] ron::error::SpannedError),
     #[error("Toml Serialization error")]
     SerializationToml(#[from] toml::de::Error),
+    #[error("Invalid block registry: {0}")]
+    InvalidBlockRegistry(String),
+    #[error("Texture error")]
+    Texture(#[from] bevy::render::texture::TextureError),
+    #[error("Invalid block textures: {0}")]
+    InvalidBlockTextures(String),
+    #[error("Corrupt region file: {0}")]
+    CorruptRegion(String),
+    #[error("Invalid biomes: {0}")]
+    InvalidBiomes(String),
+    #[error("Invalid underground: {0}")]
+    InvalidUnderground(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
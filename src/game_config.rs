@@ -1,15 +1,40 @@
 use bevy::prelude::{warn, Plugin, Resource};
 use serde::{Deserialize, Serialize};
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::error;
+use crate::{error, voxels::chunk_mesh::MeshingMode};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GameConfig {
+    /// Seed of the terrain generator
+    pub world_seed: u32,
     pub generation_maintain_fps: f32,
     pub render_around_bubble: usize,
     pub generate_around_bubble: usize,
+    /// Distances in chunks from render loaders each coarser level of detail starts at,
+    /// every level halves the resolution chunks are meshed at
+    #[serde(default)]
+    pub lod_distances: Vec<usize>,
+    /// Chunks further than generate bubble plus this margin are unloaded
+    pub unload_hysteresis: usize,
+    /// Max distance to blocks which can be broken or placed against
+    pub reach_distance: f32,
+    /// Directory with region files of the world
+    pub save_directory: PathBuf,
+    /// Seconds between saves of modified chunks
+    pub autosave_interval: f32,
+    /// Empty voxels below this height are generated as water
+    #[serde(default)]
+    pub sea_level: Option<i32>,
+    /// World ticks per second, block updates and fluids run on ticks
+    pub ticks_per_second: f32,
+    /// Voxels picked in each chunk for random ticks every world tick
+    pub random_ticks_per_chunk: u32,
+    /// Chunks at most this far from loaders get random ticks
+    pub simulation_distance: usize,
+    /// World ticks between steps of the fluid simulation
+    pub fluid_tick_interval: u32,
 }
 
 impl GameConfig {
@@ -23,6 +48,16 @@ impl GameConfig {
                 config.render_around_bubble, config.generate_around_bubble
             );
         }
+        if !config
+            .lod_distances
+            .windows(2)
+            .all(|pair| pair[0] < pair[1])
+        {
+            warn!(
+                "Level of detail distances should be ascending: {:?}",
+                config.lod_distances
+            );
+        }
         Ok(config)
     }
 }
@@ -30,8 +65,17 @@ impl GameConfig {
 #[derive(Resource)]
 pub struct RuntimeGameConfig {
     pub chunks_render_per_frame: u32,
+    /// Max meshing tasks running at once
+    pub meshing_tasks_in_flight: u32,
     pub chunks_generate_per_frame: u32,
+    /// Max terrain generation tasks running at once
+    pub generation_tasks_in_flight: u32,
+    /// Max generated chunks decorated and lit on the main thread per frame
+    pub chunks_complete_per_frame: u32,
     pub debug_show_edge_chunks: bool,
+    /// Draws outlines of chunks hidden by frustum and cave culling
+    pub debug_show_culled_chunks: bool,
+    pub meshing_mode: MeshingMode,
     pub config: GameConfig,
 }
 
@@ -40,8 +84,13 @@ impl From<GameConfig> for RuntimeGameConfig {
         Self {
             config: conf,
             chunks_generate_per_frame: 10,
+            generation_tasks_in_flight: 64,
+            chunks_complete_per_frame: 4,
             chunks_render_per_frame: 50,
+            meshing_tasks_in_flight: 64,
             debug_show_edge_chunks: false,
+            debug_show_culled_chunks: false,
+            meshing_mode: MeshingMode::Greedy,
         }
     }
 }
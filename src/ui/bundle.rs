@@ -15,7 +15,7 @@ pub struct DebugUiBundle;
 
 impl Plugin for DebugUiBundle {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.add_plugin(FrameTimeDiagnosticsPlugin::default());
+        app.add_plugin(FrameTimeDiagnosticsPlugin);
         app.add_system(chunk_counter_ui_system);
         app.add_system(fps_ui_system);
         app.add_system(current_chunk_info_system);
@@ -19,8 +19,8 @@ pub fn current_chunk_info_system(
         let mut text = ui_text.single_mut();
         text.sections[1].value = format!(
             "transp: {}; nontransp: {}",
-            chunk.is_transparent(),
-            chunk.is_nontransparent()
+            chunk.is_transparent(voxel_world.registry()),
+            chunk.is_nontransparent(voxel_world.registry())
         );
     }
 }
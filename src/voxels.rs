@@ -1,8 +1,31 @@
+pub mod biomes;
+pub mod block_behaviors;
+pub mod block_registry;
+pub mod block_texture_array;
+pub mod block_ticks;
 pub mod bundle;
+pub mod caves;
 pub mod chunk;
+pub mod chunk_material;
 pub mod chunk_mesh;
+pub mod falling_blocks;
+pub mod fluids;
+pub mod generation_stages;
+pub mod generation_tasks;
+pub mod light;
+pub mod lod;
+pub mod mesh_priority;
+pub mod mesh_snapshot;
+pub mod meshing_tasks;
+pub mod palette_chunk;
+pub mod region_storage;
 pub mod resources;
 pub mod systems;
 pub mod terrain_generation;
+#[cfg(test)]
+pub mod test_utils;
+pub mod underground;
+pub mod vegetation;
+pub mod visibility;
 pub mod voxel;
 pub mod world;
@@ -0,0 +1,398 @@
+use std::path::Path;
+
+use bevy::math::DVec2;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Error};
+
+use super::{block_registry::BlockRegistry, voxel::Voxel};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BiomeDescriptor {
+    pub name: String,
+    /// Position of the biome in climate space, roughly in [-1, 1]
+    pub temperature: f64,
+    pub humidity: f64,
+    /// Points `(noise, height)` of a piecewise linear curve mapping height noise to column height
+    pub height_curve: Vec<(f64, f64)>,
+    /// Block names of the topmost voxel, the layer below it and everything further down
+    pub surface: String,
+    pub subsurface: String,
+    pub subsurface_depth: u32,
+    pub underground: String,
+    #[serde(default)]
+    pub vegetation: VegetationDescriptor,
+}
+
+/// What grows on surface voxels of a biome
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VegetationDescriptor {
+    /// Names of trees, one of which is picked for a tree column
+    #[serde(default)]
+    pub trees: Vec<String>,
+    /// Chance of a surface column to have a tree
+    #[serde(default)]
+    pub tree_chance: f64,
+    /// Block names of plants with the chance of a surface column to have each
+    #[serde(default)]
+    pub plants: Vec<(String, f64)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TreeDescriptor {
+    pub name: String,
+    pub trunk: String,
+    pub leaves: String,
+    /// Inclusive range of the trunk height
+    pub trunk_height: (u32, u32),
+    /// Horizontal radius of the leaf canopy around the top of the trunk
+    pub canopy_radius: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BiomesConfig {
+    /// Horizontal distance in voxels over which climate noise changes noticeably
+    pub climate_scale: f64,
+    /// Horizontal distance in voxels over which height noise changes noticeably
+    pub height_scale: f64,
+    /// Biomes whose climate distance is within this of the nearest biome's are blended in
+    pub blend_width: f64,
+    pub biomes: Vec<BiomeDescriptor>,
+    #[serde(default)]
+    pub trees: Vec<TreeDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    pub name: String,
+    pub climate: DVec2,
+    height_curve: Vec<(f64, f64)>,
+    pub surface: Voxel,
+    pub subsurface: Voxel,
+    pub subsurface_depth: u32,
+    pub underground: Voxel,
+    pub vegetation: Vegetation,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Vegetation {
+    /// Indices of trees in the registry
+    pub trees: Vec<usize>,
+    pub tree_chance: f64,
+    pub plants: Vec<(Voxel, f64)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeTemplate {
+    pub name: String,
+    pub trunk: Voxel,
+    pub leaves: Voxel,
+    pub trunk_height: (u32, u32),
+    pub canopy_radius: u32,
+}
+
+impl Biome {
+    /// Column height for the height noise value, clamped to the curve ends
+    pub fn height(&self, noise: f64) -> f64 {
+        let curve = &self.height_curve;
+        match curve.iter().position(|&(point, _)| point > noise) {
+            Some(0) => curve[0].1,
+            Some(i) => {
+                let (x0, y0) = curve[i - 1];
+                let (x1, y1) = curve[i];
+                y0 + (y1 - y0) * (noise - x0) / (x1 - x0)
+            }
+            None => curve[curve.len() - 1].1,
+        }
+    }
+
+    /// Block at `depth` voxels below the top of the column
+    #[inline]
+    pub fn block_at_depth(&self, depth: u32) -> Voxel {
+        match depth {
+            0 => self.surface,
+            d if d <= self.subsurface_depth => self.subsurface,
+            _ => self.underground,
+        }
+    }
+}
+
+/// Biomes selected by temperature and humidity, with block names resolved to voxels
+#[derive(Debug, Clone)]
+pub struct BiomeRegistry {
+    pub climate_scale: f64,
+    pub height_scale: f64,
+    pub blend_width: f64,
+    biomes: Vec<Biome>,
+    trees: Vec<TreeTemplate>,
+}
+
+impl BiomeRegistry {
+    pub fn new(config: BiomesConfig, blocks: &BlockRegistry) -> error::Result<Self> {
+        if config.biomes.is_empty() {
+            return Err(Error::InvalidBiomes("no biomes defined".to_owned()));
+        }
+        if config.blend_width < 0. {
+            return Err(Error::InvalidBiomes(
+                "blend width can't be negative".to_owned(),
+            ));
+        }
+
+        let block = |owner: &str, name: &str| {
+            blocks
+                .id_of(name)
+                .map(|id| Voxel { id })
+                .ok_or_else(|| Error::InvalidBiomes(format!("{owner} uses unknown block {name}")))
+        };
+        let trees = config
+            .trees
+            .into_iter()
+            .map(|desc| {
+                let owner = format!("tree {}", desc.name);
+                if desc.trunk_height.0 > desc.trunk_height.1 {
+                    return Err(Error::InvalidBiomes(format!(
+                        "{owner} has an empty trunk height range"
+                    )));
+                }
+                Ok(TreeTemplate {
+                    trunk: block(&owner, &desc.trunk)?,
+                    leaves: block(&owner, &desc.leaves)?,
+                    trunk_height: desc.trunk_height,
+                    canopy_radius: desc.canopy_radius,
+                    name: desc.name,
+                })
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+
+        let biomes = config
+            .biomes
+            .into_iter()
+            .map(|desc| {
+                let sorted = desc.height_curve.windows(2).all(|w| w[0].0 < w[1].0);
+                if desc.height_curve.is_empty() || !sorted {
+                    return Err(Error::InvalidBiomes(format!(
+                        "height curve of biome {} must be non-empty and sorted by noise",
+                        desc.name
+                    )));
+                }
+                let owner = format!("biome {}", desc.name);
+                let vegetation = Vegetation {
+                    trees: desc
+                        .vegetation
+                        .trees
+                        .iter()
+                        .map(|name| {
+                            trees.iter().position(|t| &t.name == name).ok_or_else(|| {
+                                Error::InvalidBiomes(format!("{owner} uses unknown tree {name}"))
+                            })
+                        })
+                        .collect::<error::Result<_>>()?,
+                    tree_chance: desc.vegetation.tree_chance,
+                    plants: desc
+                        .vegetation
+                        .plants
+                        .iter()
+                        .map(|(name, chance)| Ok((block(&owner, name)?, *chance)))
+                        .collect::<error::Result<_>>()?,
+                };
+                Ok(Biome {
+                    surface: block(&owner, &desc.surface)?,
+                    subsurface: block(&owner, &desc.subsurface)?,
+                    underground: block(&owner, &desc.underground)?,
+                    vegetation,
+                    climate: DVec2::new(desc.temperature, desc.humidity),
+                    height_curve: desc.height_curve,
+                    subsurface_depth: desc.subsurface_depth,
+                    name: desc.name,
+                })
+            })
+            .collect::<error::Result<_>>()?;
+
+        Ok(Self {
+            climate_scale: config.climate_scale,
+            height_scale: config.height_scale,
+            blend_width: config.blend_width,
+            biomes,
+            trees,
+        })
+    }
+
+    pub fn from_file_ron<P: AsRef<Path>>(path: P, blocks: &BlockRegistry) -> error::Result<Self> {
+        let str = std::fs::read_to_string(path)?;
+        let config: BiomesConfig = ron::from_str(str.as_ref())?;
+        Self::new(config, blocks)
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    pub fn trees(&self) -> &[TreeTemplate] {
+        &self.trees
+    }
+
+    /// Whether the voxel is a plant growing in any of the biomes
+    pub fn is_plant(&self, voxel: Voxel) -> bool {
+        self.biomes
+            .iter()
+            .any(|b| b.vegetation.plants.iter().any(|&(plant, _)| plant == voxel))
+    }
+
+    /// Index of the biome nearest to `climate`
+    pub fn nearest(&self, climate: DVec2) -> usize {
+        self.biomes
+            .iter()
+            .map(|b| b.climate.distance_squared(climate))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+            .unwrap()
+    }
+
+    /// Column height blended between biomes near `climate` in climate space.
+    /// The nearest biome has weight 1, others fade out linearly over the blend width
+    pub fn blended_height(&self, climate: DVec2, noise: f64) -> f64 {
+        let nearest = self.biomes[self.nearest(climate)].climate.distance(climate);
+        let mut height = 0.;
+        let mut total = 0.;
+        for biome in &self.biomes {
+            let excess = biome.climate.distance(climate) - nearest;
+            let weight = if self.blend_width > 0. {
+                1. - excess / self.blend_width
+            } else if excess > 0. {
+                0.
+            } else {
+                1.
+            };
+            if weight > 0. {
+                height += weight * biome.height(noise);
+                total += weight;
+            }
+        }
+        height / total
+    }
+}
+
+impl Default for BiomeRegistry {
+    /// Single grass and dirt biome
+    fn default() -> Self {
+        Self {
+            climate_scale: 500.,
+            height_scale: 100.,
+            blend_width: 0.,
+            biomes: vec![Biome {
+                name: "plains".to_owned(),
+                climate: DVec2::ZERO,
+                height_curve: vec![(-1., -15.), (1., 5.)],
+                surface: Voxel { id: 2 },
+                subsurface: Voxel { id: 1 },
+                subsurface_depth: 0,
+                underground: Voxel { id: 1 },
+                vegetation: Vegetation::default(),
+            }],
+            trees: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn two_biomes(blend_width: f64) -> BiomeRegistry {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let biome = |name: &str, temperature, height| BiomeDescriptor {
+            name: name.to_owned(),
+            temperature,
+            humidity: 0.,
+            height_curve: vec![(0., height)],
+            surface: "grass".to_owned(),
+            subsurface: "dirt".to_owned(),
+            subsurface_depth: 2,
+            underground: "dirt".to_owned(),
+            vegetation: Default::default(),
+        };
+        BiomeRegistry::new(
+            BiomesConfig {
+                climate_scale: 100.,
+                height_scale: 100.,
+                blend_width,
+                biomes: vec![biome("cold", -0.5, 0.), biome("hot", 0.5, 10.)],
+                trees: Vec::new(),
+            },
+            &blocks,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn load_config_biomes() {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let biomes = BiomeRegistry::from_file_ron("config/biomes.ron", &blocks).unwrap();
+
+        let desert = biomes.biomes().iter().find(|b| b.name == "desert").unwrap();
+        assert_eq!(desert.surface.id, blocks.id_of("sand").unwrap());
+
+        let plains = biomes.biomes().iter().find(|b| b.name == "plains").unwrap();
+        let tree = &biomes.trees()[plains.vegetation.trees[0]];
+        assert_eq!(tree.leaves.id, blocks.id_of("leaves").unwrap());
+    }
+
+    #[test]
+    fn unknown_block_rejected() {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let mut config: BiomesConfig =
+            ron::from_str(&std::fs::read_to_string("config/biomes.ron").unwrap()).unwrap();
+        config.biomes[0].surface = "unobtainium".to_owned();
+
+        assert!(matches!(
+            BiomeRegistry::new(config, &blocks),
+            Err(Error::InvalidBiomes(_))
+        ));
+    }
+
+    #[rstest(
+        curve,
+        noise,
+        exp_height,
+        case(vec![(-1., -10.), (1., 10.)], 0., 0.),
+        case(vec![(-1., -10.), (1., 10.)], 0.5, 5.),
+        case(vec![(-1., -10.), (0., 0.), (1., 40.)], 0.5, 20.),
+        // clamped to the ends
+        case(vec![(-1., -10.), (1., 10.)], -2., -10.),
+        case(vec![(-1., -10.), (1., 10.)], 2., 10.)
+    )]
+    fn height_curve(curve: Vec<(f64, f64)>, noise: f64, exp_height: f64) {
+        let biome = Biome {
+            height_curve: curve,
+            ..BiomeRegistry::default().biomes[0].clone()
+        };
+
+        assert!((biome.height(noise) - exp_height).abs() < 1e-9);
+    }
+
+    #[rstest(
+        temperature,
+        exp_height,
+        // at the biome's point
+        case(-0.5, 0.),
+        case(0.5, 10.),
+        // far from the other biome
+        case(-0.2, 0.),
+        // on the border both biomes have equal weight
+        case(0., 5.),
+        // nearest biome at full weight, other at half
+        case(0.05, 10. / 1.5)
+    )]
+    fn blended_height(temperature: f64, exp_height: f64) {
+        let biomes = two_biomes(0.2);
+
+        let height = biomes.blended_height(DVec2::new(temperature, 0.), 0.);
+
+        assert!(
+            (height - exp_height).abs() < 1e-9,
+            "{height} != {exp_height}"
+        );
+    }
+}
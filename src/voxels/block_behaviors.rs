@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+
+use bevy::prelude::{warn, IVec3};
+use rand::Rng;
+
+use crate::directions::Directions;
+
+use super::{
+    block_registry::BlockRegistry,
+    block_ticks::{BlockBehavior, BlockTicks, TickContext},
+    voxel::Voxel,
+};
+
+/// Ticks an unsupported block with gravity waits before falling
+pub const FALL_DELAY: u32 = 2;
+/// Leaves more steps than this away from every log, walking through leaves, decay
+pub const LEAVES_DECAY_DISTANCE: usize = 4;
+
+/// Registers behaviors of the default blocks present in the registry and of blocks with gravity
+pub fn register_default_behaviors(ticks: &mut BlockTicks, registry: &BlockRegistry) {
+    let behaviors = [
+        (
+            "grass",
+            BlockBehavior {
+                on_random_tick: Some(grass_random_tick),
+                ..Default::default()
+            },
+        ),
+        (
+            "leaves",
+            BlockBehavior {
+                on_random_tick: Some(leaves_random_tick),
+                ..Default::default()
+            },
+        ),
+    ];
+    for (name, behavior) in behaviors {
+        match registry.id_of(name) {
+            Some(id) => ticks.register(id, behavior),
+            None => warn!("No block named {} to register the behavior of", name),
+        }
+    }
+    for block in registry.blocks().filter(|block| block.gravity) {
+        ticks.register(
+            block.id,
+            BlockBehavior {
+                on_neighbour_changed: Some(schedule_fall),
+                on_scheduled: Some(fall_if_unsupported),
+                ..Default::default()
+            },
+        );
+    }
+}
+
+fn block(ctx: &TickContext, name: &str) -> Option<Voxel> {
+    ctx.registry().id_of(name).map(|id| Voxel { id })
+}
+
+/// Covered grass turns into dirt, uncovered grass spreads to a random nearby uncovered dirt
+pub fn grass_random_tick(ctx: &mut TickContext, pos: IVec3, _: Voxel) {
+    let (Some(grass), Some(dirt)) = (block(ctx, "grass"), block(ctx, "dirt")) else {
+        return;
+    };
+    let is_covered = |ctx: &TickContext, pos: IVec3| {
+        ctx.voxel(pos + IVec3::Y)
+            .is_some_and(|above| !ctx.registry().is_transparent(above))
+    };
+    if is_covered(ctx, pos) {
+        ctx.set_voxel(pos, dirt);
+        return;
+    }
+    let target = pos
+        + IVec3::new(
+            ctx.rng().gen_range(-1..=1),
+            ctx.rng().gen_range(-1..=1),
+            ctx.rng().gen_range(-1..=1),
+        );
+    if ctx.voxel(target) == Some(dirt) && !is_covered(ctx, target) {
+        ctx.set_voxel(target, grass);
+    }
+}
+
+/// Leaves not connected to a log through other leaves within `LEAVES_DECAY_DISTANCE` steps decay,
+/// unloaded chunks may hold one. The search only spreads through leaves, so it stays small
+pub fn leaves_random_tick(ctx: &mut TickContext, pos: IVec3, leaves: Voxel) {
+    let Some(log) = block(ctx, "log") else {
+        return;
+    };
+    let mut visited = HashSet::from([pos]);
+    let mut frontier = vec![pos];
+    for _ in 0..LEAVES_DECAY_DISTANCE {
+        let mut next = Vec::new();
+        for cell in frontier {
+            for dir in Directions::all() {
+                let neighbour = cell + dir.to_ivec();
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                match ctx.voxel(neighbour) {
+                    None => return,
+                    Some(vox) if vox == log => return,
+                    Some(vox) if vox == leaves => next.push(neighbour),
+                    Some(_) => {}
+                }
+            }
+        }
+        frontier = next;
+    }
+    ctx.set_voxel(pos, Voxel::default());
+}
+
+fn schedule_fall(ctx: &mut TickContext, pos: IVec3, _: Voxel) {
+    ctx.schedule(pos, FALL_DELAY);
+}
+
+/// Turns the voxel into a falling block if the voxel below is loaded and not solid
+pub fn fall_if_unsupported(ctx: &mut TickContext, pos: IVec3, vox: Voxel) {
+    if ctx
+        .voxel(pos - IVec3::Y)
+        .is_some_and(|below| !ctx.registry().is_solid(below))
+    {
+        ctx.start_falling(pos, vox);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{
+        block_ticks::VoxelAccess,
+        chunk::ChunkPosition,
+        test_utils::{floor_world, named, voxel, FloorWorld, N},
+    };
+
+    /// Runs world ticks applying changes in between, with random ticks around the origin
+    fn run_ticks(world: &mut FloorWorld, ticks: &mut BlockTicks, count: usize) {
+        let loaders = [ChunkPosition::new(IVec3::ZERO)];
+        for _ in 0..count {
+            world.apply_voxel_changes();
+            let changed = world.take_voxels_changed();
+            ticks.step::<N>(world, &changed, &loaders);
+        }
+        world.apply_voxel_changes();
+    }
+
+    fn default_ticks(world: &FloorWorld, random_ticks: u32) -> BlockTicks {
+        let mut ticks = BlockTicks::new(random_ticks, 0, 7);
+        register_default_behaviors(&mut ticks, world.registry());
+        ticks
+    }
+
+    #[test]
+    fn unsupported_sand_starts_falling() {
+        let mut world = floor_world();
+        let mut ticks = default_ticks(&world, 0);
+        let (sand, gravel) = (
+            named(world.registry(), "sand"),
+            named(world.registry(), "gravel"),
+        );
+        world.set_voxel(IVec3::new(4, 6, 4), sand);
+        world.set_voxel(IVec3::new(6, 1, 4), gravel);
+
+        run_ticks(&mut world, &mut ticks, 3);
+
+        assert_eq!(voxel(&world, IVec3::new(4, 6, 4)).id, 0);
+        assert_eq!(voxel(&world, IVec3::new(6, 1, 4)), gravel);
+        assert_eq!(ticks.take_falling(), vec![(IVec3::new(4, 6, 4), sand)]);
+        assert_eq!(ticks.scheduled(), 0);
+    }
+
+    #[test]
+    fn removing_support_drops_block() {
+        let mut world = floor_world();
+        let mut ticks = default_ticks(&world, 0);
+        let sand = named(world.registry(), "sand");
+        world.set_voxel(IVec3::new(4, 1, 4), sand);
+        run_ticks(&mut world, &mut ticks, 3);
+        assert!(ticks.take_falling().is_empty());
+
+        world.set_voxel(IVec3::new(4, 0, 4), Voxel::default());
+        run_ticks(&mut world, &mut ticks, 3);
+
+        assert_eq!(ticks.take_falling(), vec![(IVec3::new(4, 1, 4), sand)]);
+    }
+
+    #[test]
+    fn grass_spreads_to_uncovered_dirt() {
+        let mut world = floor_world();
+        let mut ticks = default_ticks(&world, 4096);
+        let (grass, dirt, stone) = (
+            named(world.registry(), "grass"),
+            named(world.registry(), "dirt"),
+            named(world.registry(), "stone"),
+        );
+        world.set_voxel(IVec3::new(4, 1, 4), grass);
+        world.set_voxel(IVec3::new(5, 1, 4), dirt);
+        world.set_voxel(IVec3::new(4, 1, 5), dirt);
+        world.set_voxel(IVec3::new(4, 2, 5), stone);
+
+        run_ticks(&mut world, &mut ticks, 20);
+
+        assert_eq!(voxel(&world, IVec3::new(5, 1, 4)), grass);
+        assert_eq!(voxel(&world, IVec3::new(4, 1, 5)), dirt);
+    }
+
+    #[test]
+    fn leaves_decay_without_log() {
+        let mut world = floor_world();
+        let mut ticks = default_ticks(&world, 4096);
+        let (leaves, log) = (
+            named(world.registry(), "leaves"),
+            named(world.registry(), "log"),
+        );
+        world.set_voxel(IVec3::new(2, 4, 2), log);
+        for x in 3..=6 {
+            world.set_voxel(IVec3::new(x, 4, 2), leaves);
+        }
+        world.set_voxel(IVec3::new(12, 4, 12), leaves);
+        // near a log, but not connected to it
+        world.set_voxel(IVec3::new(2, 4, 9), log);
+        world.set_voxel(IVec3::new(4, 4, 9), leaves);
+
+        run_ticks(&mut world, &mut ticks, 20);
+
+        assert_eq!(voxel(&world, IVec3::new(6, 4, 2)), leaves);
+        assert_eq!(voxel(&world, IVec3::new(12, 4, 12)).id, 0);
+        assert_eq!(voxel(&world, IVec3::new(4, 4, 9)).id, 0);
+    }
+}
@@ -0,0 +1,512 @@
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    directions::Directions,
+    error::{self, Error},
+};
+
+use super::voxel::Voxel;
+
+/// Names of textures in `assets/blocks` used for block faces
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockTextures {
+    All(String),
+    TopSideBottom {
+        top: String,
+        side: String,
+        bottom: String,
+    },
+    Faces {
+        north: String,
+        south: String,
+        west: String,
+        east: String,
+        up: String,
+        down: String,
+    },
+}
+
+impl BlockTextures {
+    pub fn texture(&self, dir: Directions) -> &str {
+        match self {
+            BlockTextures::All(name) => name,
+            BlockTextures::TopSideBottom { top, side, bottom } => match dir {
+                Directions::UP => top,
+                Directions::DOWN => bottom,
+                _ => side,
+            },
+            BlockTextures::Faces {
+                north,
+                south,
+                west,
+                east,
+                up,
+                down,
+            } => match dir {
+                Directions::NORTH => north,
+                Directions::SOUTH => south,
+                Directions::WEST => west,
+                Directions::EAST => east,
+                Directions::UP => up,
+                Directions::DOWN => down,
+                _ => panic!("texture called with more than one direction"),
+            },
+        }
+    }
+}
+
+/// Shape of the block's mesh
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockModel {
+    /// Faces on every side not hidden by neighbours
+    #[default]
+    Cube,
+    /// Two crossed quads through the block, for plants
+    Cross,
+}
+
+/// Makes a block a fluid source which spreads in the fluid simulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FluidDescriptor {
+    /// Flow levels including the source's. Flowing voxels of decreasing level
+    /// are registered with the ids following the source's
+    pub levels: u8,
+    /// Simulation ticks between spreading steps
+    pub flow_interval: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockDescriptor {
+    pub name: String,
+    pub id: u16,
+    /// Whether the block can be collided with and destroyed
+    pub solid: bool,
+    /// Whether faces of adjacent blocks are visible through this block
+    pub transparent: bool,
+    /// Whether the block is rendered alpha blended in a separate pass, like glass or water.
+    /// Translucent blocks must be transparent cubes
+    #[serde(default)]
+    pub translucent: bool,
+    #[serde(default)]
+    pub light_emission: u8,
+    /// Blocks without textures aren't meshed
+    #[serde(default)]
+    pub textures: Option<BlockTextures>,
+    #[serde(default)]
+    pub model: BlockModel,
+    #[serde(default)]
+    pub fluid: Option<FluidDescriptor>,
+    /// Whether the block falls when nothing solid is below it, like sand.
+    /// Falling blocks must be solid cubes
+    #[serde(default)]
+    pub gravity: bool,
+}
+
+impl BlockDescriptor {
+    #[inline]
+    pub fn is_rendered(&self) -> bool {
+        self.textures.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockRegistryFile {
+    blocks: Vec<BlockDescriptor>,
+}
+
+/// Properties of every block type, indexed by voxel id.
+/// Ids missing from the registry are treated as solid opaque blocks.
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<Option<BlockDescriptor>>,
+    names: HashMap<String, u16>,
+    unknown: BlockDescriptor,
+    /// Distinct texture names, index is the texture array layer
+    textures: Vec<String>,
+    /// Texture layer of every face of every block, indexed by voxel id and direction
+    face_layers: Vec<[u32; 6]>,
+    /// Source id and flow level of fluid blocks, indexed by voxel id
+    fluid_levels: Vec<Option<(u16, u8)>>,
+}
+
+impl BlockRegistry {
+    pub fn new(blocks: Vec<BlockDescriptor>) -> error::Result<Self> {
+        let mut registry = Self {
+            blocks: Vec::new(),
+            names: HashMap::new(),
+            unknown: BlockDescriptor {
+                name: "unknown".to_owned(),
+                id: u16::MAX,
+                solid: true,
+                transparent: false,
+                translucent: false,
+                light_emission: 0,
+                textures: Some(BlockTextures::All(Self::UNKNOWN_TEXTURE.to_owned())),
+                model: BlockModel::Cube,
+                fluid: None,
+                gravity: false,
+            },
+            textures: vec![Self::UNKNOWN_TEXTURE.to_owned()],
+            face_layers: Vec::new(),
+            fluid_levels: Vec::new(),
+        };
+        let mut fluid_levels = Vec::new();
+        let blocks = blocks.into_iter().flat_map(|block| {
+            let Some(fluid) = block.fluid else {
+                return vec![block];
+            };
+            fluid_levels.push((block.id, block.id, fluid.levels));
+            let flowing = (1..fluid.levels).map(|level| {
+                let id = block.id.saturating_add(level as u16);
+                fluid_levels.push((id, block.id, fluid.levels - level));
+                BlockDescriptor {
+                    name: format!("{}_flowing_{}", block.name, fluid.levels - level),
+                    id,
+                    ..block.clone()
+                }
+            });
+            std::iter::once(block.clone()).chain(flowing).collect()
+        });
+        for block in blocks {
+            let index = block.id as usize;
+            if registry.blocks.len() <= index {
+                registry.blocks.resize(index + 1, None);
+            }
+            if registry.blocks[index].is_some() {
+                return Err(Error::InvalidBlockRegistry(format!(
+                    "duplicate block id {}",
+                    block.id
+                )));
+            }
+            if block.model == BlockModel::Cross && !block.transparent {
+                return Err(Error::InvalidBlockRegistry(format!(
+                    "block {} with cross model must be transparent",
+                    block.name
+                )));
+            }
+            if block.fluid.is_some_and(|fluid| {
+                fluid.levels == 0 || block.solid || block.model != BlockModel::Cube
+            }) {
+                return Err(Error::InvalidBlockRegistry(format!(
+                    "fluid {} must be a non-solid cube with at least one level",
+                    block.name
+                )));
+            }
+            if block.translucent && (!block.transparent || block.model != BlockModel::Cube) {
+                return Err(Error::InvalidBlockRegistry(format!(
+                    "translucent block {} must be a transparent cube",
+                    block.name
+                )));
+            }
+            if block.gravity && (!block.solid || block.model != BlockModel::Cube) {
+                return Err(Error::InvalidBlockRegistry(format!(
+                    "falling block {} must be a solid cube",
+                    block.name
+                )));
+            }
+            if registry
+                .names
+                .insert(block.name.clone(), block.id)
+                .is_some()
+            {
+                return Err(Error::InvalidBlockRegistry(format!(
+                    "duplicate block name {}",
+                    block.name
+                )));
+            }
+            registry.blocks[index] = Some(block);
+        }
+        match registry.blocks.first() {
+            Some(Some(air)) if !air.solid && air.transparent && !air.is_rendered() => {}
+            _ => {
+                return Err(Error::InvalidBlockRegistry(
+                    "block with id 0 must be non-solid, transparent and have no textures"
+                        .to_owned(),
+                ))
+            }
+        }
+
+        registry.face_layers = registry
+            .blocks
+            .iter()
+            .map(|block| {
+                let Some(textures) = block.as_ref().and_then(|b| b.textures.as_ref()) else {
+                    return [0; 6];
+                };
+                let mut layers = [0; 6];
+                for dir in Directions::all().into_iter() {
+                    let name = textures.texture(dir);
+                    let layer = match registry.textures.iter().position(|t| t == name) {
+                        Some(layer) => layer,
+                        None => {
+                            registry.textures.push(name.to_owned());
+                            registry.textures.len() - 1
+                        }
+                    };
+                    layers[Self::dir_index(dir)] = layer as u32;
+                }
+                layers
+            })
+            .collect();
+        registry.fluid_levels = vec![None; registry.blocks.len()];
+        for (id, source, level) in fluid_levels {
+            registry.fluid_levels[id as usize] = Some((source, level));
+        }
+        Ok(registry)
+    }
+
+    /// Texture shown on faces of unknown blocks, always on layer 0
+    pub const UNKNOWN_TEXTURE: &'static str = "unknown";
+
+    #[inline]
+    fn dir_index(dir: Directions) -> usize {
+        dir.bits().trailing_zeros() as usize
+    }
+
+    pub fn from_file_ron<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let str = std::fs::read_to_string(path)?;
+        let file: BlockRegistryFile = ron::from_str(str.as_ref())?;
+        Self::new(file.blocks)
+    }
+
+    #[inline]
+    pub fn get(&self, voxel: Voxel) -> &BlockDescriptor {
+        match self.blocks.get(voxel.id as usize) {
+            Some(Some(block)) => block,
+            _ => &self.unknown,
+        }
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<u16> {
+        self.names.get(name).copied()
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &BlockDescriptor> {
+        self.blocks.iter().flatten()
+    }
+
+    /// Texture names in texture array layer order
+    pub fn textures(&self) -> &[String] {
+        &self.textures
+    }
+
+    /// Texture array layer of the block face looking in `dir`
+    #[inline]
+    pub fn texture_layer(&self, voxel: Voxel, dir: Directions) -> u32 {
+        match self.face_layers.get(voxel.id as usize) {
+            Some(layers) => layers[Self::dir_index(dir)],
+            None => 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_transparent(&self, voxel: Voxel) -> bool {
+        self.get(voxel).transparent
+    }
+
+    /// Source block and flow level of a fluid voxel, sources have the highest level
+    #[inline]
+    pub fn fluid_level(&self, voxel: Voxel) -> Option<(Voxel, u8)> {
+        self.fluid_levels
+            .get(voxel.id as usize)
+            .copied()
+            .flatten()
+            .map(|(source, level)| (Voxel { id: source }, level))
+    }
+
+    /// Voxel of the fluid with the given source block at flow `level`, air at level 0
+    pub fn fluid_voxel(&self, source: Voxel, level: u8) -> Voxel {
+        match self.get(source).fluid {
+            Some(fluid) if level > 0 => Voxel {
+                id: source.id + fluid.levels.saturating_sub(level) as u16,
+            },
+            _ => Voxel::default(),
+        }
+    }
+
+    /// Whether the voxels are the same block or the same fluid at any level
+    #[inline]
+    pub fn same_block(&self, a: Voxel, b: Voxel) -> bool {
+        a == b
+            || self
+                .fluid_level(a)
+                .zip(self.fluid_level(b))
+                .is_some_and(|((a, _), (b, _))| a == b)
+    }
+
+    #[inline]
+    pub fn is_translucent(&self, voxel: Voxel) -> bool {
+        self.get(voxel).translucent
+    }
+
+    #[inline]
+    pub fn is_solid(&self, voxel: Voxel) -> bool {
+        self.get(voxel).solid
+    }
+
+    #[inline]
+    pub fn has_gravity(&self, voxel: Voxel) -> bool {
+        self.get(voxel).gravity
+    }
+}
+
+impl Default for BlockRegistry {
+    /// Registry with only air
+    fn default() -> Self {
+        Self::new(vec![BlockDescriptor {
+            name: "air".to_owned(),
+            id: 0,
+            solid: false,
+            transparent: true,
+            translucent: false,
+            light_emission: 0,
+            textures: None,
+            model: BlockModel::Cube,
+            fluid: None,
+            gravity: false,
+        }])
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_config_blocks() {
+        let registry = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+
+        let air = registry.get(Voxel { id: 0 });
+        assert!(air.transparent && !air.solid && !air.is_rendered());
+
+        let grass = registry.get(Voxel {
+            id: registry.id_of("grass").unwrap(),
+        });
+        let textures = grass.textures.as_ref().unwrap();
+        assert_eq!(textures.texture(Directions::UP), "grass_top");
+        assert_eq!(textures.texture(Directions::EAST), "grass_side");
+        assert_eq!(textures.texture(Directions::DOWN), "dirt");
+
+        // faces behind leaves are visible through them
+        let leaves = Voxel {
+            id: registry.id_of("leaves").unwrap(),
+        };
+        assert!(registry.is_transparent(leaves) && registry.is_solid(leaves));
+        let flower = registry.get(Voxel {
+            id: registry.id_of("flower").unwrap(),
+        });
+        assert_eq!(flower.model, BlockModel::Cross);
+
+        let glass = Voxel {
+            id: registry.id_of("glass").unwrap(),
+        };
+        assert!(registry.is_translucent(glass) && registry.is_transparent(glass));
+        assert!(!registry.is_translucent(leaves));
+
+        let sand = Voxel {
+            id: registry.id_of("sand").unwrap(),
+        };
+        assert!(registry.has_gravity(sand) && !registry.has_gravity(leaves));
+    }
+
+    #[test]
+    fn texture_layers_per_face() {
+        let registry = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let grass = Voxel {
+            id: registry.id_of("grass").unwrap(),
+        };
+        let layer_name = |dir| &registry.textures()[registry.texture_layer(grass, dir) as usize];
+
+        assert_eq!(layer_name(Directions::UP), "grass_top");
+        assert_eq!(layer_name(Directions::NORTH), "grass_side");
+        assert_eq!(layer_name(Directions::WEST), "grass_side");
+        assert_eq!(layer_name(Directions::DOWN), "dirt");
+        assert_eq!(
+            registry.texture_layer(Voxel { id: 1234 }, Directions::UP),
+            0
+        );
+    }
+
+    #[test]
+    fn unknown_id_is_solid() {
+        let registry = BlockRegistry::default();
+
+        assert!(registry.is_solid(Voxel { id: 1234 }));
+        assert!(!registry.is_transparent(Voxel { id: 1234 }));
+    }
+
+    #[test]
+    fn duplicate_id_rejected() {
+        let air = BlockRegistry::default().get(Voxel { id: 0 }).clone();
+        let other = BlockDescriptor {
+            name: "other".to_owned(),
+            ..air.clone()
+        };
+
+        assert!(matches!(
+            BlockRegistry::new(vec![air, other]),
+            Err(Error::InvalidBlockRegistry(_))
+        ));
+    }
+
+    #[test]
+    fn opaque_cross_rejected() {
+        let air = BlockRegistry::default().get(Voxel { id: 0 }).clone();
+        let plant = BlockDescriptor {
+            name: "plant".to_owned(),
+            id: 1,
+            transparent: false,
+            textures: Some(BlockTextures::All("plant".to_owned())),
+            model: BlockModel::Cross,
+            ..air.clone()
+        };
+
+        assert!(matches!(
+            BlockRegistry::new(vec![air, plant]),
+            Err(Error::InvalidBlockRegistry(_))
+        ));
+    }
+
+    #[test]
+    fn fluid_levels_registered() {
+        let registry = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let water = Voxel {
+            id: registry.id_of("water").unwrap(),
+        };
+        let levels = registry.get(water).fluid.unwrap().levels;
+        let flowing = registry.fluid_voxel(water, 1);
+
+        assert_eq!(registry.fluid_level(water), Some((water, levels)));
+        assert_eq!(registry.fluid_level(flowing), Some((water, 1)));
+        assert_eq!(registry.fluid_voxel(water, levels), water);
+        assert_eq!(registry.fluid_voxel(water, 0), Voxel::default());
+        assert!(registry.same_block(water, flowing));
+        assert!(registry.is_translucent(flowing));
+        assert_eq!(
+            registry.id_of(&format!("water_flowing_{}", levels - 1)),
+            Some(water.id + 1)
+        );
+        assert_eq!(registry.fluid_level(Voxel { id: 1234 }), None);
+    }
+
+    #[test]
+    fn opaque_translucent_rejected() {
+        let air = BlockRegistry::default().get(Voxel { id: 0 }).clone();
+        let tinted = BlockDescriptor {
+            name: "tinted".to_owned(),
+            id: 1,
+            transparent: false,
+            translucent: true,
+            textures: Some(BlockTextures::All("tinted".to_owned())),
+            ..air.clone()
+        };
+
+        assert!(matches!(
+            BlockRegistry::new(vec![air, tinted]),
+            Err(Error::InvalidBlockRegistry(_))
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::Path;
+
+use bevy::{
+    prelude::Image,
+    render::{
+        render_resource::{
+            AddressMode, Extent3d, FilterMode, SamplerDescriptor, TextureDimension, TextureFormat,
+            TextureViewDescriptor, TextureViewDimension,
+        },
+        texture::{CompressedImageFormats, ImageSampler, ImageType},
+    },
+};
+
+use crate::error::{self, Error};
+
+use super::block_registry::BlockRegistry;
+
+const DEFAULT_TEXTURE_SIZE: u32 = 16;
+
+/// Builds a 2d array texture with a layer per registry texture from `{name}.png` files in `dir`.
+/// Missing textures are an error, only the unknown block's texture is a generated checkerboard.
+pub fn load_block_texture_array<P: AsRef<Path>>(
+    dir: P,
+    registry: &BlockRegistry,
+) -> error::Result<Image> {
+    let mut layers = Vec::with_capacity(registry.textures().len());
+    for name in registry.textures() {
+        if name == BlockRegistry::UNKNOWN_TEXTURE {
+            layers.push(None);
+            continue;
+        }
+        let path = dir.as_ref().join(format!("{name}.png"));
+        if !path.exists() {
+            return Err(Error::InvalidBlockTextures(format!(
+                "block texture {} not found",
+                path.display()
+            )));
+        }
+
+        let bytes = std::fs::read(&path)?;
+        let image = Image::from_buffer(
+            &bytes,
+            ImageType::Extension("png"),
+            CompressedImageFormats::NONE,
+            true,
+        )?;
+        let image = image
+            .convert(TextureFormat::Rgba8UnormSrgb)
+            .ok_or_else(|| {
+                Error::InvalidBlockTextures(format!("{} can't be converted", path.display()))
+            })?;
+        layers.push(Some(image));
+    }
+
+    let size = layers
+        .iter()
+        .flatten()
+        .map(|image| image.size())
+        .next()
+        .map(|size| (size.x as u32, size.y as u32))
+        .unwrap_or((DEFAULT_TEXTURE_SIZE, DEFAULT_TEXTURE_SIZE));
+
+    let mut data = Vec::new();
+    for (name, layer) in registry.textures().iter().zip(layers) {
+        match layer {
+            Some(image) => {
+                let image_size = image.size();
+                if (image_size.x as u32, image_size.y as u32) != size {
+                    return Err(Error::InvalidBlockTextures(format!(
+                        "texture {name} is {}x{}, expected {}x{}",
+                        image_size.x, image_size.y, size.0, size.1
+                    )));
+                }
+                data.extend(image.data);
+            }
+            None => data.extend(missing_texture(size)),
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: registry.textures().len() as u32,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    // a single layer would otherwise be viewed as a plain 2d texture
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..Default::default()
+    });
+    // greedy meshing tiles uvs across merged quads
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        ..Default::default()
+    });
+    Ok(image)
+}
+
+/// Magenta and black checkerboard
+fn missing_texture((width, height): (u32, u32)) -> Vec<u8> {
+    let mut data = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let magenta = (x * 2 / width + y * 2 / height) % 2 == 0;
+            data.extend(if magenta {
+                [255, 0, 255, 255]
+            } else {
+                [0, 0, 0, 255]
+            });
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_registry_texture_exists() {
+        let registry = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+
+        for name in registry.textures() {
+            if name != BlockRegistry::UNKNOWN_TEXTURE {
+                let path = Path::new("assets/blocks").join(format!("{name}.png"));
+                assert!(path.exists(), "{} is missing", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn missing_texture_rejected() {
+        let registry = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+
+        assert!(matches!(
+            load_block_texture_array(std::env::temp_dir().join("no_block_textures"), &registry),
+            Err(Error::InvalidBlockTextures(_))
+        ));
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use bevy::prelude::{IVec3, Resource, Vec3};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::directions::Directions;
+
+use super::{
+    block_registry::BlockRegistry, chunk::ChunkPosition, terrain_generation::VoxelGenerator,
+    voxel::Voxel, world::VoxelWorld,
+};
+
+/// Voxel reads and writes available to block behaviors
+pub trait VoxelAccess {
+    fn registry(&self) -> &BlockRegistry;
+    /// Voxel at the world position, `None` in chunks which aren't loaded
+    fn voxel(&self, pos: IVec3) -> Option<Voxel>;
+    /// Queues a change of the voxel, applied with the other changes of the frame
+    fn set_voxel(&self, pos: IVec3, vox: Voxel);
+}
+
+impl<G, const N: usize> VoxelAccess for VoxelWorld<G, N>
+where
+    G: VoxelGenerator<N> + Send + Sync,
+{
+    fn registry(&self) -> &BlockRegistry {
+        VoxelWorld::registry(self)
+    }
+
+    fn voxel(&self, pos: IVec3) -> Option<Voxel> {
+        self.voxel_at_pos(&(pos.as_vec3() + Vec3::splat(0.5)))
+    }
+
+    fn set_voxel(&self, pos: IVec3, vox: Voxel) {
+        self.set_voxel_at_pos(&(pos.as_vec3() + Vec3::splat(0.5)), vox)
+    }
+}
+
+/// Called with the position and voxel a tick hits
+pub type TickCallback = fn(&mut TickContext, IVec3, Voxel);
+
+/// Callbacks of a block, each optional
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BlockBehavior {
+    /// The voxel itself or one of its neighbours changed on the previous tick
+    pub on_neighbour_changed: Option<TickCallback>,
+    /// An update scheduled with `TickContext::schedule` or `BlockTicks::schedule` is due
+    pub on_scheduled: Option<TickCallback>,
+    /// The voxel was sampled by random ticks
+    pub on_random_tick: Option<TickCallback>,
+}
+
+/// World access and scheduling for a running callback
+pub struct TickContext<'a> {
+    world: &'a dyn VoxelAccess,
+    scheduled: &'a mut BTreeMap<u64, HashSet<IVec3>>,
+    falling: &'a mut Vec<(IVec3, Voxel)>,
+    rng: &'a mut SmallRng,
+    tick: u64,
+}
+
+impl<'a> TickContext<'a> {
+    pub fn registry(&self) -> &BlockRegistry {
+        self.world.registry()
+    }
+
+    pub fn voxel(&self, pos: IVec3) -> Option<Voxel> {
+        self.world.voxel(pos)
+    }
+
+    pub fn set_voxel(&self, pos: IVec3, vox: Voxel) {
+        self.world.set_voxel(pos, vox)
+    }
+
+    /// Schedules an update of the voxel at `pos` after `delay` ticks, at least one
+    pub fn schedule(&mut self, pos: IVec3, delay: u32) {
+        schedule_at(self.scheduled, self.tick, pos, delay);
+    }
+
+    /// Removes the voxel from the world to continue as a falling block entity
+    pub fn start_falling(&mut self, pos: IVec3, vox: Voxel) {
+        self.world.set_voxel(pos, Voxel::default());
+        self.falling.push((pos, vox));
+    }
+
+    pub fn rng(&mut self) -> &mut SmallRng {
+        self.rng
+    }
+}
+
+fn schedule_at(scheduled: &mut BTreeMap<u64, HashSet<IVec3>>, tick: u64, pos: IVec3, delay: u32) {
+    scheduled
+        .entry(tick + delay.max(1) as u64)
+        .or_default()
+        .insert(pos);
+}
+
+/// Fixed-timestep world ticks: neighbour change notifications, scheduled block updates
+/// and random ticks of voxels in chunks around loaders, dispatched to behaviors by voxel id
+#[derive(Resource)]
+pub struct BlockTicks {
+    tick: u64,
+    scheduled: BTreeMap<u64, HashSet<IVec3>>,
+    behaviors: HashMap<u16, BlockBehavior>,
+    /// Voxels which started falling, waiting to be spawned as entities
+    falling: Vec<(IVec3, Voxel)>,
+    /// Voxels sampled in each chunk per tick
+    random_ticks_per_chunk: u32,
+    /// Chunks at most this far from loaders get random ticks
+    simulation_distance: usize,
+    rng: SmallRng,
+}
+
+impl BlockTicks {
+    pub fn new(random_ticks_per_chunk: u32, simulation_distance: usize, seed: u64) -> Self {
+        Self {
+            tick: 0,
+            scheduled: BTreeMap::new(),
+            behaviors: HashMap::new(),
+            falling: Vec::new(),
+            random_ticks_per_chunk,
+            simulation_distance,
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Replaces the behavior of blocks with the id
+    pub fn register(&mut self, id: u16, behavior: BlockBehavior) {
+        self.behaviors.insert(id, behavior);
+    }
+
+    /// Ticks elapsed since the start
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Schedules an update of the voxel at `pos` after `delay` ticks, at least one
+    pub fn schedule(&mut self, pos: IVec3, delay: u32) {
+        schedule_at(&mut self.scheduled, self.tick, pos, delay);
+    }
+
+    /// Positions and voxels which started falling since the last call
+    pub fn take_falling(&mut self) -> Vec<(IVec3, Voxel)> {
+        std::mem::take(&mut self.falling)
+    }
+
+    /// Number of updates waiting for their tick
+    pub fn scheduled(&self) -> usize {
+        self.scheduled.values().map(HashSet::len).sum()
+    }
+
+    /// Advances by a tick. Notifies voxels around `changed`, runs due scheduled updates
+    /// and random ticks in chunks around `loaders`, chunk positions of loaders
+    pub fn step<const N: usize>(
+        &mut self,
+        world: &dyn VoxelAccess,
+        changed: &HashSet<IVec3>,
+        loaders: &[ChunkPosition],
+    ) {
+        self.tick += 1;
+
+        let notified: HashSet<IVec3> = changed
+            .iter()
+            .flat_map(|pos| {
+                std::iter::once(*pos)
+                    .chain(Directions::all().into_iter().map(|d| *pos + d.to_ivec()))
+            })
+            .collect();
+        for pos in notified {
+            self.run(world, pos, |b| b.on_neighbour_changed);
+        }
+
+        let later = self.scheduled.split_off(&(self.tick + 1));
+        let due = std::mem::replace(&mut self.scheduled, later);
+        for pos in due.into_values().flatten() {
+            self.run(world, pos, |b| b.on_scheduled);
+        }
+
+        if self.random_ticks_per_chunk == 0 {
+            return;
+        }
+        let radius = self.simulation_distance as i32;
+        let mut chunks = HashSet::new();
+        for loader in loaders {
+            for x in -radius..=radius {
+                for y in -radius..=radius {
+                    for z in -radius..=radius {
+                        let offset = IVec3::new(x, y, z);
+                        if offset.dot(offset) <= radius * radius {
+                            chunks.insert(loader.pos + offset);
+                        }
+                    }
+                }
+            }
+        }
+        for chunk in chunks {
+            for _ in 0..self.random_ticks_per_chunk {
+                let index = IVec3::new(
+                    self.rng.gen_range(0..N as i32),
+                    self.rng.gen_range(0..N as i32),
+                    self.rng.gen_range(0..N as i32),
+                );
+                self.run(world, chunk * N as i32 + index, |b| b.on_random_tick);
+            }
+        }
+    }
+
+    /// Calls the callback picked from the behavior of the voxel at `pos`, if there's one
+    fn run(
+        &mut self,
+        world: &dyn VoxelAccess,
+        pos: IVec3,
+        callback: impl Fn(&BlockBehavior) -> Option<TickCallback>,
+    ) {
+        let Some(vox) = world.voxel(pos) else {
+            return;
+        };
+        let Some(callback) = self.behaviors.get(&vox.id).and_then(callback) else {
+            return;
+        };
+        let mut context = TickContext {
+            world,
+            scheduled: &mut self.scheduled,
+            falling: &mut self.falling,
+            rng: &mut self.rng,
+            tick: self.tick,
+        };
+        callback(&mut context, pos, vox);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    const N: usize = 4;
+    const MARKER: Voxel = Voxel { id: 1 };
+    const HIT: Voxel = Voxel { id: 2 };
+
+    /// A single chunk at the origin, changes are applied immediately
+    struct TestWorld {
+        registry: BlockRegistry,
+        voxels: RefCell<HashMap<IVec3, Voxel>>,
+    }
+
+    impl TestWorld {
+        fn new() -> Self {
+            Self {
+                registry: BlockRegistry::from_file_ron("config/blocks.ron").unwrap(),
+                voxels: RefCell::new(HashMap::new()),
+            }
+        }
+    }
+
+    impl VoxelAccess for TestWorld {
+        fn registry(&self) -> &BlockRegistry {
+            &self.registry
+        }
+
+        fn voxel(&self, pos: IVec3) -> Option<Voxel> {
+            let inside = pos.cmpge(IVec3::ZERO).all() && pos.cmplt(IVec3::splat(N as i32)).all();
+            inside.then(|| self.voxels.borrow().get(&pos).copied().unwrap_or_default())
+        }
+
+        fn set_voxel(&self, pos: IVec3, vox: Voxel) {
+            self.voxels.borrow_mut().insert(pos, vox);
+        }
+    }
+
+    fn mark_hit(ctx: &mut TickContext, pos: IVec3, _: Voxel) {
+        ctx.set_voxel(pos, HIT);
+    }
+
+    #[test]
+    fn scheduled_update_runs_after_delay() {
+        let world = TestWorld::new();
+        let mut ticks = BlockTicks::new(0, 0, 0);
+        ticks.register(
+            MARKER.id,
+            BlockBehavior {
+                on_scheduled: Some(mark_hit),
+                ..Default::default()
+            },
+        );
+        let pos = IVec3::new(1, 2, 3);
+        world.set_voxel(pos, MARKER);
+        ticks.schedule(pos, 3);
+
+        for _ in 0..2 {
+            ticks.step::<N>(&world, &HashSet::new(), &[]);
+            assert_eq!(world.voxel(pos), Some(MARKER));
+        }
+        ticks.step::<N>(&world, &HashSet::new(), &[]);
+        assert_eq!(world.voxel(pos), Some(HIT));
+        assert_eq!(ticks.scheduled(), 0);
+    }
+
+    #[test]
+    fn neighbours_of_changes_notified() {
+        let world = TestWorld::new();
+        let mut ticks = BlockTicks::new(0, 0, 0);
+        ticks.register(
+            MARKER.id,
+            BlockBehavior {
+                on_neighbour_changed: Some(mark_hit),
+                ..Default::default()
+            },
+        );
+        world.set_voxel(IVec3::new(1, 1, 1), MARKER);
+        world.set_voxel(IVec3::new(3, 3, 3), MARKER);
+
+        ticks.step::<N>(&world, &HashSet::from([IVec3::new(1, 1, 2)]), &[]);
+
+        assert_eq!(world.voxel(IVec3::new(1, 1, 1)), Some(HIT));
+        assert_eq!(world.voxel(IVec3::new(3, 3, 3)), Some(MARKER));
+    }
+
+    #[test]
+    fn random_ticks_sample_chunks_around_loaders() {
+        let world = TestWorld::new();
+        let mut ticks = BlockTicks::new(8, 1, 0);
+        ticks.register(
+            MARKER.id,
+            BlockBehavior {
+                on_random_tick: Some(mark_hit),
+                ..Default::default()
+            },
+        );
+        for x in 0..N as i32 {
+            for y in 0..N as i32 {
+                for z in 0..N as i32 {
+                    world.set_voxel(IVec3::new(x, y, z), MARKER);
+                }
+            }
+        }
+
+        ticks.step::<N>(&world, &HashSet::new(), &[ChunkPosition::new(IVec3::X)]);
+
+        let hits = world
+            .voxels
+            .borrow()
+            .values()
+            .filter(|vox| **vox == HIT)
+            .count();
+        assert!((1..=8).contains(&hits));
+    }
+}
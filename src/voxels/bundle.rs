@@ -1,30 +1,144 @@
-use bevy::prelude::Plugin;
+use std::sync::Arc;
+
+use bevy::prelude::{
+    warn, Assets, CoreSchedule, CoreSet, FixedTime, Image, IntoSystemAppConfig, IntoSystemConfig,
+    MaterialPlugin, Plugin,
+};
+
+use crate::game_config::RuntimeGameConfig;
 
 use super::{
+    biomes::BiomeRegistry,
+    block_behaviors::register_default_behaviors,
+    block_registry::BlockRegistry,
+    block_ticks::BlockTicks,
+    caves::CaveConfig,
     chunk::CHSIZE,
+    chunk_material::ChunkMaterial,
+    fluids::FluidSimulation,
+    generation_tasks::GenerationTasks,
+    meshing_tasks::MeshingTasks,
+    region_storage::RegionStorage,
     resources::EntityChunks,
     systems::{
-        chunk_render::chunk_render_system, destroy_on_touch_system::destroy_on_touch_system,
+        block_interaction_system::block_interaction_system,
+        chunk_generation_system::chunk_generation_system,
+        chunk_render::chunk_render_system,
+        chunk_save_system::chunk_save_system,
+        chunk_unload_system::chunk_unload_system,
+        chunk_visibility_system::chunk_visibility_system,
+        destroy_on_touch_system::destroy_on_touch_system,
         dirty_around_system::dirty_around_system,
+        falling_block_system::{falling_block_exit_system, falling_block_system},
         generate_map_around_system::generate_map_around_system,
+        materials::Materials,
         world_change_apply_system::world_apply_changes_system,
+        world_tick_system::world_tick_system,
     },
     terrain_generation::ProceduralGenerator,
+    underground::Underground,
+    visibility::ChunkVisibility,
+    voxel::Voxel,
     world::VoxelWorld,
 };
 
-#[derive(Debug, Default)]
-pub struct VoxelBundle;
+#[derive(Debug)]
+pub struct VoxelBundle {
+    registry: Arc<BlockRegistry>,
+    biomes: Arc<BiomeRegistry>,
+    caves: CaveConfig,
+    underground: Arc<Underground>,
+    block_textures: Image,
+}
+
+impl VoxelBundle {
+    pub fn new(
+        registry: BlockRegistry,
+        biomes: BiomeRegistry,
+        caves: CaveConfig,
+        underground: Underground,
+        block_textures: Image,
+    ) -> Self {
+        Self {
+            registry: Arc::new(registry),
+            biomes: Arc::new(biomes),
+            caves,
+            underground: Arc::new(underground),
+            block_textures,
+        }
+    }
+}
 
 impl Plugin for VoxelBundle {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.insert_resource(VoxelWorld::new(ProceduralGenerator::<CHSIZE>::new(42)));
+        let config = &app.world.resource::<RuntimeGameConfig>().config;
+        let seed = config.world_seed;
+        let save_directory = config.save_directory.clone();
+        let mut ticks = BlockTicks::new(
+            config.random_ticks_per_chunk,
+            config.simulation_distance,
+            seed as u64,
+        );
+        register_default_behaviors(&mut ticks, &self.registry);
+        let tick_length = 1. / config.ticks_per_second;
+
+        let mut generator = ProceduralGenerator::<CHSIZE>::with_biomes(seed, self.biomes.clone())
+            .with_caves(self.caves.clone())
+            .with_underground(self.underground.clone());
+        if let Some(level) = config.sea_level {
+            match self.registry.id_of("water") {
+                Some(id) => generator = generator.with_sea_level(level, Voxel { id }),
+                None => warn!("Sea level is set, but there is no water block"),
+            }
+        }
+        // the world owns the only registry, systems read it through `VoxelWorld::registry`
+        app.insert_resource(VoxelWorld::new(generator, self.registry.clone()));
+        let storage = RegionStorage::new(save_directory);
+        app.insert_resource(GenerationTasks::new(Some(storage.reader())));
+        app.insert_resource(storage);
+
+        app.add_plugin(MaterialPlugin::<ChunkMaterial>::default());
+        let textures = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(self.block_textures.clone());
+        let mut materials = app.world.resource_mut::<Assets<ChunkMaterial>>();
+        let material = materials.add(ChunkMaterial {
+            textures: textures.clone(),
+            translucent: false,
+        });
+        let translucent = materials.add(ChunkMaterial {
+            textures,
+            translucent: true,
+        });
+        app.insert_resource(Materials {
+            material,
+            translucent,
+        });
         app.insert_resource(EntityChunks::default());
+        app.insert_resource(MeshingTasks::default());
+        app.insert_resource(ChunkVisibility::default());
+        app.insert_resource(FluidSimulation::default());
+        app.insert_resource(ticks);
+        app.insert_resource(FixedTime::new_from_secs(tick_length));
 
         app.add_system(generate_map_around_system);
+        app.add_system(chunk_generation_system.after(generate_map_around_system));
         app.add_system(destroy_on_touch_system);
+        app.add_system(block_interaction_system);
         app.add_system(dirty_around_system);
         app.add_system(world_apply_changes_system);
         app.add_system(chunk_render_system);
+        app.add_system(chunk_visibility_system.after(chunk_render_system));
+        app.add_system(chunk_unload_system);
+        app.add_system(world_tick_system.in_schedule(CoreSchedule::FixedUpdate));
+        app.add_system(falling_block_system);
+        // in the last set to see exit events sent during the frame
+        app.add_system(chunk_save_system.in_base_set(CoreSet::Last));
+        app.add_system(
+            falling_block_exit_system
+                .in_base_set(CoreSet::Last)
+                .before(chunk_save_system),
+        );
     }
 }
@@ -0,0 +1,246 @@
+use std::path::Path;
+
+use bevy::{math::DVec3, prelude::IVec3};
+use ndarray::{Array2, Array3};
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
+
+use crate::error;
+
+use super::voxel::Voxel;
+
+/// Shifts the second worm noise off the first one's lattice, where both are zero
+const WORM_B_OFFSET: [f64; 3] = [0.37, 0.61, 0.19];
+
+/// Distance in voxels between noise samples which are interpolated when carving chunks
+const SAMPLE_STEP: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaveConfig {
+    /// Caves are carved only this many voxels or more below the surface
+    pub min_depth: f64,
+    /// and no deeper than this
+    pub max_depth: f64,
+    /// Distance in voxels over which worm tunnels noticeably bend
+    pub worm_scale: f64,
+    /// Tunnel thickness, voxels are carved where both worm noises are closer to zero than this
+    pub worm_threshold: f64,
+    /// Distance in voxels over which cheese caverns noticeably change
+    pub cheese_scale: f64,
+    /// Horizontal stretch of caverns, greater than 1 makes them wider than tall
+    pub cheese_stretch: f64,
+    /// Voxels are carved where cheese noise is above this
+    pub cheese_threshold: f64,
+}
+
+impl CaveConfig {
+    pub fn from_file_ron<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let str = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(str.as_ref())?)
+    }
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self {
+            min_depth: 4.,
+            max_depth: 256.,
+            worm_scale: 48.,
+            worm_threshold: 0.06,
+            cheese_scale: 64.,
+            cheese_stretch: 2.,
+            cheese_threshold: 0.45,
+        }
+    }
+}
+
+/// Carves spaghetti tunnels along intersections of two noise isosurfaces
+/// and cheese caverns where a third noise is high
+#[derive(Debug, Clone)]
+pub struct CaveCarver {
+    config: CaveConfig,
+    worm_a: Perlin,
+    worm_b: Perlin,
+    cheese: Perlin,
+}
+
+impl CaveCarver {
+    pub fn new(seed: u32, config: CaveConfig) -> Self {
+        Self {
+            config,
+            worm_a: Perlin::new(seed.wrapping_add(3000)),
+            worm_b: Perlin::new(seed.wrapping_add(4000)),
+            cheese: Perlin::new(seed.wrapping_add(5000)),
+        }
+    }
+
+    pub fn config(&self) -> &CaveConfig {
+        &self.config
+    }
+
+    /// Whether caves may be carved `depth` voxels below the surface
+    #[inline]
+    pub fn in_depth_range(&self, depth: f64) -> bool {
+        depth >= self.config.min_depth && depth <= self.config.max_depth
+    }
+
+    /// Whether the voxel at world `pos` lying `depth` voxels below the surface is carved out
+    pub fn is_carved(&self, pos: IVec3, depth: f64) -> bool {
+        self.in_depth_range(depth) && self.is_cave(self.sample(pos.as_dvec3()))
+    }
+
+    /// Carves caves out of the chunk at `origin` in world voxels given surface heights of its columns.
+    /// Noise is sampled on a coarse lattice and interpolated, so results differ slightly from `is_carved`
+    pub fn carve<const N: usize>(
+        &self,
+        origin: IVec3,
+        heights: &Array2<f64>,
+        arr: &mut Array3<Voxel>,
+    ) {
+        let (min_height, max_height) = heights
+            .iter()
+            .fold((f64::MAX, f64::MIN), |(lo, hi), &h| (lo.min(h), hi.max(h)));
+        let bottom = origin.y as f64;
+        let top = bottom + (N - 1) as f64;
+        if max_height - bottom < self.config.min_depth || min_height - top > self.config.max_depth {
+            return;
+        }
+
+        let step = if N.is_multiple_of(SAMPLE_STEP) {
+            SAMPLE_STEP
+        } else {
+            1
+        };
+        let cells = N / step;
+        let samples = Array3::from_shape_fn((cells + 1, cells + 1, cells + 1), |(x, y, z)| {
+            let offset = IVec3::new(x as i32, y as i32, z as i32) * step as i32;
+            self.sample((origin + offset).as_dvec3())
+        });
+
+        for ((x, y, z), vox) in arr.indexed_iter_mut() {
+            let depth = heights[[x, z]] - (origin.y + y as i32) as f64;
+            if !self.in_depth_range(depth) {
+                continue;
+            }
+
+            let (cx, cy, cz) = (x / step, y / step, z / step);
+            let t =
+                DVec3::new((x % step) as f64, (y % step) as f64, (z % step) as f64) / step as f64;
+            let lerp =
+                |a: [f64; 3], b: [f64; 3], t: f64| [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t);
+            let along_z = |x, y| lerp(samples[[x, y, cz]], samples[[x, y, cz + 1]], t.z);
+            let along_y = |x| lerp(along_z(x, cy), along_z(x, cy + 1), t.y);
+            let values = lerp(along_y(cx), along_y(cx + 1), t.x);
+
+            if self.is_cave(values) {
+                *vox = Voxel { id: 0 };
+            }
+        }
+    }
+
+    /// Worm and cheese noise values at world `p`
+    fn sample(&self, p: DVec3) -> [f64; 3] {
+        let worm = p / self.config.worm_scale;
+        let mut cheese = p / self.config.cheese_scale;
+        cheese.x /= self.config.cheese_stretch;
+        cheese.z /= self.config.cheese_stretch;
+        [
+            self.worm_a.get(worm.to_array()),
+            self.worm_b
+                .get((worm + DVec3::from(WORM_B_OFFSET)).to_array()),
+            self.cheese.get(cheese.to_array()),
+        ]
+    }
+
+    #[inline]
+    fn is_cave(&self, [worm_a, worm_b, cheese]: [f64; 3]) -> bool {
+        (worm_a.abs() < self.config.worm_threshold && worm_b.abs() < self.config.worm_threshold)
+            || cheese > self.config.cheese_threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_config_caves() {
+        let config = CaveConfig::from_file_ron("config/caves.ron").unwrap();
+
+        assert!(config.min_depth <= config.max_depth);
+    }
+
+    #[test]
+    fn carves_only_within_depth_range() {
+        let carver = CaveCarver::new(
+            42,
+            CaveConfig {
+                min_depth: 5.,
+                max_depth: 10.,
+                // carve everything that's in range
+                cheese_threshold: -2.,
+                ..Default::default()
+            },
+        );
+
+        for depth in 0..20 {
+            let pos = IVec3::new(3, -depth, 7);
+            assert_eq!(
+                carver.is_carved(pos, depth as f64),
+                (5..=10).contains(&depth),
+                "depth {depth}"
+            );
+        }
+    }
+
+    #[test]
+    fn thresholds_control_density() {
+        let carved = |config: CaveConfig| {
+            let carver = CaveCarver::new(42, config);
+            (0..32 * 32 * 32)
+                .map(|i| IVec3::new(i % 32, i / 32 % 32 - 64, i / 1024))
+                .filter(|&pos| carver.is_carved(pos, 64.))
+                .count()
+        };
+        let sparse = carved(CaveConfig::default());
+        let dense = carved(CaveConfig {
+            worm_threshold: 0.2,
+            cheese_threshold: 0.2,
+            ..Default::default()
+        });
+
+        assert!(sparse > 0);
+        assert!(dense > sparse * 2, "{dense} <= 2 * {sparse}");
+    }
+
+    #[test]
+    fn chunk_carving_matches_exact_at_samples() {
+        const N: usize = 32;
+        let carver = CaveCarver::new(
+            42,
+            CaveConfig {
+                worm_threshold: 0.2,
+                cheese_threshold: 0.2,
+                ..Default::default()
+            },
+        );
+        let origin = IVec3::new(-32, -96, 64);
+        let heights = Array2::from_elem((N, N), 0.);
+        let mut arr = Array3::from_elem((N, N, N), Voxel { id: 1 });
+
+        carver.carve::<N>(origin, &heights, &mut arr);
+
+        let mut mismatches = 0;
+        for ((x, y, z), vox) in arr.indexed_iter() {
+            let pos = origin + IVec3::new(x as i32, y as i32, z as i32);
+            let exact = carver.is_carved(pos, -pos.y as f64);
+            if [x, y, z].iter().all(|v| v % SAMPLE_STEP == 0) {
+                assert_eq!(vox.id == 0, exact, "{pos}");
+            } else if (vox.id == 0) != exact {
+                mismatches += 1;
+            }
+        }
+        assert!(arr.iter().any(|vox| vox.id == 0));
+        assert!(mismatches < N * N * N / 20, "{mismatches} mismatches");
+    }
+}
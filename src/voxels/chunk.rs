@@ -5,7 +5,7 @@ use bevy::prelude::{Component, IVec3};
 use ndarray::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use super::voxel::Voxel;
+use super::{block_registry::BlockRegistry, light::LightLevel, voxel::Voxel};
 
 pub const CHSIZE: usize = 32;
 pub const CHSIZEI: i32 = CHSIZE as i32;
@@ -14,8 +14,11 @@ pub const CHSIZEF: f32 = CHSIZE as f32;
 #[derive(Debug)]
 pub struct Chunk<const N: usize> {
     data: Array3<Voxel>,
+    light: Array3<LightLevel>,
     is_transparent: Mutex<Cell<Option<bool>>>,
     is_nontransparent: Mutex<Cell<Option<bool>>>,
+    /// Incremented whenever voxel data may have changed
+    revision: u64,
 }
 
 impl<const N: usize> Chunk<N> {
@@ -24,8 +27,10 @@ impl<const N: usize> Chunk<N> {
     pub fn new() -> Self {
         Chunk {
             data: Array3::default([N, N, N]),
+            light: Array3::default([N, N, N]),
             is_transparent: Default::default(),
             is_nontransparent: Default::default(),
+            revision: 0,
         }
     }
 
@@ -33,14 +38,42 @@ impl<const N: usize> Chunk<N> {
     pub fn data_mut(&mut self) -> &mut Array3<Voxel> {
         self.is_transparent.lock().unwrap().set(None);
         self.is_nontransparent.lock().unwrap().set(None);
+        self.revision += 1;
         &mut self.data
     }
 
+    #[inline]
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
     #[inline]
     pub fn data(&self) -> &Array3<Voxel> {
         &self.data
     }
 
+    #[inline]
+    pub fn light(&self) -> &Array3<LightLevel> {
+        &self.light
+    }
+
+    #[inline]
+    pub fn light_mut(&mut self) -> &mut Array3<LightLevel> {
+        &mut self.light
+    }
+
+    /// Heap and inline bytes used by the chunk, voxels and light included
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.data.len() * std::mem::size_of::<Voxel>()
+            + self.light_memory_usage()
+    }
+
+    /// Heap bytes used by light levels of the chunk
+    pub fn light_memory_usage(&self) -> usize {
+        self.light.len() * std::mem::size_of::<LightLevel>()
+    }
+
     /// Checks whether the provided index is on the chunk border
     /// and if it is, return border direction
     pub fn is_on_border(ind: &[usize; 3]) -> Option<Directions> {
@@ -55,7 +88,7 @@ impl<const N: usize> Chunk<N> {
             }
         }));
 
-        if dir.x + dir.y + dir.z == 0 {
+        if dir == IVec3::ZERO {
             None
         } else {
             let dir = Directions::from(dir);
@@ -84,20 +117,20 @@ impl<const N: usize> Chunk<N> {
         }
     }
 
-    pub fn is_nontransparent(&self) -> bool {
+    pub fn is_nontransparent(&self, registry: &BlockRegistry) -> bool {
         if let Some(v) = self.is_nontransparent.lock().unwrap().get() {
             return v;
         }
-        let all = self.data.iter().all(|x| !x.is_transparent());
+        let all = self.data.iter().all(|&x| !registry.is_transparent(x));
         self.is_nontransparent.lock().unwrap().set(Some(all));
         all
     }
 
-    pub fn is_transparent(&self) -> bool {
+    pub fn is_transparent(&self, registry: &BlockRegistry) -> bool {
         if let Some(v) = self.is_transparent.lock().unwrap().get() {
             return v;
         }
-        let all = self.data.iter().all(|x| x.is_transparent());
+        let all = self.data.iter().all(|&x| registry.is_transparent(x));
         self.is_transparent.lock().unwrap().set(Some(all));
         all
     }
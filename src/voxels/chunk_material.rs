@@ -0,0 +1,84 @@
+use bevy::{
+    pbr::{Material, MaterialPipeline, MaterialPipelineKey},
+    prelude::{AlphaMode, Handle, Image, Mesh},
+    reflect::TypeUuid,
+    render::{
+        mesh::MeshVertexBufferLayout,
+        render_resource::{
+            AsBindGroup, RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError,
+        },
+    },
+};
+
+use super::chunk_mesh::{ATTRIBUTE_AMBIENT_OCCLUSION, ATTRIBUTE_TEXTURE_LAYER};
+
+/// Material of chunk meshes, samples block faces from a 2d array texture
+#[derive(AsBindGroup, Debug, Clone, TypeUuid)]
+#[uuid = "3d6f1b8e-5c2a-4a57-9d0e-8f4b2c7a1e93"]
+#[bind_group_data(ChunkMaterialKey)]
+pub struct ChunkMaterial {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub textures: Handle<Image>,
+    /// Alpha blends faces instead of cutting out transparent texels
+    pub translucent: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkMaterialKey {
+    translucent: bool,
+}
+
+impl From<&ChunkMaterial> for ChunkMaterialKey {
+    fn from(material: &ChunkMaterial) -> Self {
+        Self {
+            translucent: material.translucent,
+        }
+    }
+}
+
+impl Material for ChunkMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/chunk.wgsl".into()
+    }
+
+    /// Bevy sorts translucent meshes back to front per entity, so whole chunks blend in order,
+    /// but faces inside one chunk mesh are drawn in mesh order, not by distance to the camera.
+    /// Overlapping translucent faces of a chunk, like water seen through glass, can blend wrongly.
+    /// The shader premultiplies its color after fog and tone mapping
+    fn alpha_mode(&self) -> AlphaMode {
+        if self.translucent {
+            AlphaMode::Premultiplied
+        } else {
+            AlphaMode::Opaque
+        }
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // locations match the ones bevy's prepass shader expects
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(2),
+            ATTRIBUTE_TEXTURE_LAYER.at_shader_location(3),
+            Mesh::ATTRIBUTE_COLOR.at_shader_location(4),
+            ATTRIBUTE_AMBIENT_OCCLUSION.at_shader_location(5),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        if key.bind_group_data.translucent {
+            if let Some(fragment) = descriptor.fragment.as_mut() {
+                fragment.shader_defs.push("TRANSLUCENT".into());
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,82 @@
 use bevy::{
     prelude::{Vec2, Vec3},
-    render::mesh::{Indices, Mesh},
+    render::{
+        mesh::{Indices, Mesh, MeshVertexAttribute},
+        render_resource::VertexFormat,
+    },
 };
+use serde::{Deserialize, Serialize};
 
 use crate::directions::Directions;
 
+use super::light::{LightLevel, MAX_LIGHT};
+
+/// Index of the block texture array layer sampled by the vertex
+pub const ATTRIBUTE_TEXTURE_LAYER: MeshVertexAttribute =
+    MeshVertexAttribute::new("TextureLayer", 736_225_613, VertexFormat::Uint32);
+
+/// Ambient occlusion factor of the vertex, 1 if it isn't occluded
+pub const ATTRIBUTE_AMBIENT_OCCLUSION: MeshVertexAttribute =
+    MeshVertexAttribute::new("AmbientOcclusion", 736_225_614, VertexFormat::Float32);
+
+/// Vertex brightness by ambient occlusion level, from three occluders to none
+const AO_CURVE: [f32; 4] = [0.4, 0.6, 0.8, 1.];
+
+/// Brightness multiplier per light level below the maximum
+const LIGHT_FALLOFF: f32 = 0.8;
+
+/// Algorithm used by `VoxelWorld` to turn chunk voxels into quads
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeshingMode {
+    /// One quad per exposed voxel face
+    #[default]
+    Naive,
+    /// Coplanar faces of the same voxel id are merged into maximal rectangles
+    Greedy,
+}
+
+/// Geometry of a chunk split by render pass
+#[derive(Debug, Default)]
+pub struct ChunkMeshes {
+    pub opaque: ChunkMeshData,
+    /// Faces of translucent blocks, rendered alpha blended after opaque geometry
+    pub translucent: ChunkMeshData,
+}
+
+impl ChunkMeshes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Vertices of both parts
+    pub fn vertex_count(&self) -> usize {
+        self.opaque.vertex_count() + self.translucent.vertex_count()
+    }
+
+    /// Part faces of a block go into
+    pub fn part_mut(&mut self, translucent: bool) -> &mut ChunkMeshData {
+        if translucent {
+            &mut self.translucent
+        } else {
+            &mut self.opaque
+        }
+    }
+
+    pub fn scale(&mut self, scale: f32) {
+        self.opaque.scale(scale);
+        self.translucent.scale(scale);
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ChunkMeshData {
     positions: Vec<Vec3>,
     normals: Vec<Vec3>,
     uv: Vec<Vec2>,
-    indices: Vec<u16>,
+    texture_layers: Vec<u32>,
+    colors: Vec<[f32; 4]>,
+    ambient_occlusion: Vec<f32>,
+    indices: Vec<u32>,
 }
 
 impl ChunkMeshData {
@@ -18,63 +84,179 @@ impl ChunkMeshData {
         Self::default()
     }
 
-    pub fn insert_quad(&mut self, pos: Vec3, dir: Directions) {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Scales vertex positions, meshes of downsampled chunks are built in downsampled voxels
+    pub fn scale(&mut self, scale: f32) {
+        self.positions.iter_mut().for_each(|pos| *pos *= scale);
+    }
+
+    /// Returns mesh indices, `u16` if every vertex can be addressed by it, `u32` otherwise.
+    pub fn indices(&self) -> Indices {
+        if self.positions.len() <= u16::MAX as usize + 1 {
+            Indices::U16(self.indices.iter().map(|&i| i as u16).collect())
+        } else {
+            Indices::U32(self.indices.clone())
+        }
+    }
+
+    pub fn insert_quad(
+        &mut self,
+        pos: Vec3,
+        dir: Directions,
+        texture_layer: u32,
+        light: LightLevel,
+        ao: [u8; 4],
+    ) {
+        self.insert_rect(pos, dir, Vec3::ONE, texture_layer, light, ao);
+    }
+
+    /// Inserts a quad centered at `pos` stretched by `size` along each axis.
+    /// UVs are tiled so that the texture repeats once per voxel.
+    /// `light` is the light in front of the quad, baked into vertex colors.
+    /// `ao` is the ambient occlusion level of each vertex from 0 (darkest) to 3.
+    pub fn insert_rect(
+        &mut self,
+        pos: Vec3,
+        dir: Directions,
+        size: Vec3,
+        texture_layer: u32,
+        light: LightLevel,
+        ao: [u8; 4],
+    ) {
         if dir.into_iter().count() > 1 {
-            panic!("insert_quad called with more than one direction");
+            panic!("insert_rect called with more than one direction");
         }
 
-        let count = self.positions.len() as u16;
+        let verts = Self::quad_vertices(dir).map(|vert| pos + vert * size);
+        self.push_quad(verts, dir.to_fvec(), texture_layer, light, ao);
+    }
+
+    /// Inserts two crossed double-sided quads filling the voxel centered at `pos`, used for plants
+    pub fn insert_cross(&mut self, pos: Vec3, texture_layer: u32, light: LightLevel) {
+        for (a, b) in [
+            (Vec3::new(-0.5, 0., -0.5), Vec3::new(0.5, 0., 0.5)),
+            (Vec3::new(-0.5, 0., 0.5), Vec3::new(0.5, 0., -0.5)),
+        ] {
+            let front = [
+                a + Vec3::Y / 2.,
+                b + Vec3::Y / 2.,
+                a - Vec3::Y / 2.,
+                b - Vec3::Y / 2.,
+            ];
+            let normal = (b - a).cross(Vec3::Y).normalize();
+            let back = [front[1], front[0], front[3], front[2]];
+            self.push_quad(front.map(|v| pos + v), normal, texture_layer, light, [3; 4]);
+            self.push_quad(back.map(|v| pos + v), -normal, texture_layer, light, [3; 4]);
+        }
+    }
+
+    /// Pushes a quad with vertices in `quad_vertices` order
+    fn push_quad(
+        &mut self,
+        verts: [Vec3; 4],
+        normal: Vec3,
+        texture_layer: u32,
+        light: LightLevel,
+        ao: [u8; 4],
+    ) {
+        let count = self.positions.len() as u32;
+        let (u_axis, v_axis) = Self::uv_axes(normal);
+        let uv = verts.map(|vert| Vec2::new(vert.dot(u_axis), vert.dot(v_axis)));
+        let min = uv.into_iter().reduce(Vec2::min).unwrap();
+
+        self.positions.extend(verts);
+        self.normals.extend([normal; 4]);
+        self.uv.extend(uv.map(|uv| uv - min));
+
+        self.texture_layers.extend([texture_layer; 4]);
+        self.colors.extend([Self::light_color(light); 4]);
+        self.ambient_occlusion
+            .extend(ao.map(|level| AO_CURVE[level as usize]));
+
+        // split along the brighter diagonal so occlusion is interpolated symmetrically
+        if ao[0] + ao[3] > ao[1] + ao[2] {
+            self.indices.push(count);
+            self.indices.push(count + 2);
+            self.indices.push(count + 3);
+            self.indices.push(count);
+            self.indices.push(count + 3);
+            self.indices.push(count + 1);
+        } else {
+            self.indices.push(count);
+            self.indices.push(count + 2);
+            self.indices.push(count + 1);
+            self.indices.push(count + 3);
+            self.indices.push(count + 1);
+            self.indices.push(count + 2);
+        }
+    }
+
+    /// Directions u and v grow along on a face seen from the front, u to the right and v down.
+    /// Side faces keep textures upright
+    fn uv_axes(normal: Vec3) -> (Vec3, Vec3) {
+        if normal.y.abs() > 0.5 {
+            (Vec3::X, Vec3::Z * normal.y.signum())
+        } else {
+            (Vec3::Y.cross(normal).normalize(), Vec3::NEG_Y)
+        }
+    }
+
+    /// Corners of a unit quad facing `dir` relative to the voxel center
+    pub fn quad_vertices(dir: Directions) -> [Vec3; 4] {
         /*
         2-------3   ^
         |       |  x|
         0-------1  y->
         */
-        let verts: [(Vec3, Vec3, Vec3, Vec3); 6] = [
+        let verts: [[Vec3; 4]; 6] = [
             // south
-            (
+            [
                 [-0.5, 0.5, 0.5].into(),
                 [0.5, 0.5, 0.5].into(),
                 [-0.5, -0.5, 0.5].into(),
                 [0.5, -0.5, 0.5].into(),
-            ),
+            ],
             // north
-            (
+            [
                 [-0.5, -0.5, -0.5].into(),
                 [0.5, -0.5, -0.5].into(),
                 [-0.5, 0.5, -0.5].into(),
                 [0.5, 0.5, -0.5].into(),
-            ),
+            ],
             // up
-            (
+            [
                 [-0.5, 0.5, -0.5].into(),
                 [0.5, 0.5, -0.5].into(),
                 [-0.5, 0.5, 0.5].into(),
                 [0.5, 0.5, 0.5].into(),
-            ),
+            ],
             // down
-            (
+            [
                 [-0.5, -0.5, 0.5].into(),
                 [0.5, -0.5, 0.5].into(),
                 [-0.5, -0.5, -0.5].into(),
                 [0.5, -0.5, -0.5].into(),
-            ),
+            ],
             // east
-            (
+            [
                 [0.5, -0.5, 0.5].into(),
                 [0.5, 0.5, 0.5].into(),
                 [0.5, -0.5, -0.5].into(),
                 [0.5, 0.5, -0.5].into(),
-            ),
+            ],
             // west
-            (
+            [
                 [-0.5, -0.5, -0.5].into(),
                 [-0.5, 0.5, -0.5].into(),
                 [-0.5, -0.5, 0.5].into(),
                 [-0.5, 0.5, 0.5].into(),
-            ),
+            ],
         ];
 
-        let (vert0, vert1, vert2, vert3) = match dir {
+        match dir {
             x if x.intersects(Directions::UP) => verts[2],
             x if x.intersects(Directions::DOWN) => verts[3],
             x if x.intersects(Directions::WEST) => verts[5],
@@ -82,29 +264,12 @@ impl ChunkMeshData {
             x if x.intersects(Directions::NORTH) => verts[1],
             x if x.intersects(Directions::SOUTH) => verts[0],
             _ => unreachable!(),
-        };
-
-        self.positions.push(pos + vert0);
-        self.positions.push(pos + vert1);
-        self.positions.push(pos + vert2);
-        self.positions.push(pos + vert3);
-
-        self.normals.push(dir.to_fvec());
-        self.normals.push(dir.to_fvec());
-        self.normals.push(dir.to_fvec());
-        self.normals.push(dir.to_fvec());
-
-        self.uv.push([0., 0.].into());
-        self.uv.push([1., 0.].into());
-        self.uv.push([0., 1.].into());
-        self.uv.push([1., 1.].into());
+        }
+    }
 
-        self.indices.push(count);
-        self.indices.push(count + 2);
-        self.indices.push(count + 1);
-        self.indices.push(count + 3);
-        self.indices.push(count + 1);
-        self.indices.push(count + 2);
+    fn light_color(light: LightLevel) -> [f32; 4] {
+        let brightness = LIGHT_FALLOFF.powi((MAX_LIGHT - light.max()) as i32);
+        [brightness, brightness, brightness, 1.]
     }
 
     /// Returns a mesh. None if mesh is empty.
@@ -132,8 +297,93 @@ impl ChunkMeshData {
                 Mesh::ATTRIBUTE_UV_0,
                 self.uv.iter().map(|&x| x.into()).collect::<Vec<[f32; 2]>>(),
             );
-            mesh.set_indices(Some(Indices::U16(self.indices.clone())));
+            mesh.insert_attribute(ATTRIBUTE_TEXTURE_LAYER, self.texture_layers.clone());
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors.clone());
+            mesh.insert_attribute(ATTRIBUTE_AMBIENT_OCCLUSION, self.ambient_occlusion.clone());
+            mesh.set_indices(Some(self.indices()));
             Some(mesh)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad_indices(ao: [u8; 4]) -> Vec<u32> {
+        let mut mesh = ChunkMeshData::new();
+        mesh.insert_quad(Vec3::ZERO, Directions::UP, 0, LightLevel::default(), ao);
+        mesh.indices
+    }
+
+    #[test]
+    fn diagonal_avoids_darkest_corner() {
+        // vertex 0 occluded, split along 1-2
+        assert_eq!(quad_indices([0, 3, 3, 3]), vec![0, 2, 1, 3, 1, 2]);
+        // vertex 1 occluded, split along 0-3
+        assert_eq!(quad_indices([3, 1, 3, 3]), vec![0, 2, 3, 0, 3, 1]);
+        assert_eq!(quad_indices([3, 3, 3, 3]), vec![0, 2, 1, 3, 1, 2]);
+    }
+
+    #[test]
+    fn side_textures_upright() {
+        for dir in Directions::all() {
+            let mut mesh = ChunkMeshData::new();
+            mesh.insert_rect(
+                Vec3::ZERO,
+                dir,
+                Vec3::splat(2.),
+                0,
+                LightLevel::default(),
+                [3; 4],
+            );
+            let normal = dir.to_fvec();
+            let (u_axis, v_axis) = ChunkMeshData::uv_axes(normal);
+
+            // textures aren't mirrored when seen from the front
+            assert_eq!(u_axis.cross(v_axis), -normal, "{:?}", dir);
+            for (pos, uv) in mesh.positions.iter().zip(&mesh.uv) {
+                if normal.y == 0. {
+                    // top edge at v = 0
+                    assert_eq!(uv.y, 1. - pos.y, "{:?}", dir);
+                }
+                assert_eq!(uv.x, pos.dot(u_axis) + 1., "{:?}", dir);
+                assert_eq!(uv.y, pos.dot(v_axis) + 1., "{:?}", dir);
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_quad_keeps_winding() {
+        let mut mesh = ChunkMeshData::new();
+        mesh.insert_quad(
+            Vec3::ZERO,
+            Directions::UP,
+            0,
+            LightLevel::default(),
+            [3, 0, 3, 3],
+        );
+
+        for tri in mesh.indices.chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[tri[i] as usize]);
+            let normal = (b - a).cross(c - a).normalize();
+            assert_eq!(normal, Vec3::Y);
+        }
+        assert_eq!(mesh.ambient_occlusion[1], AO_CURVE[0]);
+    }
+
+    #[test]
+    fn cross_faces_both_sides() {
+        let mut mesh = ChunkMeshData::new();
+        mesh.insert_cross(Vec3::ZERO, 0, LightLevel::default());
+
+        assert_eq!(mesh.vertex_count(), 16);
+        for (quad, tris) in mesh.indices.chunks(6).enumerate() {
+            let normal = mesh.normals[quad * 4];
+            for tri in tris.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| mesh.positions[tri[i] as usize]);
+                assert!((b - a).cross(c - a).normalize().distance(normal) < 1e-6);
+            }
+        }
+    }
+}
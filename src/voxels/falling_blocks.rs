@@ -0,0 +1,209 @@
+use bevy::prelude::{Component, IVec3, Mesh, Vec3};
+
+use crate::directions::Directions;
+
+use super::{
+    block_registry::BlockRegistry,
+    block_ticks::VoxelAccess,
+    chunk_mesh::ChunkMeshData,
+    light::{LightLevel, MAX_LIGHT},
+    voxel::Voxel,
+};
+
+/// Downward acceleration of falling blocks in voxels per second squared
+pub const GRAVITY: f32 = 30.;
+/// Max falling speed in voxels per second
+pub const TERMINAL_VELOCITY: f32 = 40.;
+
+/// Voxel detached from the world, falling until it lands on a solid voxel.
+/// The entity's translation is the block's minimum corner
+#[derive(Debug, Component)]
+pub struct FallingBlock {
+    pub voxel: Voxel,
+    /// Downward speed in voxels per second
+    pub velocity: f32,
+}
+
+impl FallingBlock {
+    pub fn new(voxel: Voxel) -> Self {
+        Self {
+            voxel,
+            velocity: 0.,
+        }
+    }
+
+    /// Moves the block at `pos` down for `dt` seconds, stopping on top of solid voxels.
+    /// Blocks above chunks which aren't loaded wait in place until they load.
+    /// Returns the voxel position to insert the block at once it lands
+    pub fn step(&mut self, pos: &mut Vec3, dt: f32, world: &dyn VoxelAccess) -> Option<IVec3> {
+        let velocity = (self.velocity + GRAVITY * dt).min(TERMINAL_VELOCITY);
+        let next = pos.y - velocity * dt;
+        let column = IVec3::new(pos.x.floor() as i32, 0, pos.z.floor() as i32);
+
+        // every voxel the bottom passes, checked top down so fast blocks don't tunnel
+        let top = pos.y.ceil() as i32 - 1;
+        for y in (next.floor() as i32..=top).rev() {
+            let cell = column + IVec3::Y * y;
+            match world.voxel(cell) {
+                None => {
+                    self.velocity = 0.;
+                    return None;
+                }
+                Some(vox) if world.registry().is_solid(vox) => {
+                    pos.y = (y + 1) as f32;
+                    return Some(cell + IVec3::Y);
+                }
+                Some(_) => {}
+            }
+        }
+        self.velocity = velocity;
+        pos.y = next;
+        None
+    }
+
+    /// Inserts the landed block at `cell` or the first voxel above it which isn't solid,
+    /// replacing plants and fluids there. Changes in unloaded chunks wait until they load
+    pub fn land(&self, cell: IVec3, world: &dyn VoxelAccess) {
+        let mut cell = cell;
+        while world
+            .voxel(cell)
+            .is_some_and(|vox| world.registry().is_solid(vox))
+        {
+            cell += IVec3::Y;
+        }
+        world.set_voxel(cell, self.voxel);
+    }
+
+    /// Puts the block at `pos` back into the world at the nearest voxel, for when
+    /// it can't keep falling, like on exit or when its chunk unloads
+    pub fn settle(&self, pos: Vec3, world: &dyn VoxelAccess) {
+        self.land(pos.round().as_ivec3(), world);
+    }
+}
+
+/// Cube mesh of a single voxel spanning from the origin to one, fully lit
+pub fn block_mesh(registry: &BlockRegistry, voxel: Voxel) -> Option<Mesh> {
+    let mut mesh = ChunkMeshData::new();
+    for dir in Directions::all() {
+        mesh.insert_quad(
+            Vec3::splat(0.5),
+            dir,
+            registry.texture_layer(voxel, dir),
+            LightLevel::new(MAX_LIGHT, 0),
+            [3; 4],
+        );
+    }
+    mesh.build_mesh()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{
+        chunk::ChunkPosition,
+        test_utils::{floor_world, named, voxel, world_with_floor, FloorWorld},
+    };
+
+    fn fall(block: &mut FallingBlock, pos: &mut Vec3, world: &FloorWorld) -> IVec3 {
+        for _ in 0..1000 {
+            if let Some(cell) = block.step(pos, 1. / 60., world) {
+                return cell;
+            }
+        }
+        panic!("block didn't land");
+    }
+
+    /// Lands the block and applies the change
+    fn land(block: &FallingBlock, cell: IVec3, world: &mut FloorWorld) {
+        block.land(cell, world);
+        world.apply_voxel_changes();
+    }
+
+    #[test]
+    fn lands_on_floor() {
+        let mut world = floor_world();
+        let mut block = FallingBlock::new(named(world.registry(), "sand"));
+        let mut pos = Vec3::new(3., 20., -5.);
+
+        let cell = fall(&mut block, &mut pos, &world);
+
+        assert_eq!(cell, IVec3::new(3, 1, -5));
+        assert_eq!(pos, Vec3::new(3., 1., -5.));
+        land(&block, cell, &mut world);
+        assert_eq!(voxel(&world, cell), block.voxel);
+    }
+
+    #[test]
+    fn fast_block_doesnt_tunnel() {
+        let mut world = floor_world();
+        let mut block = FallingBlock::new(named(world.registry(), "sand"));
+        block.velocity = TERMINAL_VELOCITY;
+        world.set_voxel(IVec3::new(0, 5, 0), named(world.registry(), "stone"));
+        world.apply_voxel_changes();
+        let mut pos = Vec3::new(0., 6.5, 0.);
+
+        assert_eq!(block.step(&mut pos, 0.5, &world), Some(IVec3::new(0, 6, 0)));
+        assert_eq!(pos.y, 6.);
+    }
+
+    #[test]
+    fn waits_above_unloaded_chunks() {
+        let mut world = floor_world();
+        let mut block = FallingBlock::new(named(world.registry(), "sand"));
+        let mut pos = Vec3::new(0., -3., 0.);
+        for _ in 0..100 {
+            assert_eq!(block.step(&mut pos, 1. / 60., &world), None);
+        }
+        assert!(pos.y >= -16.);
+
+        let below = ChunkPosition::new(IVec3::new(0, -2, 0));
+        let chunk = world.gen_chunk(&below);
+        world.insert_at(&below, chunk);
+        world.set_voxel(IVec3::new(0, -20, 0), named(world.registry(), "stone"));
+        world.apply_voxel_changes();
+
+        assert_eq!(fall(&mut block, &mut pos, &world), IVec3::new(0, -19, 0));
+    }
+
+    #[test]
+    fn settles_at_nearest_voxel() {
+        let mut world = floor_world();
+        let block = FallingBlock::new(named(world.registry(), "sand"));
+
+        block.settle(Vec3::new(2., 5.4, 2.), &world);
+        world.apply_voxel_changes();
+
+        assert_eq!(voxel(&world, IVec3::new(2, 5, 2)), block.voxel);
+    }
+
+    #[test]
+    fn lands_in_chunk_below() {
+        let mut world = world_with_floor("air");
+        world.set_voxel(IVec3::new(2, -10, 2), named(world.registry(), "stone"));
+        world.apply_voxel_changes();
+        world.dirty().pin().clear();
+        let mut block = FallingBlock::new(named(world.registry(), "sand"));
+        let mut pos = Vec3::new(2., 12., 2.);
+
+        let cell = fall(&mut block, &mut pos, &world);
+        land(&block, cell, &mut world);
+
+        assert_eq!(voxel(&world, IVec3::new(2, -9, 2)), block.voxel);
+        assert!(world
+            .dirty()
+            .pin()
+            .contains(&ChunkPosition::new(IVec3::NEG_Y)));
+    }
+
+    #[test]
+    fn lands_on_top_of_blocks_placed_meanwhile() {
+        let mut world = floor_world();
+        let block = FallingBlock::new(named(world.registry(), "sand"));
+        world.set_voxel(IVec3::new(0, 1, 0), named(world.registry(), "stone"));
+        world.apply_voxel_changes();
+
+        land(&block, IVec3::new(0, 1, 0), &mut world);
+
+        assert_eq!(voxel(&world, IVec3::new(0, 2, 0)), block.voxel);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+
+use bevy::prelude::{IVec3, Resource, Vec3};
+
+use crate::directions::Directions;
+
+use super::{
+    block_registry::BlockRegistry, terrain_generation::VoxelGenerator, voxel::Voxel,
+    world::VoxelWorld,
+};
+
+const HORIZONTAL: [Directions; 4] = [
+    Directions::NORTH,
+    Directions::SOUTH,
+    Directions::WEST,
+    Directions::EAST,
+];
+
+/// Cellular automaton spreading fluids into neighbouring air.
+/// Each step every pending voxel takes the strongest flow into it:
+/// one level below the fluid above it or below a horizontal neighbour which can't fall further.
+/// Sources never change, flowing voxels without inflow drain away
+#[derive(Resource, Default)]
+pub struct FluidSimulation {
+    /// Voxels to update on the next step
+    pending: HashSet<IVec3>,
+    tick: u64,
+}
+
+impl FluidSimulation {
+    /// Queues changed voxels and their neighbours for the next step
+    pub fn wake(&mut self, changed: impl IntoIterator<Item = IVec3>) {
+        for pos in changed {
+            self.pending.insert(pos);
+            for dir in Directions::all() {
+                self.pending.insert(pos + dir.to_ivec());
+            }
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Advances the simulation by a tick, pushing voxel changes through `set_voxel_at`.
+    /// Voxels of fluids which don't flow on this tick stay pending.
+    /// Returns the number of changed voxels
+    pub fn step<G, const N: usize>(&mut self, vox_world: &VoxelWorld<G, N>) -> usize
+    where
+        G: VoxelGenerator<N> + Send + Sync,
+    {
+        self.tick += 1;
+        let registry = vox_world.registry();
+        let voxel_at = |pos: IVec3| vox_world.voxel_at_pos(&(pos.as_vec3() + Vec3::splat(0.5)));
+
+        let mut changed = 0;
+        for pos in std::mem::take(&mut self.pending) {
+            let Some(current) = voxel_at(pos) else {
+                continue;
+            };
+            let Some((source, next)) =
+                Self::next_state(registry, current, |offset| voxel_at(pos + offset))
+            else {
+                continue;
+            };
+            let interval = registry
+                .get(source)
+                .fluid
+                .map_or(1, |fluid| fluid.flow_interval.max(1));
+            if !self.tick.is_multiple_of(interval as u64) {
+                self.pending.insert(pos);
+                continue;
+            }
+            if next != current {
+                vox_world.set_voxel_at_pos(&(pos.as_vec3() + Vec3::splat(0.5)), next);
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    /// Source block of the fluid involved and the next state of a voxel,
+    /// `None` for voxels which aren't air or flowing fluid and air without inflow
+    fn next_state(
+        registry: &BlockRegistry,
+        current: Voxel,
+        neighbour: impl Fn(IVec3) -> Option<Voxel>,
+    ) -> Option<(Voxel, Voxel)> {
+        let current_fluid = registry.fluid_level(current);
+        let is_source = |(source, level): (Voxel, u8)| {
+            registry
+                .get(source)
+                .fluid
+                .is_some_and(|fluid| fluid.levels == level)
+        };
+        match current_fluid {
+            Some(fluid) if is_source(fluid) => return None,
+            None if current.id != 0 => return None,
+            _ => {}
+        }
+
+        let fluid_at = |offset: IVec3| neighbour(offset).and_then(|vox| registry.fluid_level(vox));
+        let can_fall = |offset: IVec3, source: Voxel| {
+            neighbour(offset - IVec3::Y).is_some_and(|below| {
+                below.id == 0
+                    || registry
+                        .fluid_level(below)
+                        .is_some_and(|(s, _)| s == source)
+            })
+        };
+
+        let mut inflows = Vec::new();
+        // falling fluid loses a single level
+        if let Some((source, _)) = fluid_at(IVec3::Y) {
+            let levels = registry.get(source).fluid.map_or(1, |fluid| fluid.levels);
+            inflows.push((source, levels - 1));
+        }
+        for dir in HORIZONTAL {
+            let offset = dir.to_ivec();
+            if let Some((source, level)) = fluid_at(offset) {
+                if !can_fall(offset, source) {
+                    inflows.push((source, level - 1));
+                }
+            }
+        }
+
+        // flowing fluid is only fed by its own kind
+        let strongest = inflows
+            .into_iter()
+            .filter(|(source, _)| current_fluid.is_none_or(|(own, _)| own == *source))
+            .max_by_key(|(source, level)| (*level, u16::MAX - source.id));
+        match (strongest, current_fluid) {
+            (Some((source, level)), _) => Some((source, registry.fluid_voxel(source, level))),
+            (None, Some((source, _))) => Some((source, Voxel::default())),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{
+        block_ticks::VoxelAccess,
+        chunk::ChunkPosition,
+        test_utils::{floor_world, named, voxel, FloorWorld},
+    };
+
+    fn run_until_stable(world: &mut FloorWorld, sim: &mut FluidSimulation) {
+        for _ in 0..100 {
+            world.apply_voxel_changes();
+            sim.wake(world.take_voxels_changed());
+            if sim.pending() == 0 {
+                return;
+            }
+            sim.step(world);
+        }
+        panic!("fluids didn't settle");
+    }
+
+    #[test]
+    fn water_spreads_and_drains() {
+        let mut world = floor_world();
+        let mut sim = FluidSimulation::default();
+        let water = named(world.registry(), "water");
+        let source = IVec3::new(8, 1, 8);
+        let levels = world.registry().get(water).fluid.unwrap().levels as i32;
+
+        world.set_voxel(source, water);
+        run_until_stable(&mut world, &mut sim);
+
+        let level_at = |world: &FloorWorld, pos| {
+            world
+                .registry()
+                .fluid_level(voxel(world, pos))
+                .map_or(0, |(_, level)| level as i32)
+        };
+        for distance in 0..=levels {
+            // crosses into the eastern chunk
+            let pos = source + IVec3::X * distance;
+            assert_eq!(level_at(&world, pos), (levels - distance).max(0));
+        }
+        assert_eq!(level_at(&world, source + IVec3::new(2, 0, 3)), levels - 5);
+        assert_eq!(voxel(&world, source + IVec3::Y).id, 0);
+        assert!(world.dirty().pin().contains(&ChunkPosition::new(IVec3::X)));
+
+        world.set_voxel(source, Voxel::default());
+        run_until_stable(&mut world, &mut sim);
+
+        for distance in 0..levels {
+            assert_eq!(voxel(&world, source + IVec3::X * distance).id, 0);
+        }
+    }
+
+    #[test]
+    fn falling_water_spreads_on_landing() {
+        let mut world = floor_world();
+        let mut sim = FluidSimulation::default();
+        let water = named(world.registry(), "water");
+        let levels = world.registry().get(water).fluid.unwrap().levels;
+
+        world.set_voxel(IVec3::new(8, 5, 8), water);
+        run_until_stable(&mut world, &mut sim);
+
+        let registry = world.registry();
+        for y in 1..5 {
+            assert_eq!(
+                voxel(&world, IVec3::new(8, y, 8)),
+                registry.fluid_voxel(water, levels - 1)
+            );
+        }
+        // doesn't spread while it can fall
+        assert_eq!(voxel(&world, IVec3::new(9, 3, 8)).id, 0);
+        assert_eq!(
+            voxel(&world, IVec3::new(9, 1, 8)),
+            registry.fluid_voxel(water, levels - 2)
+        );
+    }
+}
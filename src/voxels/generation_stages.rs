@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use bevy::prelude::IVec3;
+
+use super::{
+    chunk::{Chunk, ChunkPosition},
+    voxel::Voxel,
+};
+
+/// Stages a chunk passes through while generating, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum GenerationStage {
+    /// Filled in isolation from the heightmap
+    Terrain,
+    /// Caves carved out
+    Carved,
+    /// Decorations placed, which could reach into neighbours
+    Decorated,
+    /// No more changes from neighbours are possible, chunk is inserted into the world
+    Finalized,
+}
+
+impl GenerationStage {
+    pub fn next(self) -> Option<Self> {
+        match self {
+            Self::Terrain => Some(Self::Carved),
+            Self::Carved => Some(Self::Decorated),
+            Self::Decorated => Some(Self::Finalized),
+            Self::Finalized => None,
+        }
+    }
+
+    /// Stage all 26 neighbours must reach before a chunk can enter this stage
+    pub fn neighbour_requirement(self) -> Option<Self> {
+        match self {
+            // decorations written into neighbours which aren't carved yet are replayed after carving
+            Self::Terrain | Self::Carved | Self::Decorated => None,
+            // all neighbours that could write into the chunk have done so
+            Self::Finalized => Some(Self::Decorated),
+        }
+    }
+}
+
+/// Chunk which hasn't passed all generation stages yet
+#[derive(Debug)]
+pub struct ProtoChunk<const N: usize> {
+    pub stage: GenerationStage,
+    pub chunk: Chunk<N>,
+}
+
+/// Chunks at most this far from a chunk along each axis must be carved before it can be finalized:
+/// its neighbours must be decorated, which doesn't wait for their neighbours
+pub const FINALIZATION_RADIUS: i32 = 1;
+
+/// Positions of the 26 chunks around `pos`
+pub fn neighbours(pos: ChunkPosition) -> impl Iterator<Item = ChunkPosition> {
+    within(pos, 1).filter(move |p| *p != pos)
+}
+
+/// Positions of chunks at most `radius` away from `pos` along each axis, including `pos`
+pub fn within(pos: ChunkPosition, radius: i32) -> impl Iterator<Item = ChunkPosition> {
+    (-radius..=radius)
+        .flat_map(move |x| {
+            (-radius..=radius)
+                .flat_map(move |y| (-radius..=radius).map(move |z| IVec3::new(x, y, z)))
+        })
+        .map(move |offset| ChunkPosition::new(pos.pos + offset))
+}
+
+/// Voxels of a chunk being decorated and of its 26 neighbours, addressed by world position.
+/// Only chunks which aren't finalized yet can be written to
+pub struct DecorationRegion<'a, const N: usize> {
+    center: ChunkPosition,
+    proto_chunks: &'a mut HashMap<ChunkPosition, ProtoChunk<N>>,
+    chunks: &'a HashMap<ChunkPosition, Chunk<N>>,
+    /// Chunk writes are limited to, if any
+    write_only: Option<ChunkPosition>,
+}
+
+impl<'a, const N: usize> DecorationRegion<'a, N> {
+    const NI: i32 = N as i32;
+
+    pub fn new(
+        center: ChunkPosition,
+        proto_chunks: &'a mut HashMap<ChunkPosition, ProtoChunk<N>>,
+        chunks: &'a HashMap<ChunkPosition, Chunk<N>>,
+    ) -> Self {
+        Self {
+            center,
+            proto_chunks,
+            chunks,
+            write_only: None,
+        }
+    }
+
+    /// Discards writes to chunks other than `pos`
+    pub fn write_only(mut self, pos: ChunkPosition) -> Self {
+        self.write_only = Some(pos);
+        self
+    }
+
+    pub fn center(&self) -> ChunkPosition {
+        self.center
+    }
+
+    /// World position of the center chunk's minimum corner
+    pub fn origin(&self) -> IVec3 {
+        self.center.pos * Self::NI
+    }
+
+    /// Voxel at the world position, `None` if it's outside of the region
+    pub fn get(&self, pos: IVec3) -> Option<Voxel> {
+        let (chpos, index) = self.split(pos)?;
+        self.proto_chunks
+            .get(&chpos)
+            .map(|proto| &proto.chunk)
+            .or_else(|| self.chunks.get(&chpos))
+            .map(|chunk| chunk.data()[index])
+    }
+
+    /// Sets the voxel at the world position.
+    /// Returns false if it's outside of the region or in a finalized chunk, which is left unchanged
+    pub fn set(&mut self, pos: IVec3, voxel: Voxel) -> bool {
+        let Some((chpos, index)) = self.split(pos) else {
+            return false;
+        };
+        if self.write_only.is_some_and(|only| only != chpos) {
+            return false;
+        }
+        match self.proto_chunks.get_mut(&chpos) {
+            Some(proto) => {
+                proto.chunk.data_mut()[index] = voxel;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn split(&self, pos: IVec3) -> Option<(ChunkPosition, [usize; 3])> {
+        let chpos = IVec3::new(
+            pos.x.div_euclid(Self::NI),
+            pos.y.div_euclid(Self::NI),
+            pos.z.div_euclid(Self::NI),
+        );
+        if (chpos - self.center.pos).abs().max_element() > 1 {
+            return None;
+        }
+        let index = (pos - chpos * Self::NI).to_array().map(|v| v as usize);
+        Some((ChunkPosition::new(chpos), index))
+    }
+}
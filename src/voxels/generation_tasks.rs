@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::{
+    prelude::Resource,
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+
+use crate::error;
+
+use super::{
+    chunk::{Chunk, ChunkPosition, CHSIZE},
+    region_storage::RegionReader,
+    terrain_generation::VoxelGenerator,
+    world::VoxelWorldProcedural,
+};
+
+/// Stored chunk read from region files, None if it was never saved
+type StoredChunk = error::Result<Option<Chunk<CHSIZE>>>;
+
+/// Chunks requested by loaders, terrain generation tasks running for them
+/// and tasks reading their saved versions, all on the `AsyncComputeTaskPool`
+#[derive(Resource, Default)]
+pub struct GenerationTasks {
+    /// Chunks to complete once terrain around them is generated
+    requested: HashSet<ChunkPosition>,
+    in_flight: HashMap<ChunkPosition, Task<Chunk<CHSIZE>>>,
+    /// Reader of saved chunks, nothing is read without one
+    storage: Option<RegionReader>,
+    reading: HashMap<ChunkPosition, Task<StoredChunk>>,
+    read: HashMap<ChunkPosition, StoredChunk>,
+}
+
+impl GenerationTasks {
+    /// Tasks which read requested chunks saved in `storage`
+    pub fn new(storage: Option<RegionReader>) -> Self {
+        Self {
+            storage,
+            ..Default::default()
+        }
+    }
+
+    /// Returns false if the chunk was already requested
+    pub fn request(&mut self, pos: ChunkPosition) -> bool {
+        self.requested.insert(pos)
+    }
+
+    pub fn requested(&self) -> &HashSet<ChunkPosition> {
+        &self.requested
+    }
+
+    /// Running generation and read tasks
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len() + self.reading.len()
+    }
+
+    /// Forgets requests and cancels tasks of chunks matching the predicate
+    pub fn cancel(&mut self, mut predicate: impl FnMut(&ChunkPosition) -> bool) {
+        self.requested.retain(|pos| !predicate(pos));
+        self.in_flight.retain(|pos, _| !predicate(pos));
+        self.reading.retain(|pos, _| !predicate(pos));
+        self.read.retain(|pos, _| !predicate(pos));
+    }
+
+    /// Inserts chunks of finished tasks into the world and keeps finished reads
+    pub fn collect_finished(&mut self, vox_world: &mut VoxelWorldProcedural) {
+        let finished = self
+            .in_flight
+            .iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        for pos in finished {
+            let task = self.in_flight.remove(&pos).unwrap();
+            // doesn't block, the task is finished
+            vox_world.insert_carved(pos, future::block_on(task));
+        }
+
+        let finished = self
+            .reading
+            .iter()
+            .filter(|(_, task)| task.is_finished())
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+        for pos in finished {
+            let task = self.reading.remove(&pos).unwrap();
+            self.read.insert(pos, future::block_on(task));
+        }
+    }
+
+    /// Spawns tasks reading requested chunks and generating terrain around them,
+    /// keeping at most `limit` in flight
+    pub fn spawn_tasks(&mut self, vox_world: &VoxelWorldProcedural, limit: usize) {
+        let pool = AsyncComputeTaskPool::get();
+        if let Some(storage) = &self.storage {
+            for &pos in self.requested.iter() {
+                if self.in_flight() >= limit {
+                    return;
+                }
+                if self.read.contains_key(&pos) {
+                    continue;
+                }
+                self.reading.entry(pos).or_insert_with(|| {
+                    let storage = storage.clone();
+                    pool.spawn(async move { storage.read_chunk(&pos) })
+                });
+            }
+        }
+        for requested in self.requested.iter() {
+            for pos in vox_world.terrain_missing_for(requested) {
+                if self.in_flight() >= limit {
+                    return;
+                }
+                self.in_flight.entry(pos).or_insert_with(|| {
+                    let generator = vox_world.generator();
+                    pool.spawn(async move { generator.generate(&pos) })
+                });
+            }
+        }
+    }
+
+    /// Removes and returns at most `limit` requested chunks which can be completed
+    /// without generating terrain, along with their stored versions
+    pub fn take_ready(
+        &mut self,
+        vox_world: &VoxelWorldProcedural,
+        limit: usize,
+    ) -> Vec<(ChunkPosition, StoredChunk)> {
+        let ready = self
+            .requested
+            .iter()
+            .filter(|pos| self.storage.is_none() || self.read.contains_key(pos))
+            .filter(|pos| vox_world.terrain_missing_for(pos).next().is_none())
+            .take(limit)
+            .copied()
+            .collect::<Vec<_>>();
+        ready
+            .into_iter()
+            .map(|pos| {
+                self.requested.remove(&pos);
+                (pos, self.read.remove(&pos).unwrap_or(Ok(None)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{
+        region_storage::RegionStorage, terrain_generation::ProceduralGenerator, voxel::Voxel,
+    };
+    use bevy::{prelude::IVec3, tasks::TaskPool};
+
+    /// Runs the tasks until some requested chunks are ready
+    fn wait_ready(
+        tasks: &mut GenerationTasks,
+        vox_world: &mut VoxelWorldProcedural,
+        limit: usize,
+    ) -> Vec<(ChunkPosition, StoredChunk)> {
+        loop {
+            tasks.collect_finished(vox_world);
+            tasks.spawn_tasks(vox_world, 16);
+            assert!(tasks.in_flight() <= 16);
+            let ready = tasks.take_ready(vox_world, limit);
+            if !ready.is_empty() {
+                return ready;
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn requested_chunk_completed_from_tasks() {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let mut vox_world =
+            VoxelWorldProcedural::new(ProceduralGenerator::default(), Default::default());
+        let mut tasks = GenerationTasks::default();
+        let pos = ChunkPosition::new(IVec3::new(1, -1, 2));
+
+        assert!(tasks.request(pos));
+        assert!(!tasks.request(pos));
+        let ready = wait_ready(&mut tasks, &mut vox_world, 16);
+
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, pos);
+        assert!(matches!(ready[0].1, Ok(None)));
+        assert!(tasks.requested().is_empty());
+        vox_world.complete_chunk(&pos, None);
+        let expected =
+            VoxelWorldProcedural::new(ProceduralGenerator::default(), Default::default())
+                .gen_chunk(&pos);
+        // the default generator doesn't decorate
+        assert_eq!(vox_world.chunk_at(&pos).data(), expected.data());
+    }
+
+    #[test]
+    fn stored_chunks_read_in_tasks_and_capped() {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let dir = std::env::temp_dir().join(format!("generation_tasks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let saved = ChunkPosition::new(IVec3::ZERO);
+        let mut chunk = Chunk::<CHSIZE>::new();
+        chunk.data_mut()[[1, 2, 3]] = Voxel { id: 5 };
+        let mut storage = RegionStorage::new(&dir);
+        storage.save_chunks([(saved, &chunk)]).unwrap();
+        let mut vox_world =
+            VoxelWorldProcedural::new(ProceduralGenerator::default(), Default::default());
+        let mut tasks = GenerationTasks::new(Some(storage.reader()));
+        tasks.request(saved);
+        tasks.request(ChunkPosition::new(IVec3::X));
+
+        let mut ready = HashMap::new();
+        while ready.len() < 2 {
+            let taken = wait_ready(&mut tasks, &mut vox_world, 1);
+            assert_eq!(taken.len(), 1);
+            ready.extend(taken);
+        }
+
+        let stored = ready.remove(&saved).unwrap().unwrap().unwrap();
+        assert_eq!(stored.data()[[1, 2, 3]].id, 5);
+        assert!(matches!(
+            ready.remove(&ChunkPosition::new(IVec3::X)),
+            Some(Ok(None))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cancel_drops_requests_and_tasks() {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let vox_world =
+            VoxelWorldProcedural::new(ProceduralGenerator::default(), Default::default());
+        let mut tasks = GenerationTasks::default();
+        tasks.request(ChunkPosition::new(IVec3::ZERO));
+        tasks.request(ChunkPosition::new(IVec3::X * 20));
+        tasks.spawn_tasks(&vox_world, 1000);
+
+        tasks.cancel(|pos| pos.pos.x > 10);
+
+        assert_eq!(tasks.requested().len(), 1);
+        assert_eq!(tasks.in_flight(), 27);
+    }
+}
@@ -0,0 +1,664 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::prelude::IVec3;
+
+use crate::{core::VecExtensions, directions::Directions};
+
+use super::{
+    block_registry::BlockRegistry,
+    chunk::{Chunk, ChunkPosition},
+    voxel::Voxel,
+};
+
+pub const MAX_LIGHT: u8 = 15;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightChannel {
+    /// Light coming from above, doesn't fade while going straight down
+    Sky,
+    /// Light emitted by blocks
+    Block,
+}
+
+impl LightChannel {
+    const ALL: [LightChannel; 2] = [LightChannel::Sky, LightChannel::Block];
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Sky light in the high and block light in the low 4 bits
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightLevel(u8);
+
+impl LightLevel {
+    pub fn new(sky: u8, block: u8) -> Self {
+        debug_assert!(sky <= MAX_LIGHT && block <= MAX_LIGHT);
+        Self(sky << 4 | block)
+    }
+
+    #[inline]
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    #[inline]
+    pub fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+
+    #[inline]
+    pub fn get(self, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Sky => self.sky(),
+            LightChannel::Block => self.block(),
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, channel: LightChannel, level: u8) {
+        *self = match channel {
+            LightChannel::Sky => Self::new(level, self.block()),
+            LightChannel::Block => Self::new(self.sky(), level),
+        }
+    }
+
+    /// Brightest of both channels
+    #[inline]
+    pub fn max(self) -> u8 {
+        self.sky().max(self.block())
+    }
+}
+
+/// Flood fills light over loaded chunks, crossing chunk borders.
+/// Chunks which aren't loaded stop light.
+pub(super) struct LightPropagator<'a, const N: usize> {
+    chunks: &'a mut HashMap<ChunkPosition, Chunk<N>>,
+    registry: &'a BlockRegistry,
+    /// Chunks whose light or light of the adjacent border voxels changed
+    changed: &'a mut HashSet<ChunkPosition>,
+    /// Chunk taken out of `chunks` so that repeated access doesn't hash
+    current: Option<(ChunkPosition, Chunk<N>)>,
+    /// Last chunk inserted into `changed`
+    last_changed: Option<ChunkPosition>,
+    add: [VecDeque<IVec3>; 2],
+    remove: [VecDeque<(IVec3, u8)>; 2],
+}
+
+impl<'a, const N: usize> LightPropagator<'a, N> {
+    const NI: i32 = N as i32;
+
+    pub fn new(
+        chunks: &'a mut HashMap<ChunkPosition, Chunk<N>>,
+        registry: &'a BlockRegistry,
+        changed: &'a mut HashSet<ChunkPosition>,
+    ) -> Self {
+        Self {
+            chunks,
+            registry,
+            changed,
+            current: None,
+            last_changed: None,
+            add: Default::default(),
+            remove: Default::default(),
+        }
+    }
+
+    /// Queues light sources of a newly inserted chunk and light flowing in from its neighbours
+    pub fn seed_chunk(&mut self, chpos: ChunkPosition) {
+        let origin = chpos.pos * Self::NI;
+
+        // top of the chunk below was lit as if it was open to the sky,
+        // which stays true only under columns this chunk passes sky light through
+        let open_sky = !self.is_loaded(ChunkPosition::new(chpos.pos + IVec3::Y));
+        let below = ChunkPosition::new(chpos.pos - IVec3::Y);
+        if self.is_loaded(below) {
+            for x in 0..Self::NI {
+                for z in 0..Self::NI {
+                    let pos = origin + IVec3::new(x, -1, z);
+                    if self.light(pos).map(|l| l.sky()) != Some(MAX_LIGHT) {
+                        continue;
+                    }
+                    let sky_above = open_sky
+                        || self
+                            .light(origin + IVec3::new(x, Self::NI, z))
+                            .map(|l| l.sky())
+                            == Some(MAX_LIGHT);
+                    let column_open = (0..Self::NI).all(|y| {
+                        self.voxel(origin + IVec3::new(x, y, z))
+                            .is_some_and(|v| self.registry.is_transparent(v))
+                    });
+                    if !sky_above || !column_open {
+                        self.set_light(pos, LightChannel::Sky, 0);
+                        self.remove[LightChannel::Sky.index()].push_back((pos, MAX_LIGHT));
+                    }
+                }
+            }
+        }
+
+        // skip flood filling chunks of open air, common above the ground
+        let sky_above_everywhere = open_sky
+            || (0..Self::NI).all(|x| {
+                (0..Self::NI).all(|z| {
+                    self.light(origin + IVec3::new(x, Self::NI, z))
+                        .is_some_and(|l| l.sky() == MAX_LIGHT)
+                })
+            });
+        let registry = self.registry;
+        let fully_lit = sky_above_everywhere
+            && self
+                .chunk(chpos)
+                .is_some_and(|c| c.is_transparent(registry));
+        if fully_lit {
+            if let Some(chunk) = self.chunk(chpos) {
+                chunk.light_mut().fill(LightLevel::new(MAX_LIGHT, 0));
+            }
+            self.changed.insert(chpos);
+            for dir in Directions::all().into_iter().map(|d| d.to_ivec()) {
+                self.changed.insert(ChunkPosition::new(chpos.pos + dir));
+                self.for_each_face_voxel(origin, dir, false, |this, pos| {
+                    this.add[LightChannel::Sky.index()].push_back(pos)
+                });
+            }
+        }
+
+        let emitters = self.chunk(chpos).map_or_else(Vec::new, |chunk| {
+            chunk
+                .data()
+                .indexed_iter()
+                .filter(|(_, &vox)| registry.get(vox).light_emission > 0)
+                .map(|((x, y, z), _)| origin + IVec3::new(x as i32, y as i32, z as i32))
+                .collect()
+        });
+        for pos in emitters {
+            let emission = self.voxel(pos).map_or(0, |v| self.emission(v));
+            self.set_light(pos, LightChannel::Block, emission);
+            self.add[LightChannel::Block.index()].push_back(pos);
+        }
+
+        if open_sky && !fully_lit {
+            for x in 0..Self::NI {
+                for z in 0..Self::NI {
+                    let pos = origin + IVec3::new(x, Self::NI - 1, z);
+                    if self.voxel(pos).is_some_and(|v| registry.is_transparent(v)) {
+                        self.set_light(pos, LightChannel::Sky, MAX_LIGHT);
+                        self.add[LightChannel::Sky.index()].push_back(pos);
+                    }
+                }
+            }
+        }
+
+        for dir in Directions::all().into_iter().map(|d| d.to_ivec()) {
+            if !self.is_loaded(ChunkPosition::new(chpos.pos + dir)) {
+                continue;
+            }
+            self.for_each_face_voxel(origin, dir, true, |this, pos| {
+                if this.light(pos).is_some_and(|l| l.max() > 0) {
+                    for channel in LightChannel::ALL {
+                        this.add[channel.index()].push_back(pos);
+                    }
+                }
+            });
+        }
+    }
+
+    /// Queues removal of light an unloaded chunk, already taken out of the chunks,
+    /// sent into its loaded neighbours. Their border voxels are relit from their own sources,
+    /// and the chunk below is open to the sky again as when it was loaded without this one
+    pub fn unseed_chunk(&mut self, chpos: ChunkPosition) {
+        let origin = chpos.pos * Self::NI;
+        for dir in Directions::all().into_iter().map(|d| d.to_ivec()) {
+            self.for_each_face_voxel(origin, dir, true, |this, pos| {
+                let Some(light) = this.light(pos) else {
+                    return;
+                };
+                for channel in LightChannel::ALL {
+                    let level = light.get(channel);
+                    if level > 0 {
+                        this.set_light(pos, channel, 0);
+                        this.remove[channel.index()].push_back((pos, level));
+                    }
+                }
+            });
+        }
+
+        let registry = self.registry;
+        for x in 0..Self::NI {
+            for z in 0..Self::NI {
+                let pos = origin + IVec3::new(x, -1, z);
+                if self.voxel(pos).is_some_and(|v| registry.is_transparent(v)) {
+                    self.set_light(pos, LightChannel::Sky, MAX_LIGHT);
+                    self.add[LightChannel::Sky.index()].push_back(pos);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with voxels on the face of the chunk at `origin` looking in `dir`,
+    /// or with voxels of the neighbour touching that face if `outside`
+    fn for_each_face_voxel(
+        &mut self,
+        origin: IVec3,
+        dir: IVec3,
+        outside: bool,
+        mut f: impl FnMut(&mut Self, IVec3),
+    ) {
+        let axis = match dir {
+            d if d.x != 0 => 0,
+            d if d.y != 0 => 1,
+            _ => 2,
+        };
+        let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+        let layer = match (dir[axis] > 0, outside) {
+            (true, true) => Self::NI,
+            (true, false) => Self::NI - 1,
+            (false, true) => -1,
+            (false, false) => 0,
+        };
+        for u in 0..Self::NI {
+            for v in 0..Self::NI {
+                let mut pos = origin;
+                pos[axis] += layer;
+                pos[u_axis] += u;
+                pos[v_axis] += v;
+                f(self, pos);
+            }
+        }
+    }
+
+    /// Queues relighting around a voxel which was changed
+    pub fn update_voxel(&mut self, pos: IVec3) {
+        let (Some(vox), Some(light)) = (self.voxel(pos), self.light(pos)) else {
+            return;
+        };
+        for channel in LightChannel::ALL {
+            let old = light.get(channel);
+            if old > 0 {
+                self.set_light(pos, channel, 0);
+                self.remove[channel.index()].push_back((pos, old));
+            }
+        }
+        let emission = self.emission(vox);
+        if emission > 0 {
+            self.set_light(pos, LightChannel::Block, emission);
+            self.add[LightChannel::Block.index()].push_back(pos);
+        }
+        let (chpos, index) = Self::split(pos);
+        let open_sky = index[1] == N - 1
+            && !self.is_loaded(ChunkPosition::new(chpos.pos + IVec3::Y))
+            && self.registry.is_transparent(vox);
+        if open_sky {
+            self.set_light(pos, LightChannel::Sky, MAX_LIGHT);
+            self.add[LightChannel::Sky.index()].push_back(pos);
+        }
+        // neighbours may now spread into the voxel
+        for dir in Directions::all().into_iter().map(|d| d.to_ivec()) {
+            for channel in LightChannel::ALL {
+                self.add[channel.index()].push_back(pos + dir);
+            }
+        }
+    }
+
+    /// Removes light that lost its source, then spreads light from all queued voxels
+    pub fn propagate(&mut self) {
+        for channel in LightChannel::ALL {
+            self.propagate_removal(channel);
+            self.propagate_addition(channel);
+        }
+    }
+
+    fn propagate_removal(&mut self, channel: LightChannel) {
+        while let Some((pos, level)) = self.remove[channel.index()].pop_front() {
+            for dir in Directions::all().into_iter() {
+                let next = pos + dir.to_ivec();
+                let Some(next_level) = self.light(next).map(|l| l.get(channel)) else {
+                    continue;
+                };
+                let sky_column = channel == LightChannel::Sky
+                    && dir == Directions::DOWN
+                    && level == MAX_LIGHT
+                    && next_level == MAX_LIGHT;
+                if next_level != 0 && (next_level < level || sky_column) {
+                    // light of the neighbour came from the removed voxel
+                    self.set_light(next, channel, 0);
+                    self.remove[channel.index()].push_back((next, next_level));
+
+                    let emission = self.voxel(next).map_or(0, |v| self.emission(v));
+                    if channel == LightChannel::Block && emission > 0 {
+                        self.set_light(next, channel, emission);
+                        self.add[channel.index()].push_back(next);
+                    }
+                } else if next_level >= level {
+                    // an independent source, light it back
+                    self.add[channel.index()].push_back(next);
+                }
+            }
+        }
+    }
+
+    fn propagate_addition(&mut self, channel: LightChannel) {
+        while let Some(pos) = self.add[channel.index()].pop_front() {
+            let Some(level) = self.light(pos).map(|l| l.get(channel)) else {
+                continue;
+            };
+            if level <= 1 {
+                continue;
+            }
+            for dir in Directions::all().into_iter() {
+                let next = pos + dir.to_ivec();
+                let next_level = if channel == LightChannel::Sky
+                    && dir == Directions::DOWN
+                    && level == MAX_LIGHT
+                {
+                    MAX_LIGHT
+                } else {
+                    level - 1
+                };
+
+                let (chpos, index) = Self::split(next);
+                let registry = self.registry;
+                let Some(chunk) = self.chunk(chpos) else {
+                    continue;
+                };
+                if !registry.is_transparent(chunk.data()[index]) {
+                    continue;
+                }
+                let light = &mut chunk.light_mut()[index];
+                if light.get(channel) < next_level {
+                    light.set(channel, next_level);
+                    self.mark_changed(chpos, index);
+                    self.add[channel.index()].push_back(next);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn emission(&self, vox: Voxel) -> u8 {
+        self.registry.get(vox).light_emission.min(MAX_LIGHT)
+    }
+
+    #[inline]
+    fn split(pos: IVec3) -> (ChunkPosition, [usize; 3]) {
+        let chpos = IVec3::new(
+            pos.x.div_euclid(Self::NI),
+            pos.y.div_euclid(Self::NI),
+            pos.z.div_euclid(Self::NI),
+        );
+        let index = pos - chpos * Self::NI;
+        (ChunkPosition::new(chpos), index.to_usize())
+    }
+
+    fn is_loaded(&self, chpos: ChunkPosition) -> bool {
+        matches!(&self.current, Some((pos, _)) if *pos == chpos) || self.chunks.contains_key(&chpos)
+    }
+
+    #[inline]
+    fn chunk(&mut self, chpos: ChunkPosition) -> Option<&mut Chunk<N>> {
+        if !matches!(&self.current, Some((pos, _)) if *pos == chpos) {
+            let chunk = self.chunks.remove(&chpos)?;
+            if let Some((pos, prev)) = self.current.replace((chpos, chunk)) {
+                self.chunks.insert(pos, prev);
+            }
+        }
+        self.current.as_mut().map(|(_, chunk)| chunk)
+    }
+
+    #[inline]
+    fn voxel(&mut self, pos: IVec3) -> Option<Voxel> {
+        let (chpos, index) = Self::split(pos);
+        self.chunk(chpos).map(|c| c.data()[index])
+    }
+
+    #[inline]
+    fn light(&mut self, pos: IVec3) -> Option<LightLevel> {
+        let (chpos, index) = Self::split(pos);
+        self.chunk(chpos).map(|c| c.light()[index])
+    }
+
+    fn set_light(&mut self, pos: IVec3, channel: LightChannel, level: u8) {
+        let (chpos, index) = Self::split(pos);
+        let Some(chunk) = self.chunk(chpos) else {
+            return;
+        };
+        chunk.light_mut()[index].set(channel, level);
+        self.mark_changed(chpos, index);
+    }
+
+    fn mark_changed(&mut self, chpos: ChunkPosition, index: [usize; 3]) {
+        if self.last_changed != Some(chpos) {
+            self.changed.insert(chpos);
+            self.last_changed = Some(chpos);
+        }
+        // faces of the neighbour chunk are lit by border voxels
+        for axis in 0..3 {
+            let mut offset = IVec3::ZERO;
+            if index[axis] == 0 {
+                offset[axis] = -1;
+            } else if index[axis] == N - 1 {
+                offset[axis] = 1;
+            } else {
+                continue;
+            }
+            self.changed.insert(ChunkPosition::new(chpos.pos + offset));
+        }
+    }
+}
+
+impl<'a, const N: usize> Drop for LightPropagator<'a, N> {
+    fn drop(&mut self) {
+        if let Some((pos, chunk)) = self.current.take() {
+            self.chunks.insert(pos, chunk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::block_registry::BlockDescriptor;
+
+    const SMALLCH: usize = 4;
+    const STONE: Voxel = Voxel { id: 1 };
+    const LAMP: Voxel = Voxel { id: 2 };
+
+    fn registry() -> BlockRegistry {
+        let air = BlockRegistry::default().get(Voxel { id: 0 }).clone();
+        let stone = BlockDescriptor {
+            name: "stone".to_owned(),
+            id: STONE.id,
+            solid: true,
+            transparent: false,
+            ..air.clone()
+        };
+        let lamp = BlockDescriptor {
+            name: "lamp".to_owned(),
+            id: LAMP.id,
+            light_emission: 14,
+            ..stone.clone()
+        };
+        BlockRegistry::new(vec![air, stone, lamp]).unwrap()
+    }
+
+    struct TestWorld {
+        chunks: HashMap<ChunkPosition, Chunk<SMALLCH>>,
+        registry: BlockRegistry,
+        changed: HashSet<ChunkPosition>,
+    }
+
+    impl TestWorld {
+        fn new() -> Self {
+            Self {
+                chunks: HashMap::new(),
+                registry: registry(),
+                changed: HashSet::new(),
+            }
+        }
+
+        fn insert(&mut self, pos: IVec3, fill: impl Fn([usize; 3]) -> Voxel) {
+            let mut chunk = Chunk::new();
+            chunk
+                .data_mut()
+                .indexed_iter_mut()
+                .for_each(|((x, y, z), v)| *v = fill([x, y, z]));
+            let pos = ChunkPosition::new(pos);
+            self.chunks.insert(pos, chunk);
+
+            let mut propagator =
+                LightPropagator::new(&mut self.chunks, &self.registry, &mut self.changed);
+            propagator.seed_chunk(pos);
+            propagator.propagate();
+        }
+
+        fn set(&mut self, pos: IVec3, vox: Voxel) {
+            let (chpos, index) = LightPropagator::<SMALLCH>::split(pos);
+            self.chunks.get_mut(&chpos).unwrap().data_mut()[index] = vox;
+
+            let mut propagator =
+                LightPropagator::new(&mut self.chunks, &self.registry, &mut self.changed);
+            propagator.update_voxel(pos);
+            propagator.propagate();
+        }
+
+        fn light(&self, pos: IVec3) -> LightLevel {
+            let (chpos, index) = LightPropagator::<SMALLCH>::split(pos);
+            self.chunks[&chpos].light()[index]
+        }
+    }
+
+    /// Stone everywhere except air at `hole`
+    fn stone_with_hole(hole: [usize; 3]) -> impl Fn([usize; 3]) -> Voxel {
+        move |ind| if ind == hole { Voxel::default() } else { STONE }
+    }
+
+    #[test]
+    fn open_sky_doesnt_fade_downwards() {
+        let mut world = TestWorld::new();
+        world.insert(IVec3::ZERO, |_| Voxel::default());
+
+        assert!(world.chunks[&ChunkPosition::default()]
+            .light()
+            .iter()
+            .all(|l| l.sky() == MAX_LIGHT));
+    }
+
+    #[test]
+    fn sky_light_through_hole_in_roof() {
+        let mut world = TestWorld::new();
+        world.insert(IVec3::ZERO, |[x, y, z]| {
+            if y == 3 && (x, z) != (0, 0) {
+                STONE
+            } else {
+                Voxel::default()
+            }
+        });
+
+        assert_eq!(world.light(IVec3::new(0, 0, 0)).sky(), MAX_LIGHT);
+        assert_eq!(world.light(IVec3::new(1, 0, 0)).sky(), MAX_LIGHT - 1);
+        assert_eq!(world.light(IVec3::new(3, 2, 3)).sky(), MAX_LIGHT - 6);
+        assert_eq!(world.light(IVec3::new(1, 3, 0)).sky(), 0);
+    }
+
+    #[test]
+    fn block_light_crosses_chunk_border() {
+        let mut world = TestWorld::new();
+        // a dark tunnel along x through two chunks, roofed by chunks above
+        let tunnel = |[_, y, z]: [usize; 3]| {
+            if y == 1 && z == 1 {
+                Voxel::default()
+            } else {
+                STONE
+            }
+        };
+        for pos in [IVec3::new(0, 1, 0), IVec3::new(1, 1, 0)] {
+            world.insert(pos, |_| STONE);
+        }
+        world.insert(IVec3::ZERO, tunnel);
+        world.insert(IVec3::X, tunnel);
+
+        world.set(IVec3::new(1, 1, 1), LAMP);
+
+        assert_eq!(world.light(IVec3::new(1, 1, 1)).block(), 14);
+        assert_eq!(world.light(IVec3::new(3, 1, 1)).block(), 12);
+        assert_eq!(world.light(IVec3::new(6, 1, 1)).block(), 9);
+        assert_eq!(world.light(IVec3::new(6, 1, 1)).sky(), 0);
+        assert!(world.changed.contains(&ChunkPosition::new(IVec3::X)));
+
+        world.set(IVec3::new(1, 1, 1), Voxel::default());
+
+        assert!(world
+            .chunks
+            .values()
+            .all(|c| c.light().iter().all(|l| l.block() == 0)));
+    }
+
+    #[test]
+    fn placing_roof_removes_sky_light() {
+        let mut world = TestWorld::new();
+        world.insert(IVec3::ZERO, stone_with_hole([2, 3, 2]));
+
+        assert_eq!(world.light(IVec3::new(2, 3, 2)).sky(), MAX_LIGHT);
+
+        world.set(IVec3::new(2, 3, 2), STONE);
+
+        assert_eq!(world.light(IVec3::new(2, 3, 2)).sky(), 0);
+    }
+
+    #[test]
+    fn breaking_block_lets_light_in() {
+        let mut world = TestWorld::new();
+        world.insert(
+            IVec3::ZERO,
+            |[_, y, _]| {
+                if y >= 2 {
+                    STONE
+                } else {
+                    Voxel::default()
+                }
+            },
+        );
+        assert_eq!(world.light(IVec3::new(1, 1, 1)).sky(), 0);
+
+        world.set(IVec3::new(1, 3, 1), Voxel::default());
+        world.set(IVec3::new(1, 2, 1), Voxel::default());
+
+        assert_eq!(world.light(IVec3::new(1, 0, 1)).sky(), MAX_LIGHT);
+        assert_eq!(world.light(IVec3::new(3, 1, 1)).sky(), MAX_LIGHT - 2);
+    }
+
+    #[test]
+    fn loading_chunk_above_removes_assumed_sky() {
+        let mut world = TestWorld::new();
+        world.insert(IVec3::ZERO, |_| Voxel::default());
+        assert_eq!(world.light(IVec3::new(1, 0, 1)).sky(), MAX_LIGHT);
+
+        world.insert(IVec3::Y, |_| STONE);
+
+        assert!(world.chunks[&ChunkPosition::default()]
+            .light()
+            .iter()
+            .all(|l| l.sky() == 0));
+    }
+
+    #[test]
+    fn open_air_chunk_lights_neighbour() {
+        let mut world = TestWorld::new();
+        world.insert(IVec3::Y, |_| STONE);
+        world.insert(IVec3::ZERO, |[_, y, z]| {
+            if y == 1 && z == 1 {
+                Voxel::default()
+            } else {
+                STONE
+            }
+        });
+        assert_eq!(world.light(IVec3::new(3, 1, 1)).sky(), 0);
+
+        world.insert(IVec3::X, |_| Voxel::default());
+
+        assert_eq!(world.light(IVec3::new(4, 0, 0)).sky(), MAX_LIGHT);
+        assert_eq!(world.light(IVec3::new(3, 1, 1)).sky(), MAX_LIGHT - 1);
+        assert_eq!(world.light(IVec3::new(0, 1, 1)).sky(), MAX_LIGHT - 4);
+    }
+}
@@ -0,0 +1,87 @@
+use bevy::prelude::IVec3;
+
+use crate::directions::Directions;
+
+use super::chunk::ChunkPosition;
+
+/// Coarsest level of detail, merging 2^`MAX_LOD` voxels per side
+pub const MAX_LOD: u8 = 3;
+
+/// Picks levels of detail of chunks by their distance to the nearest render loader
+#[derive(Debug, Clone, Default)]
+pub struct LodLevels {
+    /// Chunk positions of render loaders
+    loaders: Vec<IVec3>,
+    /// Distances in chunks each coarser level starts at, ascending
+    distances: Vec<usize>,
+}
+
+impl LodLevels {
+    pub fn new(loaders: Vec<IVec3>, distances: Vec<usize>) -> Self {
+        Self { loaders, distances }
+    }
+
+    /// Full detail without loaders, otherwise the number of distances the nearest loader is at or beyond
+    pub fn level(&self, pos: &ChunkPosition) -> u8 {
+        let Some(distance) = self
+            .loaders
+            .iter()
+            .map(|loader| (*loader - pos.pos).as_vec3().length())
+            .min_by(f32::total_cmp)
+        else {
+            return 0;
+        };
+        let level = self
+            .distances
+            .iter()
+            .take_while(|start| distance >= **start as f32)
+            .count();
+        level.min(MAX_LOD as usize) as u8
+    }
+
+    /// Sides of the chunk whose neighbours are at a different level
+    pub fn seams(&self, pos: &ChunkPosition) -> Directions {
+        let level = self.level(pos);
+        Directions::all()
+            .into_iter()
+            .filter(|dir| self.level(&ChunkPosition::new(pos.pos + dir.to_ivec())) != level)
+            .fold(Directions::empty(), |seams, dir| seams | dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest(
+        x,
+        level,
+        case(0, 0),
+        case(3, 0),
+        case(4, 1),
+        case(9, 2),
+        case(16, 3),
+        case(100, 3)
+    )]
+    fn level_by_distance(x: i32, level: u8) {
+        let lods = LodLevels::new(vec![IVec3::ZERO], vec![4, 8, 16, 32]);
+
+        assert_eq!(lods.level(&ChunkPosition::new(IVec3::X * x)), level);
+    }
+
+    #[test]
+    fn seams_towards_other_levels() {
+        let lods = LodLevels::new(vec![IVec3::ZERO], vec![4]);
+
+        assert_eq!(
+            lods.seams(&ChunkPosition::new(IVec3::X * 3)),
+            Directions::EAST
+        );
+        assert_eq!(
+            lods.seams(&ChunkPosition::new(IVec3::X * 4)),
+            Directions::WEST
+        );
+        assert!(lods.seams(&ChunkPosition::new(IVec3::Y)).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use bevy::{prelude::IVec3, render::primitives::Frustum};
+
+use super::{chunk::ChunkPosition, visibility::chunk_in_frustum};
+
+/// How urgently a dirty chunk should be meshed, chunks with lower values are meshed first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Urgency {
+    /// Changed by voxel edits, the player waits for them
+    Edited,
+    /// Inside the view frustum of a camera
+    Visible,
+    Hidden,
+}
+
+/// Orders dirty chunks for meshing by urgency, then by distance to the nearest render loader
+#[derive(Debug, Clone, Default)]
+pub struct MeshPriority<const N: usize> {
+    /// Chunk positions of render loaders
+    loaders: Vec<IVec3>,
+    frusta: Vec<Frustum>,
+}
+
+impl<const N: usize> MeshPriority<N> {
+    pub fn new(loaders: Vec<IVec3>, frusta: Vec<Frustum>) -> Self {
+        Self { loaders, frusta }
+    }
+
+    pub fn is_visible(&self, pos: &ChunkPosition) -> bool {
+        self.frusta
+            .iter()
+            .any(|frustum| chunk_in_frustum::<N>(frustum, pos))
+    }
+
+    /// Squared distance in chunks to the nearest loader, 0 without loaders
+    pub fn distance_squared(&self, pos: &ChunkPosition) -> i32 {
+        self.loaders
+            .iter()
+            .map(|loader| {
+                let offset = *loader - pos.pos;
+                offset.dot(offset)
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    pub fn key(&self, pos: &ChunkPosition, edited: bool) -> (Urgency, i32) {
+        let urgency = if edited {
+            Urgency::Edited
+        } else if self.is_visible(pos) {
+            Urgency::Visible
+        } else {
+            Urgency::Hidden
+        };
+        (urgency, self.distance_squared(pos))
+    }
+
+    /// Up to `count` of the most urgent chunks, in order, given whether each was edited
+    pub fn most_urgent(
+        &self,
+        candidates: impl IntoIterator<Item = (ChunkPosition, bool)>,
+        count: usize,
+    ) -> Vec<ChunkPosition> {
+        let mut keyed = candidates
+            .into_iter()
+            .map(|(pos, edited)| (self.key(&pos, edited), pos))
+            .collect::<Vec<_>>();
+        if count < keyed.len() {
+            keyed.select_nth_unstable_by_key(count, |(key, _)| *key);
+            keyed.truncate(count);
+        }
+        keyed.sort_unstable_by_key(|(key, _)| *key);
+        keyed.into_iter().map(|(_, pos)| pos).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::{Mat4, Transform, Vec3};
+    use rstest::rstest;
+
+    const N: usize = 16;
+
+    fn looking_north() -> Frustum {
+        let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1., 0.1, 1000.);
+        let view = Transform::from_xyz(8., 8., 8.).looking_to(Vec3::NEG_Z, Vec3::Y);
+        Frustum::from_view_projection(&(projection * view.compute_matrix().inverse()))
+    }
+
+    #[rstest(
+        pos,
+        visible,
+        case(IVec3::new(0, 0, -3), true),
+        case(IVec3::new(0, 0, 0), true),
+        case(IVec3::new(0, 0, 3), false),
+        case(IVec3::new(5, 0, 0), false)
+    )]
+    fn chunks_in_frustum_visible(pos: IVec3, visible: bool) {
+        let priority = MeshPriority::<N>::new(vec![IVec3::ZERO], vec![looking_north()]);
+
+        assert_eq!(priority.is_visible(&ChunkPosition::new(pos)), visible);
+    }
+
+    #[test]
+    fn edited_then_visible_then_nearest() {
+        let priority = MeshPriority::<N>::new(vec![IVec3::ZERO], vec![looking_north()]);
+        let chunk = |x, z| ChunkPosition::new(IVec3::new(x, 0, z));
+        let candidates = [
+            (chunk(0, 2), false),
+            (chunk(0, -6), false),
+            (chunk(0, 1), false),
+            (chunk(0, 9), true),
+            (chunk(0, -2), false),
+        ];
+
+        assert_eq!(
+            priority.most_urgent(candidates, 10),
+            vec![
+                chunk(0, 9),
+                chunk(0, -2),
+                chunk(0, -6),
+                chunk(0, 1),
+                chunk(0, 2)
+            ]
+        );
+        assert_eq!(
+            priority.most_urgent(candidates, 2),
+            vec![chunk(0, 9), chunk(0, -2)]
+        );
+    }
+
+    #[test]
+    fn nearest_loader_counts() {
+        let priority = MeshPriority::<N>::new(vec![IVec3::ZERO, IVec3::X * 10], Vec::new());
+
+        assert_eq!(
+            priority.distance_squared(&ChunkPosition::new(IVec3::X * 8)),
+            4
+        );
+    }
+}
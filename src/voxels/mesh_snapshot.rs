@@ -0,0 +1,424 @@
+use std::sync::Arc;
+
+use bevy::prelude::{IVec3, Vec3};
+use ndarray::Array3;
+
+use crate::{
+    core::{ConvertVecExtension, VecExtensions},
+    directions::Directions,
+};
+
+use super::{
+    block_registry::{BlockModel, BlockRegistry},
+    chunk::{Chunk, ChunkPosition},
+    chunk_mesh::{ChunkMeshData, ChunkMeshes, MeshingMode},
+    light::LightLevel,
+    visibility::FaceConnections,
+    voxel::Voxel,
+};
+
+/// Copy of everything meshing a chunk reads: its voxels and light
+/// and the layer of voxels around it from its neighbours, so it can be meshed off the world
+#[derive(Debug, Clone)]
+pub struct MeshSnapshot<const N: usize> {
+    registry: Arc<BlockRegistry>,
+    /// Revision of the chunk when the snapshot was taken
+    revision: u64,
+    /// Indexed by position relative to the chunk plus one, `None` in unloaded diagonal neighbours
+    voxels: Array3<Option<Voxel>>,
+    light: Array3<LightLevel>,
+    /// Voxels along each side of the chunk, less than `N` when downsampled
+    size: usize,
+    /// Side of a voxel in world voxels
+    scale: usize,
+    /// Sides whose border faces are meshed even if covered, see `with_lod`
+    seams: Directions,
+}
+
+impl<const N: usize> MeshSnapshot<N> {
+    const NI: i32 = N as i32;
+
+    /// Copies the chunk at `pos` and the layer around it.
+    /// `None` if the chunk or any of its six face neighbours isn't loaded
+    pub fn new<'a>(
+        pos: &ChunkPosition,
+        chunk_at: impl Fn(&ChunkPosition) -> Option<&'a Chunk<N>>,
+        registry: Arc<BlockRegistry>,
+    ) -> Option<Self> {
+        let chunk = chunk_at(pos)?;
+        for dir in Directions::all() {
+            chunk_at(&ChunkPosition::new(pos.pos + dir.to_ivec()))?;
+        }
+
+        let mut voxels = Array3::from_elem([N + 2; 3], None);
+        let mut light = Array3::default([N + 2; 3]);
+        for x in -1..=Self::NI {
+            for y in -1..=Self::NI {
+                for z in -1..=Self::NI {
+                    let rel = IVec3::new(x, y, z);
+                    let (source, index) = match Chunk::<N>::chunk_voxel_index_wrap(&rel) {
+                        Some(index) => {
+                            let offset = IVec3::new(
+                                x.div_euclid(Self::NI),
+                                y.div_euclid(Self::NI),
+                                z.div_euclid(Self::NI),
+                            );
+                            (chunk_at(&ChunkPosition::new(pos.pos + offset)), index)
+                        }
+                        None => (Some(chunk), rel),
+                    };
+                    if let Some(source) = source {
+                        let padded = (rel + 1).to_usize();
+                        voxels[padded] = Some(source.data()[index.to_usize()]);
+                        light[padded] = source.light()[index.to_usize()];
+                    }
+                }
+            }
+        }
+
+        Some(Self {
+            registry,
+            revision: chunk.revision(),
+            voxels,
+            light,
+            size: N,
+            scale: 1,
+            seams: Directions::empty(),
+        })
+    }
+
+    /// Downsamples the snapshot to level of detail `level`, merging `2^level` voxels per side into one:
+    /// the most common cube among them if cubes make up at least half, air otherwise.
+    /// Border faces on `seams` sides are meshed even if covered,
+    /// closing cracks towards neighbours meshed at a different level.
+    /// Cross blocks are dropped, they count as air
+    pub fn with_lod(self, level: u8, seams: Directions) -> Self {
+        let scale = 1 << level;
+        debug_assert!(self.scale == 1 && N.is_multiple_of(scale));
+        if scale == 1 {
+            return Self { seams, ..self };
+        }
+
+        let size = N / scale;
+        // voxels merged into a voxel of the downsampled snapshot, the layer around it is one voxel thick
+        let merged = |coarse: i32| match coarse {
+            c if c < 0 => -1..=-1,
+            c if c >= size as i32 => Self::NI..=Self::NI,
+            c => c * scale as i32..=(c + 1) * scale as i32 - 1,
+        };
+        let mut voxels = Array3::from_elem([size + 2; 3], None);
+        let mut light = Array3::default([size + 2; 3]);
+        let mut cubes: Vec<(Voxel, usize)> = Vec::new();
+        for x in -1..=size as i32 {
+            for y in -1..=size as i32 {
+                for z in -1..=size as i32 {
+                    cubes.clear();
+                    let mut loaded = 0;
+                    let (mut sky, mut block) = (0, 0);
+                    for fx in merged(x) {
+                        for fy in merged(y) {
+                            for fz in merged(z) {
+                                let fine = IVec3::new(fx, fy, fz);
+                                let Some(vox) = self.voxel_relative(fine) else {
+                                    continue;
+                                };
+                                loaded += 1;
+                                let fine_light = self.light[(fine + 1).to_usize()];
+                                sky = fine_light.sky().max(sky);
+                                block = fine_light.block().max(block);
+                                if !self.is_cube_rendered(vox) {
+                                    continue;
+                                }
+                                match cubes.iter_mut().find(|(cube, _)| *cube == vox) {
+                                    Some((_, count)) => *count += 1,
+                                    None => cubes.push((vox, 1)),
+                                }
+                            }
+                        }
+                    }
+                    if loaded == 0 {
+                        continue;
+                    }
+
+                    let padded = IVec3::new(x + 1, y + 1, z + 1).to_usize();
+                    let total = cubes.iter().map(|(_, count)| count).sum::<usize>();
+                    voxels[padded] = Some(if total * 2 >= loaded {
+                        cubes
+                            .iter()
+                            .max_by_key(|(vox, count)| (*count, vox.id))
+                            .unwrap()
+                            .0
+                    } else {
+                        Voxel::default()
+                    });
+                    light[padded] = LightLevel::new(sky, block);
+                }
+            }
+        }
+
+        Self {
+            voxels,
+            light,
+            size,
+            scale,
+            seams,
+            ..self
+        }
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Faces of the chunk connected through transparent voxels, for cave culling
+    pub fn face_connections(&self) -> FaceConnections {
+        FaceConnections::from_transparent::<N>(|pos| self.registry.is_transparent(self.voxel(pos)))
+    }
+
+    pub fn mesh_with(&self, mode: MeshingMode) -> ChunkMeshes {
+        match mode {
+            MeshingMode::Naive => self.mesh(),
+            MeshingMode::Greedy => self.mesh_greedy(),
+        }
+    }
+
+    pub fn mesh(&self) -> ChunkMeshes {
+        let onef: Vec3 = [1., 1., 1.].into();
+        let size = self.size as i32;
+
+        let mut chunk_mesh = ChunkMeshes::new();
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let pos: IVec3 = [x, y, z].into();
+                    let vox = self.voxel(pos);
+                    if !self.is_cube_rendered(vox) {
+                        // if current voxel is invisible
+                        continue;
+                    }
+                    // if current voxel is visible
+                    for dir in Directions::all().into_iter() {
+                        if let Some((adj_light, ao)) = self.visible_face(pos, dir, vox) {
+                            // if adjacent voxel is transparent
+                            let convert_vec: Vec3 = pos.convert_vec();
+                            let part = self.registry.is_translucent(vox);
+                            chunk_mesh.part_mut(part).insert_quad(
+                                convert_vec + onef / 2.,
+                                dir,
+                                self.registry.texture_layer(vox, dir),
+                                adj_light,
+                                ao,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        self.mesh_cross_blocks(&mut chunk_mesh.opaque);
+        chunk_mesh.scale(self.scale as f32);
+
+        chunk_mesh
+    }
+
+    /// Meshes the chunk merging coplanar equally lit and occluded faces of the same voxel id into maximal rectangles
+    pub fn mesh_greedy(&self) -> ChunkMeshes {
+        let n = self.size;
+        let mut chunk_mesh = ChunkMeshes::new();
+        let mut mask: Vec<Option<(u16, LightLevel, [u8; 4])>> = vec![None; n * n];
+        for dir in Directions::all().into_iter() {
+            // axis along the face normal and two axes spanning the face plane
+            let normal_axis = match dir.to_ivec() {
+                v if v.x != 0 => 0,
+                v if v.y != 0 => 1,
+                _ => 2,
+            };
+            let u_axis = (normal_axis + 1) % 3;
+            let v_axis = (normal_axis + 2) % 3;
+
+            for layer in 0..n as i32 {
+                for v in 0..n {
+                    for u in 0..n {
+                        let mut pos = IVec3::ZERO;
+                        pos[normal_axis] = layer;
+                        pos[u_axis] = u as i32;
+                        pos[v_axis] = v as i32;
+
+                        let vox = self.voxel(pos);
+                        mask[u + v * n] = if self.is_cube_rendered(vox) {
+                            self.visible_face(pos, dir, vox)
+                                .map(|(adj_light, ao)| (vox.id, adj_light, ao))
+                        } else {
+                            None
+                        };
+                    }
+                }
+
+                for v in 0..n {
+                    let mut u = 0;
+                    while u < n {
+                        let Some(face) = mask[u + v * n] else {
+                            u += 1;
+                            continue;
+                        };
+
+                        let mut width = 1;
+                        while u + width < n && mask[u + width + v * n] == Some(face) {
+                            width += 1;
+                        }
+                        let mut height = 1;
+                        while v + height < n
+                            && (u..u + width).all(|k| mask[k + (v + height) * n] == Some(face))
+                        {
+                            height += 1;
+                        }
+                        for dv in v..v + height {
+                            mask[u + dv * n..u + width + dv * n].fill(None);
+                        }
+
+                        let mut center = Vec3::ZERO;
+                        center[normal_axis] = layer as f32 + 0.5;
+                        center[u_axis] = u as f32 + width as f32 / 2.;
+                        center[v_axis] = v as f32 + height as f32 / 2.;
+                        let mut size = Vec3::ONE;
+                        size[u_axis] = width as f32;
+                        size[v_axis] = height as f32;
+                        let (id, light, ao) = face;
+                        let part = self.registry.is_translucent(Voxel { id });
+                        chunk_mesh.part_mut(part).insert_rect(
+                            center,
+                            dir,
+                            size,
+                            self.registry.texture_layer(Voxel { id }, dir),
+                            light,
+                            ao,
+                        );
+
+                        u += width;
+                    }
+                }
+            }
+        }
+
+        self.mesh_cross_blocks(&mut chunk_mesh.opaque);
+        chunk_mesh.scale(self.scale as f32);
+
+        chunk_mesh
+    }
+
+    /// Voxel of the chunk or of the layer around it
+    #[inline]
+    fn voxel_relative(&self, pos: IVec3) -> Option<Voxel> {
+        self.voxels[(pos + 1).to_usize()]
+    }
+
+    /// Voxel of the chunk or of its face neighbours, which are always loaded
+    #[inline]
+    fn voxel(&self, pos: IVec3) -> Voxel {
+        self.voxel_relative(pos).unwrap_or_default()
+    }
+
+    #[inline]
+    fn is_cube_rendered(&self, vox: Voxel) -> bool {
+        let block = self.registry.get(vox);
+        block.is_rendered() && block.model == BlockModel::Cube
+    }
+
+    fn mesh_cross_blocks(&self, chunk_mesh: &mut ChunkMeshData) {
+        let size = self.size as i32;
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let pos = IVec3::new(x, y, z);
+                    let vox = self.voxel(pos);
+                    let block = self.registry.get(vox);
+                    if block.is_rendered() && block.model == BlockModel::Cross {
+                        chunk_mesh.insert_cross(
+                            pos.as_vec3() + Vec3::splat(0.5),
+                            self.registry.texture_layer(vox, Directions::NORTH),
+                            self.light[(pos + 1).to_usize()],
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether the face of `vox` can be seen through the adjacent voxel
+    fn is_face_visible(&self, vox: Voxel, adj_vox: Voxel) -> bool {
+        !self.registry.same_block(vox, adj_vox) && self.registry.is_transparent(adj_vox)
+    }
+
+    /// Light in front of the face of `vox` at `pos` looking in `dir` and its ambient occlusion,
+    /// `None` if the face is hidden. Covered faces on seams aren't occluded
+    /// and are lit by the brightest of the voxel itself, the cell above it and the covering cell
+    pub(crate) fn visible_face(
+        &self,
+        pos: IVec3,
+        dir: Directions,
+        vox: Voxel,
+    ) -> Option<(LightLevel, [u8; 4])> {
+        let (adj_vox, adj_light) = self.adjacent(pos, dir);
+        if self.is_face_visible(vox, adj_vox) {
+            return Some((adj_light, self.face_ao(pos, dir)));
+        }
+        let adj = pos + dir.to_ivec();
+        let on_seam = self.seams.contains(dir)
+            && (adj.cmplt(IVec3::ZERO).any() || adj.cmpge(IVec3::splat(self.size as i32)).any());
+        if !on_seam {
+            return None;
+        }
+        // the covering voxel is unlit, a downsampled voxel keeps the light of the transparent voxels merged into it
+        let light = [pos, pos + IVec3::Y]
+            .map(|pos| self.light[(pos + 1).to_usize()])
+            .into_iter()
+            .fold(adj_light, |light, other| {
+                LightLevel::new(
+                    light.sky().max(other.sky()),
+                    light.block().max(other.block()),
+                )
+            });
+        Some((light, [3; 4]))
+    }
+
+    /// Returns the voxel adjacent to `pos` in direction `dir` and its light
+    fn adjacent(&self, pos: IVec3, dir: Directions) -> (Voxel, LightLevel) {
+        let adj = pos + dir.to_ivec();
+        (self.voxel(adj), self.light[(adj + 1).to_usize()])
+    }
+
+    /// Ambient occlusion level of every vertex of the face of `pos` looking in `dir`,
+    /// in `ChunkMeshData::quad_vertices` order.
+    /// 3 is unoccluded, 0 is a vertex between two occluding sides
+    pub(crate) fn face_ao(&self, pos: IVec3, dir: Directions) -> [u8; 4] {
+        let front = pos + dir.to_ivec();
+        let occludes = |pos: IVec3| {
+            self.voxel_relative(pos)
+                .is_some_and(|vox| !self.registry.is_transparent(vox)) as u8
+        };
+
+        ChunkMeshData::quad_vertices(dir).map(|vert| {
+            // offsets towards the vertex along both axes of the face plane
+            let mut side1 = IVec3::ZERO;
+            let mut side2 = IVec3::ZERO;
+            for axis in 0..3 {
+                if front[axis] != pos[axis] {
+                    continue;
+                }
+                let offset = if side1 == IVec3::ZERO {
+                    &mut side1
+                } else {
+                    &mut side2
+                };
+                offset[axis] = vert[axis].signum() as i32;
+            }
+
+            let side1_occludes = occludes(front + side1);
+            let side2_occludes = occludes(front + side2);
+            if side1_occludes == 1 && side2_occludes == 1 {
+                0
+            } else {
+                3 - side1_occludes - side2_occludes - occludes(front + side1 + side2)
+            }
+        })
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use bevy::{
+    prelude::{Mesh, Resource},
+    tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+
+use crate::directions::Directions;
+
+use super::{
+    chunk::ChunkPosition, chunk_mesh::MeshingMode, lod::LodLevels, visibility::FaceConnections,
+    world::VoxelWorldProcedural,
+};
+
+/// Meshes of a chunk, `None` if they have nothing to render, and connections of its faces
+pub struct MeshedChunk {
+    pub opaque: Option<Mesh>,
+    pub translucent: Option<Mesh>,
+    pub connections: FaceConnections,
+}
+
+/// Chunk meshes being built from snapshots on the `AsyncComputeTaskPool`
+#[derive(Resource, Default)]
+pub struct MeshingTasks {
+    /// Tasks with the chunk revision their snapshot was taken at
+    in_flight: HashMap<ChunkPosition, (u64, Task<MeshedChunk>)>,
+    /// Level of detail and seams chunks were last submitted with
+    levels: HashMap<ChunkPosition, (u8, Directions)>,
+}
+
+impl MeshingTasks {
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn is_in_flight(&self, pos: &ChunkPosition) -> bool {
+        self.in_flight.contains_key(pos)
+    }
+
+    /// Snapshots the chunk and starts meshing it at its level of detail.
+    /// Returns false if the chunk or any of its face neighbours isn't loaded
+    pub fn submit(
+        &mut self,
+        pos: ChunkPosition,
+        vox_world: &VoxelWorldProcedural,
+        mode: MeshingMode,
+        lods: &LodLevels,
+    ) -> bool {
+        let Some(snapshot) = vox_world.mesh_snapshot(&pos) else {
+            return false;
+        };
+        let revision = snapshot.revision();
+        let (level, seams) = (lods.level(&pos), lods.seams(&pos));
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let connections = snapshot.face_connections();
+            let meshes = snapshot.with_lod(level, seams).mesh_with(mode);
+            MeshedChunk {
+                opaque: meshes.opaque.build_mesh(),
+                translucent: meshes.translucent.build_mesh(),
+                connections,
+            }
+        });
+        self.in_flight.insert(pos, (revision, task));
+        self.levels.insert(pos, (level, seams));
+        true
+    }
+
+    /// Marks dirty chunks submitted with a different level of detail or seams than `lods` picks now
+    /// and forgets unloaded ones
+    pub fn mark_outdated_levels(&mut self, vox_world: &VoxelWorldProcedural, lods: &LodLevels) {
+        let dirty = vox_world.dirty().pin();
+        self.levels.retain(|pos, submitted| {
+            if vox_world.get_chunk_at(pos).is_none() {
+                return false;
+            }
+            if *submitted != (lods.level(pos), lods.seams(pos)) {
+                dirty.insert(*pos);
+            }
+            true
+        });
+    }
+
+    /// Removes finished tasks and returns meshes of chunks which weren't changed since submission.
+    /// Chunks changed in the meantime are marked dirty to be meshed again, unloaded ones are skipped
+    pub fn collect_finished(
+        &mut self,
+        vox_world: &VoxelWorldProcedural,
+    ) -> Vec<(ChunkPosition, MeshedChunk)> {
+        let finished = self
+            .in_flight
+            .iter()
+            .filter(|(_, (_, task))| task.is_finished())
+            .map(|(pos, _)| *pos)
+            .collect::<Vec<_>>();
+
+        let mut meshes = Vec::new();
+        for pos in finished {
+            let (revision, task) = self.in_flight.remove(&pos).unwrap();
+            // doesn't block, the task is finished
+            let meshed = future::block_on(task);
+            match vox_world.get_chunk_at(&pos) {
+                Some(chunk) if chunk.revision() == revision => meshes.push((pos, meshed)),
+                // applied changes marked the chunk edited already if a player made them
+                Some(_) => {
+                    vox_world.dirty().pin().insert(pos);
+                }
+                None => {}
+            }
+        }
+        meshes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{generation_stages, terrain_generation::ProceduralGenerator, voxel::Voxel};
+    use bevy::{prelude::IVec3, tasks::TaskPool};
+
+    fn world_around_origin() -> VoxelWorldProcedural {
+        let mut world =
+            VoxelWorldProcedural::new(ProceduralGenerator::default(), Default::default());
+        for pos in generation_stages::within(ChunkPosition::default(), 1) {
+            let chunk = world.gen_chunk(&pos);
+            world.insert_at(&pos, chunk);
+        }
+        world
+    }
+
+    fn wait_finished(
+        tasks: &mut MeshingTasks,
+        world: &VoxelWorldProcedural,
+    ) -> Vec<(ChunkPosition, MeshedChunk)> {
+        let mut meshes = Vec::new();
+        while tasks.in_flight() > 0 {
+            meshes.extend(tasks.collect_finished(world));
+            std::thread::yield_now();
+        }
+        meshes
+    }
+
+    #[test]
+    fn meshes_unchanged_chunk() {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let world = world_around_origin();
+        let mut tasks = MeshingTasks::default();
+        let origin = ChunkPosition::default();
+
+        assert!(tasks.submit(origin, &world, MeshingMode::Greedy, &LodLevels::default()));
+        assert!(!tasks.submit(
+            ChunkPosition::new(IVec3::X * 5),
+            &world,
+            MeshingMode::Greedy,
+            &LodLevels::default()
+        ));
+        let meshes = wait_finished(&mut tasks, &world);
+
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].0, origin);
+        assert!(!world.dirty().pin().contains(&origin));
+    }
+
+    #[test]
+    fn stale_mesh_rejected_and_requeued() {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let mut world = world_around_origin();
+        let mut tasks = MeshingTasks::default();
+        let origin = ChunkPosition::default();
+
+        assert!(tasks.submit(origin, &world, MeshingMode::Greedy, &LodLevels::default()));
+        world.set_voxel_at(&origin, &[1, 1, 1], Voxel { id: 1 });
+        world.apply_voxel_changes();
+        world.dirty().pin().clear();
+        let meshes = wait_finished(&mut tasks, &world);
+
+        assert!(meshes.is_empty());
+        assert!(world.dirty().pin().contains(&origin));
+        assert!(!world.edited().pin().contains(&origin));
+    }
+
+    #[test]
+    fn changed_level_marks_dirty() {
+        AsyncComputeTaskPool::init(TaskPool::default);
+        let world = world_around_origin();
+        let mut tasks = MeshingTasks::default();
+        let origin = ChunkPosition::default();
+        let near = LodLevels::new(vec![IVec3::ZERO], vec![4]);
+
+        assert!(tasks.submit(origin, &world, MeshingMode::Greedy, &near));
+        wait_finished(&mut tasks, &world);
+        tasks.mark_outdated_levels(&world, &near);
+        assert!(!world.dirty().pin().contains(&origin));
+
+        let far = LodLevels::new(vec![IVec3::X * 10], vec![4]);
+        tasks.mark_outdated_levels(&world, &far);
+        assert!(world.dirty().pin().contains(&origin));
+    }
+}
@@ -0,0 +1,300 @@
+//! Palette compressed chunk storage, holding voxels only, no light.
+//! `VoxelWorld` keeps dense `Chunk`s, this is only used by the `chunk_storage` benchmark
+
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+};
+
+use ndarray::Array3;
+
+use super::{chunk::Chunk, voxel::Voxel};
+
+const WORD_BITS: u32 = u64::BITS;
+
+/// Chunk storage keeping a palette of distinct voxels and a bit-packed palette index per voxel.
+/// Uniform chunks keep a single palette entry and no indices.
+#[derive(Debug, Clone)]
+pub struct PaletteChunk<const N: usize> {
+    palette: Vec<Voxel>,
+    /// Bits per packed index, 0 for uniform chunks
+    bits: u32,
+    /// Indices in `Array3` standard order, never straddling word boundaries
+    words: Vec<u64>,
+}
+
+impl<const N: usize> PaletteChunk<N> {
+    const VOLUME: usize = N * N * N;
+
+    pub fn new() -> Self {
+        Self::uniform(Voxel::default())
+    }
+
+    pub fn uniform(voxel: Voxel) -> Self {
+        Self {
+            palette: vec![voxel],
+            bits: 0,
+            words: Vec::new(),
+        }
+    }
+
+    pub fn from_dense(data: &Array3<Voxel>) -> Self {
+        assert_eq!(data.dim(), (N, N, N), "chunk data has wrong dimensions");
+
+        let mut palette = Vec::new();
+        let mut lookup = HashMap::new();
+        let indices = data
+            .iter()
+            .map(|vox| {
+                *lookup.entry(vox.id).or_insert_with(|| {
+                    palette.push(*vox);
+                    palette.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let bits = Self::bits_for(palette.len());
+        let mut chunk = Self {
+            palette,
+            bits,
+            words: vec![0; Self::word_count(bits)],
+        };
+        if bits > 0 {
+            for (i, index) in indices.into_iter().enumerate() {
+                chunk.write_index(i, index);
+            }
+        }
+        chunk
+    }
+
+    /// Unpacks the chunk into the dense layout
+    pub fn data(&self) -> Array3<Voxel> {
+        let voxels = (0..Self::VOLUME)
+            .map(|i| self.palette[self.read_index(i) as usize])
+            .collect();
+        Array3::from_shape_vec((N, N, N), voxels).unwrap()
+    }
+
+    /// Unpacked chunk data which is packed back, dropping unused palette entries, when the guard is dropped
+    pub fn data_mut(&mut self) -> PaletteChunkDataMut<'_, N> {
+        PaletteChunkDataMut {
+            data: self.data(),
+            chunk: self,
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, ind: [usize; 3]) -> Voxel {
+        self.palette[self.read_index(Self::flat_index(ind)) as usize]
+    }
+
+    /// Sets a single voxel, growing the palette and index width if needed
+    pub fn set(&mut self, ind: [usize; 3], voxel: Voxel) {
+        let index = match self.palette.iter().position(|v| v.id == voxel.id) {
+            Some(index) => index,
+            None => {
+                self.palette.push(voxel);
+                let bits = Self::bits_for(self.palette.len());
+                if bits != self.bits {
+                    self.repack(bits);
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits > 0 {
+            self.write_index(Self::flat_index(ind), index as u32);
+        }
+    }
+
+    pub fn palette(&self) -> &[Voxel] {
+        &self.palette
+    }
+
+    pub fn bits_per_voxel(&self) -> u32 {
+        self.bits
+    }
+
+    #[inline]
+    pub fn is_uniform(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Heap and inline bytes used by the chunk
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.palette.capacity() * std::mem::size_of::<Voxel>()
+            + self.words.capacity() * std::mem::size_of::<u64>()
+    }
+
+    #[inline]
+    fn flat_index(ind: [usize; 3]) -> usize {
+        (ind[0] * N + ind[1]) * N + ind[2]
+    }
+
+    fn bits_for(palette_len: usize) -> u32 {
+        if palette_len <= 1 {
+            0
+        } else {
+            usize::BITS - (palette_len - 1).leading_zeros()
+        }
+    }
+
+    fn word_count(bits: u32) -> usize {
+        WORD_BITS
+            .checked_div(bits)
+            .map_or(0, |per_word| Self::VOLUME.div_ceil(per_word as usize))
+    }
+
+    #[inline]
+    fn read_index(&self, i: usize) -> u32 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (WORD_BITS / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.words[i / per_word] >> shift) & mask) as u32
+    }
+
+    #[inline]
+    fn write_index(&mut self, i: usize, index: u32) {
+        let per_word = (WORD_BITS / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !(mask << shift)) | ((index as u64) << shift);
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let old = std::mem::replace(
+            self,
+            Self {
+                palette: Vec::new(),
+                bits,
+                words: vec![0; Self::word_count(bits)],
+            },
+        );
+        for i in 0..Self::VOLUME {
+            self.write_index(i, old.read_index(i));
+        }
+        self.palette = old.palette;
+    }
+}
+
+impl<const N: usize> Default for PaletteChunk<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> From<&Chunk<N>> for PaletteChunk<N> {
+    fn from(chunk: &Chunk<N>) -> Self {
+        Self::from_dense(chunk.data())
+    }
+}
+
+impl<const N: usize> From<&PaletteChunk<N>> for Chunk<N> {
+    fn from(chunk: &PaletteChunk<N>) -> Self {
+        let mut dense = Chunk::new();
+        *dense.data_mut() = chunk.data();
+        dense
+    }
+}
+
+pub struct PaletteChunkDataMut<'a, const N: usize> {
+    chunk: &'a mut PaletteChunk<N>,
+    data: Array3<Voxel>,
+}
+
+impl<'a, const N: usize> Deref for PaletteChunkDataMut<'a, N> {
+    type Target = Array3<Voxel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<'a, const N: usize> DerefMut for PaletteChunkDataMut<'a, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+impl<'a, const N: usize> Drop for PaletteChunkDataMut<'a, N> {
+    fn drop(&mut self) {
+        *self.chunk = PaletteChunk::from_dense(&self.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const SMALLCH: usize = 8;
+
+    fn ids(data: &Array3<Voxel>) -> Vec<u16> {
+        data.iter().map(|v| v.id).collect()
+    }
+
+    #[rstest(
+        distinct,
+        exp_bits,
+        case(1, 0),
+        case(2, 1),
+        case(3, 2),
+        case(16, 4),
+        case(17, 5),
+        case(200, 8)
+    )]
+    fn dense_roundtrip(distinct: u16, exp_bits: u32) {
+        let data = Array3::from_shape_fn((SMALLCH, SMALLCH, SMALLCH), |(x, y, z)| Voxel {
+            id: ((x * 31 + y * 7 + z) % distinct as usize) as u16 + 5,
+        });
+
+        let chunk = PaletteChunk::<SMALLCH>::from_dense(&data);
+
+        assert_eq!(chunk.bits_per_voxel(), exp_bits);
+        assert_eq!(chunk.palette().len(), distinct as usize);
+        assert_eq!(ids(&chunk.data()), ids(&data));
+    }
+
+    #[test]
+    fn uniform_chunk_has_no_indices() {
+        let chunk = PaletteChunk::<32>::new();
+        let dense = Chunk::<32>::new();
+
+        assert!(chunk.is_uniform());
+        assert!(chunk.memory_usage() < 100);
+        assert!(dense.memory_usage() >= 32 * 32 * 32 * 2);
+    }
+
+    #[test]
+    fn set_grows_palette() {
+        let mut chunk = PaletteChunk::<SMALLCH>::new();
+        let mut dense = Array3::<Voxel>::default((SMALLCH, SMALLCH, SMALLCH));
+
+        for (i, ind) in [[0, 0, 0], [1, 2, 3], [7, 7, 7], [4, 0, 6], [2, 2, 2]]
+            .into_iter()
+            .enumerate()
+        {
+            chunk.set(ind, Voxel { id: i as u16 + 1 });
+            dense[ind] = Voxel { id: i as u16 + 1 };
+            assert_eq!(ids(&chunk.data()), ids(&dense));
+        }
+        assert_eq!(chunk.bits_per_voxel(), 3);
+        assert_eq!(chunk.get([1, 2, 3]).id, 2);
+    }
+
+    #[test]
+    fn data_mut_drops_unused_palette_entries() {
+        let mut chunk = PaletteChunk::<SMALLCH>::new();
+        chunk.set([1, 1, 1], Voxel { id: 3 });
+        assert!(!chunk.is_uniform());
+
+        chunk.data_mut()[[1, 1, 1]] = Voxel { id: 0 };
+
+        assert!(chunk.is_uniform());
+        assert_eq!(chunk.get([1, 1, 1]).id, 0);
+    }
+}
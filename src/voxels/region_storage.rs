@@ -0,0 +1,483 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use bevy::prelude::{IVec3, Resource};
+use ndarray::Array3;
+
+use crate::error::{self, Error};
+
+use super::{
+    chunk::{Chunk, ChunkPosition},
+    voxel::Voxel,
+};
+
+/// Chunks per region along each axis
+pub const REGION_SIZE: i32 = 16;
+const REGION_CHUNKS: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+const MAGIC: &[u8; 4] = b"VXRG";
+pub const FORMAT_VERSION: u16 = 1;
+/// Magic, version, chunk size and an (offset, length) pair per chunk
+const HEADER_LEN: usize = 4 + 2 + 2 + REGION_CHUNKS * 8;
+
+/// Offset and length of every chunk payload in a region file, offset 0 if chunk is absent
+type RegionHeader = Vec<(u32, u32)>;
+
+/// Saves chunks to region files, each holding `REGION_SIZE`³ run-length encoded chunks.
+/// Saved chunks are appended and only their header entries are rewritten, the space of
+/// replaced payloads is reclaimed by compacting the region once it outweighs live payloads
+#[derive(Debug, Resource)]
+pub struct RegionStorage {
+    dir: PathBuf,
+    /// Cached headers, None if region file doesn't exist
+    headers: HashMap<IVec3, Option<RegionHeader>>,
+    /// Held for writing while saving so readers never see a half updated region
+    lock: Arc<RwLock<()>>,
+}
+
+/// Reads chunks saved by a `RegionStorage` off the main thread
+#[derive(Debug, Clone)]
+pub struct RegionReader {
+    dir: PathBuf,
+    lock: Arc<RwLock<()>>,
+}
+
+impl RegionReader {
+    /// Loads a chunk, None if it was never saved. Headers aren't cached
+    pub fn read_chunk<const N: usize>(
+        &self,
+        pos: &ChunkPosition,
+    ) -> error::Result<Option<Chunk<N>>> {
+        let _guard = self.lock.read().unwrap();
+        let (region, index) = RegionStorage::region_of(pos);
+        let path = region_path(&self.dir, region);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let (offset, len) = RegionStorage::parse_header::<N>(&mut file, &path)?[index];
+        if offset == 0 {
+            return Ok(None);
+        }
+        RegionStorage::read_payload(&mut file, offset, len).map(Some)
+    }
+}
+
+impl RegionStorage {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            headers: HashMap::new(),
+            lock: Default::default(),
+        }
+    }
+
+    pub fn reader(&self) -> RegionReader {
+        RegionReader {
+            dir: self.dir.clone(),
+            lock: self.lock.clone(),
+        }
+    }
+
+    pub fn region_of(pos: &ChunkPosition) -> (IVec3, usize) {
+        let region = IVec3::new(
+            pos.pos.x.div_euclid(REGION_SIZE),
+            pos.pos.y.div_euclid(REGION_SIZE),
+            pos.pos.z.div_euclid(REGION_SIZE),
+        );
+        let local = pos.pos - region * REGION_SIZE;
+        let index = local.x + local.y * REGION_SIZE + local.z * REGION_SIZE * REGION_SIZE;
+        (region, index as usize)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Loads a chunk, None if it was never saved
+    pub fn load_chunk<const N: usize>(
+        &mut self,
+        pos: &ChunkPosition,
+    ) -> error::Result<Option<Chunk<N>>> {
+        let (region, index) = Self::region_of(pos);
+        let path = region_path(&self.dir, region);
+        let header = match self.headers.get(&region) {
+            Some(header) => header,
+            None => {
+                let header = Self::read_header::<N>(&path)?;
+                self.headers.entry(region).or_insert(header)
+            }
+        };
+        let Some(header) = header else {
+            return Ok(None);
+        };
+        let (offset, len) = header[index];
+        if offset == 0 {
+            return Ok(None);
+        }
+        Self::read_payload(&mut File::open(&path)?, offset, len).map(Some)
+    }
+
+    fn read_payload<const N: usize>(
+        file: &mut File,
+        offset: u32,
+        len: u32,
+    ) -> error::Result<Chunk<N>> {
+        file.seek(SeekFrom::Start(offset as u64))?;
+        let mut payload = vec![0; len as usize];
+        file.read_exact(&mut payload)?;
+
+        let mut chunk = Chunk::<N>::new();
+        *chunk.data_mut() = decode_voxels::<N>(&payload)?;
+        Ok(chunk)
+    }
+
+    /// Saves chunks, appending their payloads to region files
+    pub fn save_chunks<'a, const N: usize>(
+        &mut self,
+        chunks: impl IntoIterator<Item = (ChunkPosition, &'a Chunk<N>)>,
+    ) -> error::Result<()> {
+        let mut by_region: HashMap<IVec3, Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (pos, chunk) in chunks {
+            let (region, index) = Self::region_of(&pos);
+            by_region
+                .entry(region)
+                .or_default()
+                .push((index, encode_voxels(chunk.data())));
+        }
+        if by_region.is_empty() {
+            return Ok(());
+        }
+
+        let lock = self.lock.clone();
+        let _guard = lock.write().unwrap();
+        std::fs::create_dir_all(&self.dir)?;
+        for (region, new_payloads) in by_region {
+            let path = region_path(&self.dir, region);
+            let header = match Self::read_header::<N>(&path)? {
+                Some(header) => Self::append_to_region::<N>(&path, header, new_payloads)?,
+                None => {
+                    let mut payloads = vec![None; REGION_CHUNKS];
+                    for (index, payload) in new_payloads {
+                        payloads[index] = Some(payload);
+                    }
+                    Self::write_region::<N>(&path, &payloads)?
+                }
+            };
+            self.headers.insert(region, Some(header));
+        }
+        Ok(())
+    }
+
+    /// Appends payloads to the end of an existing region and points their header entries
+    /// at them, then compacts the region if replaced payloads take more space than live ones.
+    /// A crash before header entries are written leaves them pointing at the old payloads
+    fn append_to_region<const N: usize>(
+        path: &Path,
+        mut header: RegionHeader,
+        new_payloads: Vec<(usize, Vec<u8>)>,
+    ) -> error::Result<RegionHeader> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let end = file.seek(SeekFrom::End(0))?;
+
+        let mut body = Vec::new();
+        for (index, payload) in new_payloads.iter() {
+            header[*index] = ((end as usize + body.len()) as u32, payload.len() as u32);
+            body.extend_from_slice(payload);
+        }
+        file.write_all(&body)?;
+        for (index, _) in new_payloads.iter() {
+            let (offset, len) = header[*index];
+            file.seek(SeekFrom::Start((8 + index * 8) as u64))?;
+            file.write_all(&[offset.to_le_bytes(), len.to_le_bytes()].concat())?;
+        }
+
+        let live = header.iter().map(|&(_, len)| len as u64).sum::<u64>();
+        let dead = end + body.len() as u64 - HEADER_LEN as u64 - live;
+        if dead <= live {
+            return Ok(header);
+        }
+        let mut payloads = vec![None; REGION_CHUNKS];
+        for (index, &(offset, len)) in header.iter().enumerate() {
+            if offset == 0 {
+                continue;
+            }
+            file.seek(SeekFrom::Start(offset as u64))?;
+            let mut payload = vec![0; len as usize];
+            file.read_exact(&mut payload)?;
+            payloads[index] = Some(payload);
+        }
+        drop(file);
+        Self::write_region::<N>(path, &payloads)
+    }
+
+    /// Writes a whole region holding only the given payloads
+    fn write_region<const N: usize>(
+        path: &Path,
+        payloads: &[Option<Vec<u8>>],
+    ) -> error::Result<RegionHeader> {
+        let mut header = vec![(0, 0); REGION_CHUNKS];
+        let mut body = Vec::new();
+        for (index, payload) in payloads.iter().enumerate() {
+            if let Some(payload) = payload {
+                header[index] = ((HEADER_LEN + body.len()) as u32, payload.len() as u32);
+                body.extend_from_slice(payload);
+            }
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(N as u16).to_le_bytes());
+        for (offset, len) in header.iter() {
+            bytes.extend_from_slice(&offset.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+        }
+        bytes.extend_from_slice(&body);
+
+        // write to a temporary file first so a crash never leaves a half written region
+        let tmp_path = path.with_extension("region.tmp");
+        File::create(&tmp_path)?.write_all(&bytes)?;
+        std::fs::rename(tmp_path, path)?;
+
+        Ok(header)
+    }
+
+    fn read_header<const N: usize>(path: &Path) -> error::Result<Option<RegionHeader>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        Self::parse_header::<N>(&mut file, path).map(Some)
+    }
+
+    fn parse_header<const N: usize>(file: &mut File, path: &Path) -> error::Result<RegionHeader> {
+        let mut bytes = vec![0; HEADER_LEN];
+        file.read_exact(&mut bytes)?;
+
+        if &bytes[0..4] != MAGIC {
+            return Err(Error::CorruptRegion(format!(
+                "{} is not a region file",
+                path.display()
+            )));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != FORMAT_VERSION {
+            return Err(Error::CorruptRegion(format!(
+                "{} has unsupported format version {version}",
+                path.display()
+            )));
+        }
+        let chunk_size = u16::from_le_bytes([bytes[6], bytes[7]]);
+        if chunk_size as usize != N {
+            return Err(Error::CorruptRegion(format!(
+                "{} has chunk size {chunk_size}, expected {N}",
+                path.display()
+            )));
+        }
+
+        let header = bytes[8..]
+            .chunks_exact(8)
+            .map(|entry| {
+                (
+                    u32::from_le_bytes(entry[0..4].try_into().unwrap()),
+                    u32::from_le_bytes(entry[4..8].try_into().unwrap()),
+                )
+            })
+            .collect();
+        Ok(header)
+    }
+}
+
+fn region_path(dir: &Path, region: IVec3) -> PathBuf {
+    dir.join(format!("r.{}.{}.{}.region", region.x, region.y, region.z))
+}
+
+/// Run-length encodes voxels as (run length, voxel id) pairs of little endian u16
+fn encode_voxels(data: &Array3<Voxel>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut push_run = |run: u16, id: u16| {
+        bytes.extend_from_slice(&run.to_le_bytes());
+        bytes.extend_from_slice(&id.to_le_bytes());
+    };
+
+    let mut iter = data.iter();
+    let Some(first) = iter.next() else {
+        return Vec::new();
+    };
+    let (mut run, mut id) = (1u16, first.id);
+    for vox in iter {
+        if vox.id == id && run < u16::MAX {
+            run += 1;
+        } else {
+            push_run(run, id);
+            (run, id) = (1, vox.id);
+        }
+    }
+    push_run(run, id);
+    bytes
+}
+
+fn decode_voxels<const N: usize>(bytes: &[u8]) -> error::Result<Array3<Voxel>> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(Error::CorruptRegion(
+            "chunk payload isn't a sequence of runs".to_owned(),
+        ));
+    }
+    let mut voxels = Vec::with_capacity(N * N * N);
+    for run in bytes.chunks_exact(4) {
+        let len = u16::from_le_bytes([run[0], run[1]]);
+        let id = u16::from_le_bytes([run[2], run[3]]);
+        voxels.extend(std::iter::repeat_n(Voxel { id }, len as usize));
+    }
+    Array3::from_shape_vec((N, N, N), voxels)
+        .map_err(|_| Error::CorruptRegion("chunk payload has wrong voxel count".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    const SMALLCH: usize = 4;
+
+    fn temp_storage(name: &str) -> RegionStorage {
+        let dir = std::env::temp_dir().join(format!(
+            "voxel_region_storage_{name}_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        RegionStorage::new(dir)
+    }
+
+    fn filled_chunk(id: u16) -> Chunk<SMALLCH> {
+        let mut chunk = Chunk::new();
+        chunk.data_mut()[[1, 2, 3]] = Voxel { id };
+        chunk.data_mut()[[0, 0, 0]] = Voxel { id: id + 1 };
+        chunk
+    }
+
+    #[rstest(pos, exp_region, exp_index,
+        case(IVec3::new(0, 0, 0), IVec3::new(0, 0, 0), 0),
+        case(IVec3::new(1, 2, 3), IVec3::new(0, 0, 0), 1 + 2 * 16 + 3 * 256),
+        case(IVec3::new(-1, 0, 16), IVec3::new(-1, 0, 1), 15),
+    )]
+    fn region_of(pos: IVec3, exp_region: IVec3, exp_index: usize) {
+        assert_eq!(
+            RegionStorage::region_of(&pos.into()),
+            (exp_region, exp_index)
+        );
+    }
+
+    #[test]
+    fn save_load_roundtrip() {
+        let mut storage = temp_storage("roundtrip");
+        let positions = [IVec3::new(0, 0, 0), IVec3::new(-3, 5, 17)];
+        let chunks = [filled_chunk(5), filled_chunk(7)];
+
+        storage
+            .save_chunks(positions.iter().map(|&p| p.into()).zip(chunks.iter()))
+            .unwrap();
+
+        // fresh storage doesn't share cached headers
+        let mut storage = RegionStorage::new(&storage.dir);
+        for (pos, chunk) in positions.iter().zip(chunks.iter()) {
+            let loaded = storage
+                .load_chunk::<SMALLCH>(&(*pos).into())
+                .unwrap()
+                .unwrap();
+            assert!(loaded
+                .data()
+                .iter()
+                .zip(chunk.data().iter())
+                .all(|(a, b)| a.id == b.id));
+        }
+        assert!(storage
+            .load_chunk::<SMALLCH>(&IVec3::new(1, 0, 0).into())
+            .unwrap()
+            .is_none());
+
+        std::fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn overwrite_keeps_other_chunks() {
+        let mut storage = temp_storage("overwrite");
+        let first = ChunkPosition::new(IVec3::new(0, 0, 0));
+        let second = ChunkPosition::new(IVec3::new(1, 0, 0));
+
+        storage
+            .save_chunks([(first, &filled_chunk(1)), (second, &filled_chunk(2))])
+            .unwrap();
+        storage.save_chunks([(first, &filled_chunk(3))]).unwrap();
+
+        let mut storage = RegionStorage::new(&storage.dir);
+        let first = storage.load_chunk::<SMALLCH>(&first).unwrap().unwrap();
+        let second = storage.load_chunk::<SMALLCH>(&second).unwrap().unwrap();
+        assert_eq!(first.data()[[1, 2, 3]].id, 3);
+        assert_eq!(second.data()[[1, 2, 3]].id, 2);
+        let read = storage
+            .reader()
+            .read_chunk::<SMALLCH>(&ChunkPosition::default());
+        assert_eq!(read.unwrap().unwrap().data()[[1, 2, 3]].id, 3);
+
+        std::fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn resave_appends_then_compacts() {
+        let mut storage = temp_storage("append");
+        let (first, second) = (ChunkPosition::default(), ChunkPosition::new(IVec3::X));
+        let path = region_path(&storage.dir, IVec3::ZERO);
+        let size = || std::fs::metadata(&path).unwrap().len();
+        let payload_len = encode_voxels(filled_chunk(1).data()).len() as u64;
+
+        storage
+            .save_chunks([(first, &filled_chunk(1)), (second, &filled_chunk(2))])
+            .unwrap();
+        let compact = size();
+        storage.save_chunks([(first, &filled_chunk(3))]).unwrap();
+        assert_eq!(size(), compact + payload_len);
+        for id in 4..10 {
+            storage.save_chunks([(first, &filled_chunk(id))]).unwrap();
+        }
+        assert!(size() <= compact + 2 * payload_len);
+
+        let mut storage = RegionStorage::new(&storage.dir);
+        let first = storage.load_chunk::<SMALLCH>(&first).unwrap().unwrap();
+        let second = storage.load_chunk::<SMALLCH>(&second).unwrap().unwrap();
+        assert_eq!(first.data()[[1, 2, 3]].id, 9);
+        assert_eq!(second.data()[[1, 2, 3]].id, 2);
+
+        std::fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn chunk_size_mismatch_rejected() {
+        let mut storage = temp_storage("mismatch");
+        let pos = ChunkPosition::default();
+        storage.save_chunks([(pos, &filled_chunk(1))]).unwrap();
+
+        let mut storage = RegionStorage::new(&storage.dir);
+        assert!(matches!(
+            storage.load_chunk::<8>(&pos),
+            Err(Error::CorruptRegion(_))
+        ));
+
+        std::fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn uniform_chunk_is_one_run() {
+        let chunk = Chunk::<SMALLCH>::new();
+
+        assert_eq!(encode_voxels(chunk.data()).len(), 4);
+    }
+}
@@ -1,8 +1,15 @@
+pub mod block_interaction_system;
+pub mod chunk_generation_system;
 pub mod chunk_render;
+pub mod chunk_save_system;
+pub mod chunk_unload_system;
+pub mod chunk_visibility_system;
 pub mod common;
 pub mod components;
 pub mod destroy_on_touch_system;
 pub mod dirty_around_system;
+pub mod falling_block_system;
 pub mod generate_map_around_system;
 pub mod materials;
 pub mod world_change_apply_system;
+pub mod world_tick_system;
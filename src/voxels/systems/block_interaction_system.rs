@@ -0,0 +1,77 @@
+use bevy::prelude::{Input, KeyCode, MouseButton, Query, Res, Transform, Vec3};
+
+use crate::{
+    game_config::RuntimeGameConfig,
+    voxels::{
+        voxel::Voxel,
+        world::{RaycastResult, VoxelWorldProcedural},
+    },
+};
+
+use super::components::BlockInteraction;
+
+const SELECT_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+pub fn block_interaction_system(
+    vox_world: Res<VoxelWorldProcedural>,
+    config: Res<RuntimeGameConfig>,
+    mouse: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
+    mut interactors: Query<(&mut BlockInteraction, &Transform)>,
+) {
+    let registry = vox_world.registry();
+    // number keys select from rendered blocks in registry order
+    let selected = SELECT_KEYS
+        .iter()
+        .position(|&key| keyboard.just_pressed(key))
+        .and_then(|n| registry.blocks().filter(|b| b.is_rendered()).nth(n))
+        .map(|b| Voxel { id: b.id });
+
+    let break_block = mouse.just_pressed(MouseButton::Left);
+    let place_block = mouse.just_pressed(MouseButton::Right);
+
+    for (mut interaction, transform) in interactors.iter_mut() {
+        if let Some(selected) = selected {
+            interaction.selected = selected;
+        }
+        if !break_block && !place_block {
+            continue;
+        }
+
+        let RaycastResult::Hit(hit) = vox_world.raycast(
+            transform.translation,
+            transform.forward(),
+            config.config.reach_distance,
+        ) else {
+            continue;
+        };
+
+        if break_block {
+            vox_world.set_voxel_by_player(&hit.chunk, &hit.index, Voxel { id: 0 });
+        } else {
+            let target =
+                VoxelWorldProcedural::voxel_pos(&hit.chunk, &hit.index) + hit.face.to_ivec();
+            let target_center = target.as_vec3() + Vec3::splat(0.5);
+            // the interactor would immediately be stuck in it
+            if transform.translation.floor().as_ivec3() == target {
+                continue;
+            }
+            match vox_world.voxel_at_pos(&target_center) {
+                Some(vox) if !registry.is_solid(vox) => {
+                    vox_world.set_voxel_by_player_at_pos(&target_center, interaction.selected)
+                }
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use bevy::prelude::{error, Commands, MaterialMeshBundle, Res, ResMut, Transform};
+
+use crate::{
+    game_config::RuntimeGameConfig,
+    voxels::{
+        chunk::CHSIZEI, chunk_material::ChunkMaterial, generation_tasks::GenerationTasks,
+        resources::EntityChunks, world::VoxelWorldProcedural,
+    },
+};
+
+use super::components::EdgeChunk;
+
+/// Generates terrain of requested chunks and their surroundings and reads saved chunks in tasks,
+/// then completes a few of the chunks per frame and spawns their entities.
+/// Decorating and lighting stay on the main thread, they write into neighbouring chunks
+/// and spread light through the loaded world, both owned by `VoxelWorld`
+pub fn chunk_generation_system(
+    mut vox_world: ResMut<VoxelWorldProcedural>,
+    mut tasks: ResMut<GenerationTasks>,
+    mut ent_chunks: ResMut<EntityChunks>,
+    config: Res<RuntimeGameConfig>,
+    mut commands: Commands,
+) {
+    tasks.collect_finished(&mut vox_world);
+    tasks.spawn_tasks(&vox_world, config.generation_tasks_in_flight as usize);
+
+    let ready = tasks.take_ready(&vox_world, config.chunks_complete_per_frame as usize);
+    for (chpos, stored) in ready {
+        if ent_chunks.map.contains_key(&chpos) {
+            continue;
+        }
+        // terrain around is generated, only decorations and finalization are left
+        let stored = stored.unwrap_or_else(|err| {
+            error!("Failed to load chunk {:?}: {}", chpos.pos, err);
+            None
+        });
+        vox_world.complete_chunk(&chpos, stored);
+        let ent = commands
+            .spawn((
+                chpos,
+                EdgeChunk,
+                MaterialMeshBundle::<ChunkMaterial> {
+                    transform: Transform {
+                        translation: (chpos.pos * CHSIZEI).as_vec3(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            ))
+            .id();
+        ent_chunks.map.insert(chpos, ent);
+    }
+}
@@ -1,58 +1,110 @@
 use crate::{
     game_config::RuntimeGameConfig,
-    voxels::{chunk::ChunkPosition, systems::components::RenderedTag, world::VoxelWorldProcedural},
+    voxels::{
+        chunk::CHSIZE,
+        lod::LodLevels,
+        mesh_priority::MeshPriority,
+        meshing_tasks::MeshingTasks,
+        resources::EntityChunks,
+        systems::components::{RenderAround, RenderedTag, TranslucentPart},
+        visibility::ChunkVisibility,
+        world::VoxelWorldProcedural,
+    },
+};
+use bevy::{
+    prelude::{
+        Assets, BuildChildren, Camera, Commands, Handle, IVec3, Local, MaterialMeshBundle, Mesh,
+        Query, Res, ResMut, Transform, With,
+    },
+    render::primitives::Frustum,
 };
-use bevy::prelude::{Assets, Commands, Entity, Mesh, Query, Res, ResMut};
-use rayon::prelude::*;
-use std::{collections::HashMap, sync::mpsc::channel};
 
 use super::materials::Materials;
 
+/// Inserts meshes of finished meshing tasks and submits the most urgent dirty chunks for meshing,
+/// see `MeshPriority`, at levels of detail by distance to render loaders.
+/// Chunks are meshed again when loaders move far enough to change their level
+#[allow(clippy::too_many_arguments)]
 pub fn chunk_render_system(
     mut commands: Commands,
     vox_world: Res<VoxelWorldProcedural>,
     config: Res<RuntimeGameConfig>,
     mats: Res<Materials>,
-    mut chunks: Query<(Entity, &mut ChunkPosition)>,
+    ent_chunks: Res<EntityChunks>,
+    mut tasks: ResMut<MeshingTasks>,
+    mut visibility: ResMut<ChunkVisibility>,
     mut meshes: ResMut<Assets<Mesh>>,
+    render_bubbles: Query<&Transform, With<RenderAround>>,
+    cameras: Query<&Frustum, With<Camera>>,
+    parts: Query<&TranslucentPart>,
+    mut last_loaders: Local<Vec<IVec3>>,
 ) {
-    let chunk_entities = {
-        let mut map = HashMap::new();
-        for (ent, chunk_pos) in chunks.iter_mut() {
-            map.insert(*chunk_pos, ent);
-        }
-        map
-    };
-
-    let (sender, receiver) = channel();
-
-    let dirty = vox_world.dirty().pin();
-    dirty
-        .iter()
-        .take(config.chunks_render_per_frame as usize)
-        .collect::<Vec<_>>()
-        .into_par_iter()
-        .copied()
-        .map_with(sender, |s, pos| (pos, s.clone()))
-        .for_each(|(to_clean, sender)| {
-            let mesh = vox_world.mesh(&to_clean);
-
-            // create mesh
-            let mesh: Option<Mesh> = mesh.build_mesh();
+    for (pos, meshed) in tasks.collect_finished(&vox_world) {
+        visibility.set_connections(pos, meshed.connections);
+        let Some(&ent) = ent_chunks.map.get(&pos) else {
+            continue;
+        };
 
-            // get entity from hashmap or create a new one
-            let ent = chunk_entities[&to_clean];
-            sender.send((mesh, ent, to_clean)).unwrap();
-        });
+        match (meshed.translucent, parts.get(ent)) {
+            (Some(mesh), Ok(part)) => {
+                commands.entity(part.0).insert(meshes.add(mesh));
+            }
+            (Some(mesh), Err(_)) => {
+                // inherits the chunk's transform and visibility
+                let part = commands
+                    .spawn(MaterialMeshBundle {
+                        mesh: meshes.add(mesh),
+                        material: mats.translucent.clone(),
+                        ..Default::default()
+                    })
+                    .id();
+                commands
+                    .entity(ent)
+                    .add_child(part)
+                    .insert(TranslucentPart(part));
+            }
+            (None, Ok(part)) => {
+                commands.entity(part.0).remove::<Handle<Mesh>>();
+            }
+            (None, Err(_)) => {}
+        }
 
-    for cmd in receiver.into_iter() {
-        let (mesh, ent, to_clean) = cmd;
         let mut entity = commands.entity(ent);
-        if let Some(mesh) = mesh {
+        if let Some(mesh) = meshed.opaque {
             entity.insert((meshes.add(mesh), mats.material.clone()));
+        } else {
+            // downsampling can leave nothing to render
+            entity.remove::<Handle<Mesh>>();
         }
         entity.insert(RenderedTag);
+    }
+
+    let loaders = render_bubbles
+        .iter()
+        .map(|t| VoxelWorldProcedural::to_ch_pos_index(&t.translation).0.pos)
+        .collect::<Vec<_>>();
+    let lods = LodLevels::new(loaders.clone(), config.config.lod_distances.clone());
+    if *last_loaders != loaders {
+        tasks.mark_outdated_levels(&vox_world, &lods);
+        *last_loaders = loaders.clone();
+    }
 
-        dirty.remove(&to_clean);
+    let dirty = vox_world.dirty().pin();
+    let edited = vox_world.edited().pin();
+    let priority = MeshPriority::<CHSIZE>::new(loaders, cameras.iter().cloned().collect());
+    let free = (config.meshing_tasks_in_flight as usize).saturating_sub(tasks.in_flight());
+    // chunks already being meshed stay dirty until their task finishes
+    let to_submit = priority.most_urgent(
+        dirty
+            .iter()
+            .filter(|pos| !tasks.is_in_flight(pos))
+            .map(|pos| (*pos, edited.contains(pos))),
+        free.min(config.chunks_render_per_frame as usize),
+    );
+    for pos in to_submit {
+        // chunks with unloaded neighbours are marked again once they load
+        tasks.submit(pos, &vox_world, config.meshing_mode, &lods);
+        dirty.remove(&pos);
+        edited.remove(&pos);
     }
 }
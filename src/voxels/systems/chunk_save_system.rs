@@ -0,0 +1,36 @@
+use bevy::{
+    app::AppExit,
+    prelude::{error, info, EventReader, Local, Res, ResMut},
+    time::Time,
+};
+
+use crate::{
+    game_config::RuntimeGameConfig,
+    voxels::{region_storage::RegionStorage, world::VoxelWorldProcedural},
+};
+
+/// Periodically and on exit saves modified chunks
+pub fn chunk_save_system(
+    mut vox_world: ResMut<VoxelWorldProcedural>,
+    mut storage: ResMut<RegionStorage>,
+    config: Res<RuntimeGameConfig>,
+    time: Res<Time>,
+    mut exit: EventReader<AppExit>,
+    mut since_save: Local<f32>,
+) {
+    *since_save += time.delta_seconds();
+    let exiting = exit.iter().count() > 0;
+    if !exiting && *since_save < config.config.autosave_interval {
+        return;
+    }
+    *since_save = 0.;
+
+    if vox_world.modified().is_empty() {
+        return;
+    }
+    let count = vox_world.modified().len();
+    match vox_world.save_modified(&mut storage) {
+        Ok(()) => info!("Saved {} chunks", count),
+        Err(err) => error!("Failed to save chunks: {}", err),
+    }
+}
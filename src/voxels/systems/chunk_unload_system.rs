@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use bevy::prelude::{
+    error, Commands, DespawnRecursiveExt, Entity, IVec3, Query, Res, ResMut, Transform, With,
+};
+
+use crate::{
+    directions::Directions,
+    game_config::RuntimeGameConfig,
+    voxels::{
+        chunk::ChunkPosition, falling_blocks::FallingBlock, generation_tasks::GenerationTasks,
+        region_storage::RegionStorage, resources::EntityChunks, visibility::ChunkVisibility,
+        world::VoxelWorldProcedural,
+    },
+};
+
+use super::components::{EdgeChunk, GenerateMapAround};
+
+/// Unloads chunks outside of generate bubbles of all loaders plus a hysteresis margin
+#[allow(clippy::too_many_arguments)]
+pub fn chunk_unload_system(
+    mut vox_world: ResMut<VoxelWorldProcedural>,
+    mut ent_chunks: ResMut<EntityChunks>,
+    mut tasks: ResMut<GenerationTasks>,
+    mut visibility: ResMut<ChunkVisibility>,
+    mut storage: Option<ResMut<RegionStorage>>,
+    config: Res<RuntimeGameConfig>,
+    loaders: Query<&Transform, (With<GenerateMapAround>,)>,
+    falling: Query<(Entity, &FallingBlock, &Transform)>,
+    mut commands: Commands,
+) {
+    let loader_chunks = loaders
+        .iter()
+        .map(|t| VoxelWorldProcedural::to_ch_pos_index(&t.translation).0.pos)
+        .collect::<Vec<IVec3>>();
+    // without loaders everything would be unloaded
+    if loader_chunks.is_empty() {
+        return;
+    }
+
+    let unload_distance = config.config.generate_around_bubble + config.config.unload_hysteresis;
+    let is_far = |chpos: &&ChunkPosition| {
+        loader_chunks
+            .iter()
+            .all(|l| (*l - chpos.pos).as_vec3().length() as usize > unload_distance)
+    };
+
+    tasks.cancel(|chpos| is_far(&chpos));
+    visibility.forget(|chpos| is_far(&chpos));
+    let proto_to_remove = vox_world
+        .proto_chunks()
+        .filter(is_far)
+        .copied()
+        .collect::<Vec<ChunkPosition>>();
+    vox_world.remove_proto_chunks(&proto_to_remove);
+
+    let to_unload = vox_world
+        .chunks()
+        .keys()
+        .filter(is_far)
+        .copied()
+        .collect::<Vec<ChunkPosition>>();
+    if to_unload.is_empty() {
+        return;
+    }
+
+    // falling blocks go back into their chunks, unloading applies and saves the change
+    let unloading = to_unload.iter().collect::<HashSet<_>>();
+    for (entity, block, transform) in falling.iter() {
+        let (chpos, _) = VoxelWorldProcedural::to_ch_pos_index(&transform.translation.round());
+        if unloading.contains(&chpos) {
+            block.settle(transform.translation, &*vox_world);
+            commands.entity(entity).despawn();
+        }
+    }
+
+    if let Err(err) = vox_world.unload_chunks(&to_unload, storage.as_deref_mut()) {
+        error!("Failed to save chunks before unloading: {}", err);
+        return;
+    }
+
+    for chpos in to_unload.iter() {
+        if let Some(ent) = ent_chunks.map.remove(chpos) {
+            // with its translucent part
+            commands.entity(ent).despawn_recursive();
+        }
+    }
+    // loaded neighbours are on the edge of generated area again
+    for chpos in to_unload.iter() {
+        for dir in Directions::all().into_iter().map(|d| d.to_ivec()) {
+            if let Some(&ent) = ent_chunks.map.get(&(chpos.pos + dir).into()) {
+                commands.entity(ent).insert(EdgeChunk);
+            }
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use bevy::prelude::{Camera, Color, GlobalTransform, Query, Res, ResMut, Vec3, Visibility, With};
+use bevy::render::primitives::Frustum;
+use bevy_prototype_debug_lines::DebugShapes;
+
+use crate::{
+    game_config::RuntimeGameConfig,
+    voxels::{
+        chunk::{ChunkPosition, CHSIZE, CHSIZEF, CHSIZEI},
+        visibility::ChunkVisibility,
+        world::VoxelWorldProcedural,
+    },
+};
+
+use super::components::RenderedTag;
+
+/// Hides rendered chunks cameras can't see, see `ChunkVisibility`
+pub fn chunk_visibility_system(
+    mut visibility: ResMut<ChunkVisibility>,
+    config: Res<RuntimeGameConfig>,
+    cameras: Query<(&GlobalTransform, &Frustum), With<Camera>>,
+    mut chunks: Query<(&ChunkPosition, &mut Visibility), With<RenderedTag>>,
+    mut lines: ResMut<DebugShapes>,
+) {
+    visibility.update::<CHSIZE>(
+        cameras.iter().map(|(transform, frustum)| {
+            let (chpos, _) = VoxelWorldProcedural::to_ch_pos_index(&transform.translation());
+            (chpos, *frustum)
+        }),
+        config.config.render_around_bubble,
+    );
+
+    for (chpos, mut vis) in chunks.iter_mut() {
+        let culled = !visibility.is_visible(chpos);
+        let expected = if culled {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
+        // avoids triggering change detection every frame
+        if *vis != expected {
+            *vis = expected;
+        }
+
+        if culled && config.debug_show_culled_chunks {
+            let min = (chpos.pos * CHSIZEI).as_vec3();
+            lines
+                .cuboid()
+                .min_max(min, min + Vec3::ONE * CHSIZEF)
+                .color(Color::RED);
+        }
+    }
+}
@@ -3,9 +3,10 @@ use bevy::prelude::IVec3;
 use crate::voxels::world::VoxelWorldProcedural;
 
 pub fn may_chunk_produce_mesh(vox_world: &VoxelWorldProcedural, pos: IVec3) -> bool {
+    let registry = vox_world.registry();
     let chunk_at = vox_world.chunk_at(&pos.into());
-    let is_transparent = chunk_at.is_transparent();
-    let is_nontransparent = chunk_at.is_nontransparent();
+    let is_transparent = chunk_at.is_transparent(registry);
+    let is_nontransparent = chunk_at.is_nontransparent(registry);
     if !is_transparent && !is_nontransparent {
         return true;
     }
@@ -19,8 +20,11 @@ pub fn may_chunk_produce_mesh(vox_world: &VoxelWorldProcedural, pos: IVec3) -> b
         let Some(next_chunk) = &vox_world.get_chunk_at(&edge_chunk_pos.into()) else {
             return true;
         };
-        let is_next = (next_chunk.is_transparent(), next_chunk.is_nontransparent());
-        if (chunk_at.is_transparent(), chunk_at.is_nontransparent()) != is_next {
+        let is_next = (
+            next_chunk.is_transparent(registry),
+            next_chunk.is_nontransparent(registry),
+        );
+        if (is_transparent, is_nontransparent) != is_next {
             will_produce_mesh = true;
             break;
         }
@@ -29,6 +33,7 @@ pub fn may_chunk_produce_mesh(vox_world: &VoxelWorldProcedural, pos: IVec3) -> b
 }
 
 pub fn may_neighbours_produce_mesh(vox_world: &VoxelWorldProcedural, pos: IVec3) -> bool {
+    let registry = vox_world.registry();
     let mut prev_indicators = None;
 
     let mut may_produce_mesh = false;
@@ -41,8 +46,8 @@ pub fn may_neighbours_produce_mesh(vox_world: &VoxelWorldProcedural, pos: IVec3)
             continue;
         };
 
-        let is_transparent = next_chunk.is_transparent();
-        let is_nontransparent = next_chunk.is_nontransparent();
+        let is_transparent = next_chunk.is_transparent(registry);
+        let is_nontransparent = next_chunk.is_nontransparent(registry);
         if !is_transparent && !is_nontransparent {
             return true;
         }
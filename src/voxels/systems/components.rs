@@ -1,4 +1,6 @@
-use bevy::prelude::Component;
+use bevy::prelude::{Component, Entity};
+
+use crate::voxels::voxel::Voxel;
 
 #[derive(Component)]
 pub struct GenerateMapAround;
@@ -12,8 +14,24 @@ pub struct RenderAround;
 #[derive(Component)]
 pub struct EdgeChunk;
 
+/// Child entity of a chunk rendering its translucent faces
+#[derive(Component)]
+pub struct TranslucentPart(pub Entity);
+
 #[derive(Component)]
 pub struct EdgeRenderChunk;
 
 #[derive(Debug, Default, Component)]
 pub struct DestroyVoxOnTouch;
+
+/// Breaks the targeted voxel on left click and places `selected` on right click
+#[derive(Debug, Component)]
+pub struct BlockInteraction {
+    pub selected: Voxel,
+}
+
+impl BlockInteraction {
+    pub fn new(selected: Voxel) -> Self {
+        Self { selected }
+    }
+}
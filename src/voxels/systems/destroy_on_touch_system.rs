@@ -9,9 +9,10 @@ pub fn destroy_on_touch_system(
 ) {
     for (_destr_on_touch, transform) in q1.iter() {
         match vox_world.voxel_at_pos(&transform.translation) {
-            Some(Voxel { id: 0 }) => {}
-            Some(_) => vox_world.set_voxel_at_pos(&transform.translation, Voxel { id: 0 }),
-            None => {}
+            Some(vox) if vox_world.registry().is_solid(vox) => {
+                vox_world.set_voxel_by_player_at_pos(&transform.translation, Voxel { id: 0 })
+            }
+            _ => {}
         }
     }
 }
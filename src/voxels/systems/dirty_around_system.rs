@@ -7,7 +7,10 @@ use crate::{
     },
 };
 
-use bevy::prelude::{Commands, Entity, IVec3, Query, Res, ResMut, Transform, Vec3, With};
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::{Commands, Entity, IVec3, Query, Res, ResMut, Transform, Vec3, With},
+};
 use bevy_prototype_debug_lines::DebugShapes;
 
 use super::{
@@ -15,17 +18,29 @@ use super::{
     components::{EdgeChunk, EdgeRenderChunk, RenderAround, RenderedTag},
 };
 
+/// Chunk entities and their render and generation state
+#[derive(SystemParam)]
+pub struct ChunkEntities<'w, 's> {
+    ent_chunks: Res<'w, EntityChunks>,
+    rendered_chunks: Query<'w, 's, &'static ChunkPosition, (With<RenderedTag>,)>,
+    edge_chunks: Query<'w, 's, (Entity, &'static ChunkPosition), (With<EdgeRenderChunk>,)>,
+    edge_generated_chunks: Query<'w, 's, (Entity, &'static ChunkPosition), (With<EdgeChunk>,)>,
+}
+
 pub fn dirty_around_system(
     vox_world: ResMut<VoxelWorldProcedural>,
     config: Res<RuntimeGameConfig>,
     render_bubbles: Query<&Transform, (With<RenderAround>,)>,
-    rendered_chunks: Query<&ChunkPosition, (With<RenderedTag>,)>,
-    ent_chunks: Res<EntityChunks>,
-    edge_chunks: Query<(Entity, &ChunkPosition), (With<EdgeRenderChunk>,)>,
-    edge_generated_chunks: Query<(Entity, &ChunkPosition), (With<EdgeChunk>,)>,
+    chunks: ChunkEntities,
     mut commands: Commands,
     mut lines: ResMut<DebugShapes>,
 ) {
+    let ChunkEntities {
+        ent_chunks,
+        rendered_chunks,
+        edge_chunks,
+        edge_generated_chunks,
+    } = chunks;
     // info!("edge_chunks {}", edge_chunks.iter().count());
     // info!("dirty {}", vox_world.dirty().len());
 
@@ -47,7 +62,11 @@ pub fn dirty_around_system(
             .map(|d| d.to_ivec())
         {
             let edge_chunk_pos = chpos.pos + dir;
-            let entity = ent_chunks.map[&edge_chunk_pos.into()];
+            let Some(&entity) = ent_chunks.map.get(&edge_chunk_pos.into()) else {
+                // neighbour was unloaded
+                is_edge = true;
+                continue;
+            };
 
             // don't step on generated edge chunks, or meshing will fail
             if edge_generated_chunks.contains(entity) {
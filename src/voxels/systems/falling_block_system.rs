@@ -0,0 +1,76 @@
+use bevy::{
+    app::AppExit,
+    prelude::{
+        default, Assets, Commands, Entity, EventReader, MaterialMeshBundle, Mesh, Query, Res,
+        ResMut, Time, Transform,
+    },
+};
+
+use crate::voxels::{
+    block_ticks::BlockTicks,
+    falling_blocks::{block_mesh, FallingBlock},
+    world::VoxelWorldProcedural,
+};
+
+use super::materials::Materials;
+
+/// Spawns entities for voxels which started falling on world ticks, moves them
+/// and puts them back into the world where they land
+pub fn falling_block_system(
+    mut commands: Commands,
+    vox_world: Res<VoxelWorldProcedural>,
+    mut ticks: ResMut<BlockTicks>,
+    mats: Res<Materials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    time: Res<Time>,
+    mut falling: Query<(Entity, &mut FallingBlock, &mut Transform)>,
+) {
+    for (entity, mut block, mut transform) in falling.iter_mut() {
+        if let Some(cell) = block.step(
+            &mut transform.translation,
+            time.delta_seconds(),
+            &*vox_world,
+        ) {
+            block.land(cell, &*vox_world);
+            commands.entity(entity).despawn();
+        }
+    }
+
+    let registry = vox_world.registry();
+    for (pos, voxel) in ticks.take_falling() {
+        let Some(mesh) = block_mesh(registry, voxel) else {
+            continue;
+        };
+        let material = if registry.is_translucent(voxel) {
+            mats.translucent.clone()
+        } else {
+            mats.material.clone()
+        };
+        commands.spawn((
+            MaterialMeshBundle {
+                mesh: meshes.add(mesh),
+                material,
+                transform: Transform::from_translation(pos.as_vec3()),
+                ..default()
+            },
+            FallingBlock::new(voxel),
+        ));
+    }
+}
+
+/// Puts falling blocks back into the world on exit, before chunks are saved
+pub fn falling_block_exit_system(
+    mut commands: Commands,
+    mut vox_world: ResMut<VoxelWorldProcedural>,
+    mut exit: EventReader<AppExit>,
+    falling: Query<(Entity, &FallingBlock, &Transform)>,
+) {
+    if exit.iter().count() == 0 {
+        return;
+    }
+    for (entity, block, transform) in falling.iter() {
+        block.settle(transform.translation, &*vox_world);
+        commands.entity(entity).despawn();
+    }
+    vox_world.apply_voxel_changes();
+}
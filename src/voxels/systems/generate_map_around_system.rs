@@ -3,13 +3,11 @@ use crate::{
     game_config::RuntimeGameConfig,
     voxels::{
         chunk::{ChunkPosition, CHSIZEF, CHSIZEI},
-        resources::EntityChunks,
+        generation_tasks::GenerationTasks,
         world::VoxelWorldProcedural,
     },
 };
-use bevy::prelude::{
-    Color, Commands, Entity, IVec3, PbrBundle, Query, Res, ResMut, Transform, Vec3, With,
-};
+use bevy::prelude::{Color, Commands, Entity, IVec3, Query, Res, ResMut, Transform, Vec3, With};
 use bevy_prototype_debug_lines::DebugShapes;
 
 use super::{
@@ -17,9 +15,10 @@ use super::{
     components::{EdgeChunk, GenerateMapAround},
 };
 
+/// Requests generation of chunks around loaders, see `chunk_generation_system`
 pub fn generate_map_around_system(
-    mut vox_world: ResMut<VoxelWorldProcedural>,
-    mut ent_chunks: ResMut<EntityChunks>,
+    vox_world: Res<VoxelWorldProcedural>,
+    mut tasks: ResMut<GenerationTasks>,
     config: Res<RuntimeGameConfig>,
     loaders: Query<&Transform, (With<GenerateMapAround>,)>,
     edge_chunks: Query<(Entity, &ChunkPosition), (With<EdgeChunk>,)>,
@@ -48,9 +47,8 @@ pub fn generate_map_around_system(
                     &loaders,
                     edge_chunk_pos,
                     &config,
-                    &mut vox_world,
-                    &mut ent_chunks,
-                    &mut commands,
+                    &vox_world,
+                    &mut tasks,
                     &mut chunks_generated,
                 )
             }
@@ -65,7 +63,7 @@ pub fn generate_map_around_system(
 
         // chunk loader currently occupies MUST be generated
         if vox_world.get_chunk_at(&curr_chpos).is_none() {
-            create_chunk(&mut vox_world, &mut ent_chunks, curr_chpos, &mut commands);
+            tasks.request(curr_chpos);
         };
     }
 
@@ -73,7 +71,7 @@ pub fn generate_map_around_system(
         .chunk_changes()
         .pin()
         .iter()
-        .filter(|(_, changes)| changes.lock().unwrap().len() > 0)
+        .filter(|(_, changes)| !changes.lock().unwrap().is_empty())
         .flat_map(|(pos, _1)| {
             std::iter::once(pos.pos)
                 .chain(Directions::all().into_iter().map(|d| pos.pos + d.to_ivec()))
@@ -81,7 +79,7 @@ pub fn generate_map_around_system(
         .collect::<Vec<_>>();
     for chpos in pos_with_changes {
         if vox_world.get_chunk_at(&chpos.into()).is_none() {
-            create_chunk(&mut vox_world, &mut ent_chunks, chpos.into(), &mut commands);
+            tasks.request(chpos.into());
         };
     }
 }
@@ -90,9 +88,8 @@ fn generate_chunks_on_edge(
     loaders: &Query<&Transform, (With<GenerateMapAround>,)>,
     edge_chunk_pos: IVec3,
     config: &RuntimeGameConfig,
-    vox_world: &mut VoxelWorldProcedural,
-    ent_chunks: &mut EntityChunks,
-    commands: &mut Commands,
+    vox_world: &VoxelWorldProcedural,
+    tasks: &mut GenerationTasks,
     chunks_generated: &mut usize,
 ) {
     for transform in loaders.iter() {
@@ -100,44 +97,20 @@ fn generate_chunks_on_edge(
 
         let may_neighbours_mesh = || may_neighbours_produce_mesh(vox_world, edge_chunk_pos);
         if (curr_chpos.pos - edge_chunk_pos).as_vec3().length() as usize <= 2 {
-            create_chunk(vox_world, ent_chunks, edge_chunk_pos.into(), commands);
+            tasks.request(edge_chunk_pos.into());
         } else if (curr_chpos.pos - edge_chunk_pos).as_vec3().length() as usize
             <= config.config.render_around_bubble
         {
             if may_neighbours_mesh() {
-                create_chunk(vox_world, ent_chunks, edge_chunk_pos.into(), commands);
+                tasks.request(edge_chunk_pos.into());
             }
         } else if (curr_chpos.pos - edge_chunk_pos).as_vec3().length() as usize
             <= config.config.generate_around_bubble
             && *chunks_generated < config.chunks_generate_per_frame as usize
             && may_neighbours_mesh()
+            && tasks.request(edge_chunk_pos.into())
         {
-            create_chunk(vox_world, ent_chunks, edge_chunk_pos.into(), commands);
             *chunks_generated += 1;
         }
     }
 }
-
-fn create_chunk(
-    vox_world: &mut VoxelWorldProcedural,
-    ent_chunks: &mut EntityChunks,
-    chpos: ChunkPosition,
-    commands: &mut Commands,
-) {
-    let new_chunk = vox_world.gen_chunk(&chpos);
-    vox_world.insert_at(&chpos, new_chunk);
-    let ent = commands
-        .spawn((
-            chpos,
-            EdgeChunk,
-            PbrBundle {
-                transform: Transform {
-                    translation: (chpos.pos * CHSIZEI).as_vec3(),
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-        ))
-        .id();
-    ent_chunks.map.insert(chpos, ent);
-}
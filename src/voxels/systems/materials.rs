@@ -1,6 +1,10 @@
-use bevy::prelude::{Handle, Resource, StandardMaterial};
+use bevy::prelude::{Handle, Resource};
+
+use crate::voxels::chunk_material::ChunkMaterial;
 
 #[derive(Debug, Clone, Resource)]
 pub struct Materials {
-    pub material: Handle<StandardMaterial>,
+    pub material: Handle<ChunkMaterial>,
+    /// Alpha blended material of translucent chunk parts
+    pub translucent: Handle<ChunkMaterial>,
 }
@@ -1,7 +1,34 @@
-use bevy::prelude::ResMut;
+use bevy::prelude::{Query, Res, ResMut, With};
 
-use crate::voxels::world::VoxelWorldProcedural;
+use crate::{
+    directions::Directions,
+    voxels::{chunk::ChunkPosition, resources::EntityChunks, world::VoxelWorldProcedural},
+};
 
-pub fn world_apply_changes_system(mut vox_world: ResMut<VoxelWorldProcedural>) {
+use super::components::RenderedTag;
+
+pub fn world_apply_changes_system(
+    mut vox_world: ResMut<VoxelWorldProcedural>,
+    ent_chunks: Res<EntityChunks>,
+    rendered: Query<(), With<RenderedTag>>,
+) {
     vox_world.apply_voxel_changes();
+
+    // only existing meshes need new light, meshing needs every neighbour loaded
+    let light_changed = vox_world.take_light_changed();
+    let dirty = vox_world.dirty().pin();
+    for chpos in light_changed {
+        let is_rendered = ent_chunks
+            .map
+            .get(&chpos)
+            .is_some_and(|&ent| rendered.contains(ent));
+        let neighbours_loaded = Directions::all().into_iter().all(|d| {
+            vox_world
+                .get_chunk_at(&ChunkPosition::new(chpos.pos + d.to_ivec()))
+                .is_some()
+        });
+        if is_rendered && neighbours_loaded {
+            dirty.insert(chpos);
+        }
+    }
 }
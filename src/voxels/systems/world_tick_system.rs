@@ -0,0 +1,34 @@
+use bevy::prelude::{Query, Res, ResMut, Transform, With};
+
+use crate::{
+    game_config::RuntimeGameConfig,
+    voxels::{
+        block_ticks::BlockTicks, chunk::CHSIZE, fluids::FluidSimulation,
+        world::VoxelWorldProcedural,
+    },
+};
+
+use super::components::GenerateMapAround;
+
+/// Runs a world tick on the fixed timestep: block behaviors for voxels changed since the last tick,
+/// due scheduled updates and random ticks around loaders, and the fluid simulation every few ticks
+pub fn world_tick_system(
+    mut vox_world: ResMut<VoxelWorldProcedural>,
+    mut ticks: ResMut<BlockTicks>,
+    mut fluids: ResMut<FluidSimulation>,
+    config: Res<RuntimeGameConfig>,
+    loaders: Query<&Transform, With<GenerateMapAround>>,
+) {
+    let changed = vox_world.take_voxels_changed();
+    let loaders: Vec<_> = loaders
+        .iter()
+        .map(|transform| VoxelWorldProcedural::to_ch_pos_index(&transform.translation).0)
+        .collect();
+    ticks.step::<CHSIZE>(&*vox_world, &changed, &loaders);
+
+    fluids.wake(changed);
+    let fluid_interval = config.config.fluid_tick_interval.max(1) as u64;
+    if ticks.tick().is_multiple_of(fluid_interval) {
+        fluids.step(&vox_world);
+    }
+}
@@ -1,15 +1,64 @@
-use super::{chunk::ChunkPosition, voxel::Voxel};
-use bevy::{math::Vec3Swizzles, prelude::IVec2};
+use std::sync::Arc;
+
+use super::{
+    biomes::BiomeRegistry,
+    caves::{CaveCarver, CaveConfig},
+    chunk::{Chunk, ChunkPosition},
+    generation_stages::DecorationRegion,
+    underground::Underground,
+    vegetation,
+    voxel::Voxel,
+};
+use bevy::{
+    math::{DVec2, Vec3Swizzles},
+    prelude::{IVec2, IVec3},
+};
 
 use ndarray::prelude::*;
 use noise::{Fbm, NoiseFn, Perlin};
+use rand::{rngs::SmallRng, SeedableRng};
 
+/// Generates chunks in stages, see `GenerationStage`
 pub trait VoxelGenerator<const N: usize> {
+    /// Terrain stage, fills the chunk in isolation
     fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>);
+
+    /// Carving stage, runs on the chunk's own terrain
+    fn carve(&self, _pos: &ChunkPosition, _arr: &mut Array3<Voxel>) {}
+
+    /// Decoration stage, runs when all neighbours are carved and may write into them
+    fn decorate(&self, _region: &mut DecorationRegion<N>) {}
+
+    /// Runs the stages which don't depend on neighbours, up to carving
+    fn generate(&self, pos: &ChunkPosition) -> Chunk<N> {
+        let mut chunk = Chunk::new();
+        self.fill_random(pos, chunk.data_mut());
+        self.carve(pos, chunk.data_mut());
+        chunk
+    }
+}
+
+/// Rng seeded from the world seed and integer coordinates,
+/// so features at the same position are generated the same way in every chunk
+pub fn position_rng(seed: u32, values: impl IntoIterator<Item = i32>) -> SmallRng {
+    let mut hash = seed as u64;
+    for value in values {
+        hash = (hash ^ value as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        hash ^= hash >> 29;
+    }
+    SmallRng::seed_from_u64(hash)
 }
 
 pub struct ProceduralGenerator<const N: usize> {
     rng: Fbm<Perlin>,
+    temperature: Fbm<Perlin>,
+    humidity: Fbm<Perlin>,
+    biomes: Arc<BiomeRegistry>,
+    caves: Option<CaveCarver>,
+    underground: Arc<Underground>,
+    /// Height below which empty voxels are filled with the voxel
+    sea: Option<(i32, Voxel)>,
+    seed: u32,
 }
 
 impl<const N: usize> Default for ProceduralGenerator<N> {
@@ -18,35 +67,346 @@ impl<const N: usize> Default for ProceduralGenerator<N> {
     }
 }
 
+/// Terrain column at some horizontal position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    /// Voxels with y above this are air
+    pub height: f64,
+    /// Index of the biome in the registry
+    pub biome: usize,
+}
+
 impl<const N: usize> ProceduralGenerator<N> {
     const NI: i32 = N as i32;
 
+    /// Generator with the default single biome
     pub fn new(seed: u32) -> Self {
+        Self::with_biomes(seed, Arc::new(BiomeRegistry::default()))
+    }
+
+    pub fn with_biomes(seed: u32, biomes: Arc<BiomeRegistry>) -> Self {
         Self {
             rng: Fbm::new(seed),
+            temperature: Fbm::new(seed.wrapping_add(1000)),
+            humidity: Fbm::new(seed.wrapping_add(2000)),
+            biomes,
+            caves: None,
+            underground: Default::default(),
+            sea: None,
+            seed,
+        }
+    }
+
+    /// Carves caves below the heightmap
+    pub fn with_caves(mut self, config: CaveConfig) -> Self {
+        self.caves = Some(CaveCarver::new(self.seed, config));
+        self
+    }
+
+    /// Fills the underground with strata and ore veins
+    pub fn with_underground(mut self, underground: Arc<Underground>) -> Self {
+        self.underground = underground;
+        self
+    }
+
+    /// Fills empty voxels below `level` with `water`, vegetation doesn't grow under it
+    pub fn with_sea_level(mut self, level: i32, water: Voxel) -> Self {
+        self.sea = Some((level, water));
+        self
+    }
+
+    pub fn biomes(&self) -> &BiomeRegistry {
+        &self.biomes
+    }
+
+    pub fn column(&self, p: IVec2) -> Column {
+        let climate_point = [
+            p.x as f64 / self.biomes.climate_scale,
+            p.y as f64 / self.biomes.climate_scale,
+        ];
+        let climate = DVec2::new(
+            self.temperature.get(climate_point),
+            self.humidity.get(climate_point),
+        );
+        let value = self.rng.get([
+            p.x as f64 / self.biomes.height_scale,
+            p.y as f64 / self.biomes.height_scale,
+        ]);
+
+        Column {
+            height: self.biomes.blended_height(climate, value),
+            biome: self.biomes.nearest(climate),
         }
     }
 }
 
 impl<const N: usize> VoxelGenerator<N> for ProceduralGenerator<N> {
     fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
-        //let mut filled = 0;
         for x in 0..Self::NI {
             for z in 0..Self::NI {
                 let p = IVec2::from([x, z]);
-                let p = p + pos.pos.xz() * Self::NI;
-                let value = self.rng.get([p.x as f64 / 100., p.y as f64 / 100.]);
+                let column = self.column(p + pos.pos.xz() * Self::NI);
+                let biome = &self.biomes.biomes()[column.biome];
                 for y in 0..Self::NI {
-                    let height = y + pos.pos[1] * Self::NI;
-                    arr[(x as usize, y as usize, z as usize)] = match value {
-                        value if height as f64 + 5. > value * 10. => Voxel { id: 0 },
-                        _ => {
-                            //filled += 1;
-                            Voxel { id: 1 }
+                    let height = (y + pos.pos[1] * Self::NI) as f64;
+                    let depth = column.height - height;
+                    arr[(x as usize, y as usize, z as usize)] = if depth < 0. {
+                        match self.sea {
+                            Some((level, water)) if height < level as f64 => water,
+                            _ => Voxel { id: 0 },
                         }
+                    } else if depth as u32 > biome.subsurface_depth {
+                        self.underground
+                            .stratum_at(depth)
+                            .unwrap_or(biome.underground)
+                    } else {
+                        // the topmost voxel of the column has depth 0
+                        biome.block_at_depth(depth as u32)
                     };
                 }
             }
         }
+        self.underground.place_ores::<N>(
+            self.seed,
+            pos.pos * Self::NI,
+            |p| self.column(p).height,
+            arr,
+        );
+    }
+
+    fn carve(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+        let Some(caves) = &self.caves else {
+            return;
+        };
+        let heights = Array2::from_shape_fn((N, N), |(x, z)| {
+            let p = IVec2::new(x as i32, z as i32);
+            self.column(p + pos.pos.xz() * Self::NI).height
+        });
+        caves.carve::<N>(pos.pos * Self::NI, &heights, arr);
+    }
+
+    fn decorate(&self, region: &mut DecorationRegion<N>) {
+        let origin = region.origin();
+        for x in 0..N {
+            for z in 0..N {
+                let p = origin.xz() + IVec2::new(x as i32, z as i32);
+                let column = self.column(p);
+                let surface = IVec3::new(p.x, column.height.floor() as i32, p.y);
+                // surfaces of other chunks are decorated by them
+                if !(origin.y..origin.y + Self::NI).contains(&surface.y) {
+                    continue;
+                }
+                if self.sea.is_some_and(|(level, _)| surface.y + 1 < level) {
+                    continue;
+                }
+                let biome = &self.biomes.biomes()[column.biome];
+                // skip columns carved out by caves
+                if region.get(surface) != Some(biome.surface) {
+                    continue;
+                }
+                let mut rng = vegetation::column_rng(self.seed, &region.center(), x, z);
+                vegetation::decorate_surface(
+                    &self.biomes,
+                    &biome.vegetation,
+                    region,
+                    surface,
+                    &mut rng,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::{
+        biomes::BiomesConfig, block_registry::BlockRegistry, chunk::Chunk, world::VoxelWorld,
+    };
+    use bevy::prelude::IVec3;
+
+    const SMALLCH: usize = 16;
+
+    fn config_generator(seed: u32) -> ProceduralGenerator<SMALLCH> {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let biomes = BiomeRegistry::from_file_ron("config/biomes.ron", &blocks).unwrap();
+        let underground = Underground::from_file_ron("config/underground.ron", &blocks).unwrap();
+        ProceduralGenerator::with_biomes(seed, Arc::new(biomes))
+            .with_underground(Arc::new(underground))
+    }
+
+    fn generate(gen: &ProceduralGenerator<SMALLCH>, pos: IVec3) -> Chunk<SMALLCH> {
+        gen.generate(&ChunkPosition::new(pos))
+    }
+
+    #[test]
+    fn deterministic_for_seed() {
+        let pos = IVec3::new(3, -1, -7);
+
+        let first = generate(&config_generator(7), pos);
+        let second = generate(&config_generator(7), pos);
+        let other_seed = generate(&config_generator(8), pos);
+
+        assert_eq!(first.data(), second.data());
+        assert_ne!(first.data(), other_seed.data());
+    }
+
+    #[test]
+    fn biome_borders_are_blended() {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let mut config: BiomesConfig =
+            ron::from_str(&std::fs::read_to_string("config/biomes.ron").unwrap()).unwrap();
+        // two flat biomes so that height only changes at borders
+        config.biomes.truncate(2);
+        config.biomes[0].height_curve = vec![(0., 0.)];
+        config.biomes[1].height_curve = vec![(0., 10.)];
+        let biomes = BiomeRegistry::new(config, &blocks).unwrap();
+        let gen = ProceduralGenerator::<SMALLCH>::with_biomes(42, Arc::new(biomes));
+
+        let columns = (0..20_000)
+            .map(|x| gen.column(IVec2::new(x, 0)))
+            .collect::<Vec<_>>();
+
+        let borders = columns
+            .windows(2)
+            .filter(|pair| pair[0].biome != pair[1].biome)
+            .count();
+        assert!(borders > 0, "no biome borders generated");
+        assert!(columns.iter().any(|c| c.height > 1. && c.height < 9.));
+        for pair in columns.windows(2) {
+            assert!(
+                (pair[0].height - pair[1].height).abs() < 2.,
+                "height jumps between {pair:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn surface_uses_biome_blocks() {
+        let gen = config_generator(42);
+
+        for x in (0..4000).step_by(97) {
+            let p = IVec2::new(x, 0);
+            let column = gen.column(p);
+            let biome = &gen.biomes().biomes()[column.biome];
+            let top = column.height.floor() as i32;
+            let n = SMALLCH as i32;
+            let chunk_pos = IVec3::new(p.x.div_euclid(n), top.div_euclid(n), 0);
+            let chunk = generate(&gen, chunk_pos);
+            let local = [p.x.rem_euclid(n), top.rem_euclid(n), 0].map(|v| v as usize);
+
+            assert_eq!(chunk.data()[local], biome.surface);
+        }
+    }
+
+    #[test]
+    fn sea_fills_air_below_level() {
+        const SEA: i32 = 5;
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let water = Voxel {
+            id: blocks.id_of("water").unwrap(),
+        };
+        let gen = config_generator(42).with_sea_level(SEA, water);
+
+        let mut flooded = 0;
+        for x in (0..4000).step_by(211) {
+            let p = IVec2::new(x, 0);
+            let height = gen.column(p).height;
+            let n = SMALLCH as i32;
+            for chunk_y in -1..=1 {
+                let chunk = generate(&gen, IVec3::new(p.x.div_euclid(n), chunk_y, 0));
+                for y in 0..SMALLCH {
+                    let world_y = chunk_y * n + y as i32;
+                    let vox = chunk.data()[[p.x.rem_euclid(n) as usize, y, 0]];
+                    if world_y as f64 > height {
+                        assert_eq!(vox == water, world_y < SEA, "{x} {world_y} {height}");
+                        flooded += (vox == water) as usize;
+                    } else {
+                        assert_ne!(vox, water);
+                    }
+                }
+            }
+        }
+        assert!(flooded > 0);
+    }
+
+    #[test]
+    fn caves_carved_below_min_depth() {
+        let plain = config_generator(42);
+        let caves = config_generator(42).with_caves(CaveConfig::default());
+        let min_depth = CaveConfig::default().min_depth;
+
+        let mut carved = 0;
+        for y in -6..1 {
+            let pos = IVec3::new(0, y, 0);
+            let plain_chunk = generate(&plain, pos);
+            let caves_chunk = generate(&caves, pos);
+
+            for ((x, y, z), vox) in caves_chunk.data().indexed_iter() {
+                let plain_vox = plain_chunk.data()[[x, y, z]];
+                if *vox == plain_vox {
+                    continue;
+                }
+                let world_pos = IVec3::new(x as i32, y as i32, z as i32) + pos * SMALLCH as i32;
+                let column = plain.column(IVec2::new(world_pos.x, world_pos.z));
+                assert_eq!(vox.id, 0);
+                assert!(column.height - world_pos.y as f64 >= min_depth);
+                carved += 1;
+            }
+        }
+        assert!(carved > 0);
+    }
+
+    #[test]
+    fn deep_chunks_stratified_with_ores() {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let gen = config_generator(42);
+
+        let deep = generate(&gen, IVec3::new(0, -10, 0));
+
+        let count = |name| {
+            let id = blocks.id_of(name).unwrap();
+            deep.data().iter().filter(|vox| vox.id == id).count()
+        };
+        assert_eq!(count("stone"), 0);
+        assert!(count("deep_stone") > 0);
+        assert!(count("coal_ore") + count("iron_ore") + count("gold_ore") > 0);
+    }
+
+    #[test]
+    fn vegetation_independent_of_generation_order() {
+        let blocks = Arc::new(BlockRegistry::from_file_ron("config/blocks.ron").unwrap());
+        let positions = (0..6)
+            .flat_map(|x| (-1..=0).map(move |y| ChunkPosition::new(IVec3::new(x, y, 0))))
+            .collect::<Vec<_>>();
+        let generate_world = |order: &mut dyn Iterator<Item = &ChunkPosition>| {
+            let mut world = VoxelWorld::new(config_generator(42), blocks.clone());
+            for pos in order {
+                world.complete_chunk(pos, None);
+            }
+            world
+        };
+
+        let forward = generate_world(&mut positions.iter());
+        let backward = generate_world(&mut positions.iter().rev());
+
+        let id = |name| blocks.id_of(name).unwrap();
+        let (mut logs, mut plants) = (0, 0);
+        for pos in &positions {
+            let chunk = forward.chunk_at(pos);
+            assert_eq!(chunk.data(), backward.chunk_at(pos).data(), "{pos:?}");
+            for ((x, y, z), vox) in chunk.data().indexed_iter() {
+                if vox.id == id("log") {
+                    logs += 1;
+                } else if vox.id == id("tall_grass") || vox.id == id("flower") {
+                    plants += 1;
+                    if y > 0 {
+                        assert!(blocks.is_solid(chunk.data()[[x, y - 1, z]]));
+                    }
+                }
+            }
+        }
+        assert!(logs > 0);
+        assert!(plants > 0);
     }
 }
@@ -0,0 +1,69 @@
+//! Fixtures shared by tests of block behaviors, fluids and falling blocks
+
+use std::sync::Arc;
+
+use bevy::prelude::IVec3;
+use ndarray::Array3;
+
+use super::{
+    block_registry::BlockRegistry, block_ticks::VoxelAccess, chunk::ChunkPosition,
+    terrain_generation::VoxelGenerator, voxel::Voxel, world::VoxelWorld,
+};
+
+pub const N: usize = 16;
+
+/// Fills the bottom layer of chunks at y = 0, the world's y = 0
+pub struct FloorGenerator {
+    floor: Voxel,
+}
+
+impl VoxelGenerator<N> for FloorGenerator {
+    fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+        if pos.pos.y == 0 {
+            arr.indexed_iter_mut()
+                .filter(|((_, y, _), _)| *y == 0)
+                .for_each(|(_, v)| *v = self.floor);
+        }
+    }
+}
+
+pub type FloorWorld = VoxelWorld<FloorGenerator, N>;
+
+pub fn registry() -> BlockRegistry {
+    BlockRegistry::from_file_ron("config/blocks.ron").unwrap()
+}
+
+/// Voxel of the block with the name in the registry
+pub fn named(registry: &BlockRegistry, name: &str) -> Voxel {
+    Voxel {
+        id: registry.id_of(name).unwrap(),
+    }
+}
+
+/// 3x3x3 chunks around the origin, loaded from -N to 2N on every axis, with a floor
+/// of the named block at y = 0
+pub fn world_with_floor(floor: &str) -> FloorWorld {
+    let registry = registry();
+    let floor = named(&registry, floor);
+    let mut world = VoxelWorld::new(FloorGenerator { floor }, Arc::new(registry));
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let pos = ChunkPosition::new(IVec3::new(x, y, z));
+                let chunk = world.gen_chunk(&pos);
+                world.insert_at(&pos, chunk);
+            }
+        }
+    }
+    world
+}
+
+/// `world_with_floor` with a stone floor
+pub fn floor_world() -> FloorWorld {
+    world_with_floor("stone")
+}
+
+/// Loaded voxel at the world position
+pub fn voxel(world: &dyn VoxelAccess, pos: IVec3) -> Voxel {
+    world.voxel(pos).unwrap()
+}
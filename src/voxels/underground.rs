@@ -0,0 +1,346 @@
+use std::{collections::HashSet, path::Path};
+
+use bevy::prelude::{IVec2, IVec3};
+use ndarray::Array3;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{self, Error};
+
+use super::{block_registry::BlockRegistry, terrain_generation::position_rng, voxel::Voxel};
+
+/// Side of the cubic cells veins are scattered in
+pub const ORE_CELL: i32 = 16;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StratumDescriptor {
+    pub block: String,
+    /// Voxels this many voxels or more below the surface belong to the stratum,
+    /// unless a deeper stratum starts above them
+    pub min_depth: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OreDescriptor {
+    pub block: String,
+    /// Average number of veins in a cell of `ORE_CELL`³ voxels
+    pub frequency: f64,
+    /// Inclusive range of voxels in a vein
+    pub vein_size: (u32, u32),
+    /// Range of depths below the surface vein centers are placed at
+    pub min_depth: f64,
+    pub max_depth: f64,
+    /// Block names veins replace, other blocks are left intact
+    pub replaces: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UndergroundConfig {
+    #[serde(default)]
+    pub strata: Vec<StratumDescriptor>,
+    #[serde(default)]
+    pub ores: Vec<OreDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stratum {
+    pub block: Voxel,
+    pub min_depth: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ore {
+    pub block: Voxel,
+    pub frequency: f64,
+    pub vein_size: (u32, u32),
+    pub min_depth: f64,
+    pub max_depth: f64,
+    pub replaces: Vec<Voxel>,
+}
+
+/// Strata below biomes' subsurface layers and ore veins in them, with block names resolved to voxels
+#[derive(Debug, Clone, Default)]
+pub struct Underground {
+    /// Sorted by depth, deepest first
+    strata: Vec<Stratum>,
+    ores: Vec<Ore>,
+}
+
+impl Underground {
+    pub fn new(config: UndergroundConfig, blocks: &BlockRegistry) -> error::Result<Self> {
+        let block = |name: &str| {
+            blocks
+                .id_of(name)
+                .map(|id| Voxel { id })
+                .ok_or_else(|| Error::InvalidUnderground(format!("unknown block {name}")))
+        };
+
+        let mut strata = config
+            .strata
+            .iter()
+            .map(|desc| {
+                Ok(Stratum {
+                    block: block(&desc.block)?,
+                    min_depth: desc.min_depth,
+                })
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+        strata.sort_by(|a, b| b.min_depth.total_cmp(&a.min_depth));
+
+        let ores = config
+            .ores
+            .iter()
+            .map(|desc| {
+                if desc.vein_size.0 == 0 || desc.vein_size.0 > desc.vein_size.1 {
+                    return Err(Error::InvalidUnderground(format!(
+                        "ore {} must have a non-empty vein size range above zero",
+                        desc.block
+                    )));
+                }
+                if desc.frequency < 0. || desc.min_depth > desc.max_depth {
+                    return Err(Error::InvalidUnderground(format!(
+                        "ore {} must have a non-negative frequency and a depth range",
+                        desc.block
+                    )));
+                }
+                Ok(Ore {
+                    block: block(&desc.block)?,
+                    frequency: desc.frequency,
+                    vein_size: desc.vein_size,
+                    min_depth: desc.min_depth,
+                    max_depth: desc.max_depth,
+                    replaces: desc
+                        .replaces
+                        .iter()
+                        .map(|name| block(name))
+                        .collect::<error::Result<_>>()?,
+                })
+            })
+            .collect::<error::Result<Vec<_>>>()?;
+
+        Ok(Self { strata, ores })
+    }
+
+    pub fn from_file_ron<P: AsRef<Path>>(path: P, blocks: &BlockRegistry) -> error::Result<Self> {
+        let str = std::fs::read_to_string(path)?;
+        Self::new(ron::from_str(str.as_ref())?, blocks)
+    }
+
+    pub fn ores(&self) -> &[Ore] {
+        &self.ores
+    }
+
+    /// Block of the deepest stratum starting above `depth`
+    pub fn stratum_at(&self, depth: f64) -> Option<Voxel> {
+        self.strata
+            .iter()
+            .find(|stratum| depth >= stratum.min_depth)
+            .map(|stratum| stratum.block)
+    }
+
+    /// Places ore veins into the chunk at `origin` in world voxels.
+    /// Veins are scattered per world cell so they continue across chunk borders
+    pub fn place_ores<const N: usize>(
+        &self,
+        seed: u32,
+        origin: IVec3,
+        surface_height: impl Fn(IVec2) -> f64,
+        arr: &mut Array3<Voxel>,
+    ) {
+        let max = origin + IVec3::splat(N as i32 - 1);
+        for (index, ore) in self.ores.iter().enumerate() {
+            // a vein can't reach further than its size from the center
+            let reach = ore.vein_size.1 as i32;
+            let cell_of = |pos: IVec3| IVec3::from(pos.to_array().map(|v| v.div_euclid(ORE_CELL)));
+            let min_cell = cell_of(origin - reach);
+            let max_cell = cell_of(max + reach);
+
+            for x in min_cell.x..=max_cell.x {
+                for y in min_cell.y..=max_cell.y {
+                    for z in min_cell.z..=max_cell.z {
+                        let cell = IVec3::new(x, y, z);
+                        let mut rng = position_rng(seed, [index as i32, x, y, z]);
+                        let mut veins = ore.frequency as u32;
+                        if rng.gen_bool(ore.frequency.fract()) {
+                            veins += 1;
+                        }
+                        for _ in 0..veins {
+                            let center = cell * ORE_CELL
+                                + IVec3::new(
+                                    rng.gen_range(0..ORE_CELL),
+                                    rng.gen_range(0..ORE_CELL),
+                                    rng.gen_range(0..ORE_CELL),
+                                );
+                            let size = rng.gen_range(ore.vein_size.0..=ore.vein_size.1);
+
+                            let depth =
+                                surface_height(IVec2::new(center.x, center.z)) - center.y as f64;
+                            if depth < ore.min_depth || depth > ore.max_depth {
+                                continue;
+                            }
+                            for pos in grow_vein(center, size as usize, &mut rng) {
+                                if pos.cmplt(origin).any() || pos.cmpgt(max).any() {
+                                    continue;
+                                }
+                                let local = (pos - origin).to_array().map(|v| v as usize);
+                                if ore.replaces.contains(&arr[local]) {
+                                    arr[local] = ore.block;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Blob of `size` connected voxels grown from `center` by attaching voxels next to random ones
+fn grow_vein(center: IVec3, size: usize, rng: &mut impl Rng) -> Vec<IVec3> {
+    const STEPS: [IVec3; 6] = [
+        IVec3::X,
+        IVec3::NEG_X,
+        IVec3::Y,
+        IVec3::NEG_Y,
+        IVec3::Z,
+        IVec3::NEG_Z,
+    ];
+    let mut vein = vec![center];
+    let mut taken = HashSet::from([center]);
+    while vein.len() < size {
+        let next = vein[rng.gen_range(0..vein.len())] + STEPS[rng.gen_range(0..STEPS.len())];
+        if taken.insert(next) {
+            vein.push(next);
+        }
+    }
+    vein
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_underground() -> (BlockRegistry, Underground) {
+        let blocks = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let underground = Underground::from_file_ron("config/underground.ron", &blocks).unwrap();
+        (blocks, underground)
+    }
+
+    #[test]
+    fn strata_by_depth() {
+        let (blocks, underground) = config_underground();
+        let deep_stone = Voxel {
+            id: blocks.id_of("deep_stone").unwrap(),
+        };
+        let stone = Voxel {
+            id: blocks.id_of("stone").unwrap(),
+        };
+
+        assert_eq!(underground.stratum_at(0.), None);
+        assert_eq!(underground.stratum_at(10.), Some(stone));
+        assert_eq!(underground.stratum_at(1000.), Some(deep_stone));
+    }
+
+    #[test]
+    fn unknown_block_rejected() {
+        let blocks = BlockRegistry::default();
+        let config = UndergroundConfig {
+            strata: vec![StratumDescriptor {
+                block: "nonexistent".to_owned(),
+                min_depth: 0.,
+            }],
+            ores: Vec::new(),
+        };
+
+        assert!(matches!(
+            Underground::new(config, &blocks),
+            Err(Error::InvalidUnderground(_))
+        ));
+    }
+
+    #[test]
+    fn veins_are_connected() {
+        let vein = grow_vein(IVec3::ZERO, 20, &mut position_rng(42, [0]));
+
+        assert_eq!(vein.len(), 20);
+        assert!(vein[1..]
+            .iter()
+            .all(|pos| vein.iter().any(|other| (*pos - *other)
+                .abs()
+                .to_array()
+                .iter()
+                .sum::<i32>()
+                == 1)));
+    }
+
+    #[test]
+    fn ore_distribution() {
+        const N: usize = 16;
+        const SURFACE: f64 = 0.;
+        let (blocks, underground) = config_underground();
+        let deep_stone = Voxel {
+            id: blocks.id_of("deep_stone").unwrap(),
+        };
+
+        let mut counts = vec![0; underground.ores().len()];
+        let mut volume = 0;
+        for x in 0..16 {
+            for z in 0..16 {
+                // depths from 72 to 120
+                for y in -8..-5 {
+                    let origin = IVec3::new(x, y, z) * N as i32;
+                    let mut arr = Array3::from_elem((N, N, N), deep_stone);
+                    underground.place_ores::<N>(42, origin, |_| SURFACE, &mut arr);
+
+                    volume += arr.len();
+                    for vox in arr.iter() {
+                        if let Some(i) = underground.ores().iter().position(|o| o.block == *vox) {
+                            counts[i] += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        let cells = volume as f64 / (ORE_CELL * ORE_CELL * ORE_CELL) as f64;
+        for (ore, count) in underground.ores().iter().zip(counts) {
+            assert!(ore.min_depth < 72. && ore.max_depth > 120.);
+            let mean_size = (ore.vein_size.0 + ore.vein_size.1) as f64 / 2.;
+            let expected = cells * ore.frequency * mean_size;
+            let ratio = count as f64 / expected;
+            assert!(
+                (0.7..1.3).contains(&ratio),
+                "{ore:?}: {count} voxels, {expected} expected"
+            );
+        }
+    }
+
+    #[test]
+    fn no_ores_outside_depth_range() {
+        const N: usize = 16;
+        let (blocks, underground) = config_underground();
+        let deep_stone = Voxel {
+            id: blocks.id_of("deep_stone").unwrap(),
+        };
+        let gold = underground
+            .ores()
+            .iter()
+            .find(|o| o.block.id == blocks.id_of("gold_ore").unwrap())
+            .unwrap();
+
+        for x in 0..8 {
+            for y in -4..0 {
+                let origin = IVec3::new(x, y, 0) * N as i32;
+                let mut arr = Array3::from_elem((N, N, N), deep_stone);
+                underground.place_ores::<N>(42, origin, |_| 0., &mut arr);
+
+                for ((_, y, _), vox) in arr.indexed_iter() {
+                    if *vox == gold.block {
+                        let depth = -(origin.y + y as i32) as f64;
+                        assert!(depth >= gold.min_depth - gold.vein_size.1 as f64);
+                    }
+                }
+            }
+        }
+    }
+}
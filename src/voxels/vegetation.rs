@@ -0,0 +1,85 @@
+use bevy::prelude::IVec3;
+use rand::{rngs::SmallRng, Rng};
+
+use super::{
+    biomes::{BiomeRegistry, TreeTemplate, Vegetation},
+    chunk::ChunkPosition,
+    generation_stages::DecorationRegion,
+    terrain_generation::position_rng,
+    voxel::Voxel,
+};
+
+/// Rng of the surface column at `x`, `z` within the chunk, the same every time the chunk is generated
+pub fn column_rng(seed: u32, chunk: &ChunkPosition, x: usize, z: usize) -> SmallRng {
+    position_rng(
+        seed,
+        [chunk.pos.x, chunk.pos.y, chunk.pos.z, x as i32, z as i32],
+    )
+}
+
+/// Grows vegetation on top of the `surface` voxel.
+/// Overlapping vegetation resolves the same regardless of which chunk is decorated first:
+/// trunks replace anything, leaves replace air and plants, plants only grow in air
+pub fn decorate_surface<const N: usize>(
+    biomes: &BiomeRegistry,
+    vegetation: &Vegetation,
+    region: &mut DecorationRegion<N>,
+    surface: IVec3,
+    rng: &mut SmallRng,
+) {
+    let above = surface + IVec3::Y;
+    let mut roll = rng.gen::<f64>();
+    if roll < vegetation.tree_chance && !vegetation.trees.is_empty() {
+        let tree = &biomes.trees()[vegetation.trees[rng.gen_range(0..vegetation.trees.len())]];
+        let replaceable = |vox: Voxel| vox.id == 0 || biomes.is_plant(vox);
+        grow_tree(tree, region, above, rng, replaceable);
+        return;
+    }
+    roll -= vegetation.tree_chance;
+
+    for &(plant, chance) in &vegetation.plants {
+        if roll < chance {
+            if region.get(above) == Some(Voxel { id: 0 }) {
+                region.set(above, plant);
+            }
+            return;
+        }
+        roll -= chance;
+    }
+}
+
+/// Places the trunk starting at `base` and a canopy of leaves around its top.
+/// Leaves only go where `replaceable` holds, parts outside of the region are cut off
+pub fn grow_tree<const N: usize>(
+    tree: &TreeTemplate,
+    region: &mut DecorationRegion<N>,
+    base: IVec3,
+    rng: &mut SmallRng,
+    replaceable: impl Fn(Voxel) -> bool,
+) {
+    let height = rng.gen_range(tree.trunk_height.0..=tree.trunk_height.1) as i32;
+    let top = base + IVec3::Y * (height - 1);
+    let radius = tree.canopy_radius as i32;
+
+    for dy in -2..=1 {
+        // the topmost layer is narrower
+        let r = if dy == 1 { (radius - 1).max(0) } else { radius };
+        for dx in -r..=r {
+            for dz in -r..=r {
+                let corner = r > 0 && dx.abs() == r && dz.abs() == r;
+                // corners are ragged
+                if corner && (dy == 1 || rng.gen_bool(0.5)) {
+                    continue;
+                }
+                let pos = top + IVec3::new(dx, dy, dz);
+                if region.get(pos).is_some_and(&replaceable) {
+                    region.set(pos, tree.leaves);
+                }
+            }
+        }
+    }
+
+    for y in 0..height {
+        region.set(base + IVec3::Y * y, tree.trunk);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bevy::{
+    prelude::{IVec3, Mat4, Resource},
+    render::primitives::{Aabb, Frustum},
+};
+
+use crate::directions::Directions;
+
+use super::chunk::ChunkPosition;
+
+/// Whether any part of the chunk at `pos` is inside the frustum
+pub fn chunk_in_frustum<const N: usize>(frustum: &Frustum, pos: &ChunkPosition) -> bool {
+    let min = (pos.pos * N as i32).as_vec3();
+    let aabb = Aabb::from_min_max(min, min + N as f32);
+    frustum.intersects_obb(&aabb, &Mat4::IDENTITY, true, false)
+}
+
+/// Which faces of a chunk are connected to each other through transparent voxels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceConnections([Directions; 6]);
+
+impl FaceConnections {
+    /// Every face connected to every other, assumed for chunks which weren't meshed
+    pub const ALL: Self = Self([Directions::all(); 6]);
+
+    /// Flood fills transparent voxels of a chunk with `N` voxels per side
+    /// connecting all faces each filled region touches
+    pub fn from_transparent<const N: usize>(is_transparent: impl Fn(IVec3) -> bool) -> Self {
+        let mut connections = [Directions::empty(); 6];
+        let mut visited = vec![false; N * N * N];
+        let index = |pos: IVec3| pos.x as usize + (pos.y as usize + pos.z as usize * N) * N;
+        let mut stack = Vec::new();
+        for start in (0..N * N * N)
+            .map(|i| IVec3::new((i % N) as i32, (i / N % N) as i32, (i / (N * N)) as i32))
+        {
+            if visited[index(start)] || !is_transparent(start) {
+                continue;
+            }
+            visited[index(start)] = true;
+            stack.push(start);
+
+            let mut touched = Directions::empty();
+            while let Some(pos) = stack.pop() {
+                for dir in Directions::all() {
+                    let next = pos + dir.to_ivec();
+                    if next.cmplt(IVec3::ZERO).any() || next.cmpge(IVec3::splat(N as i32)).any() {
+                        touched |= dir;
+                    } else if !visited[index(next)] && is_transparent(next) {
+                        visited[index(next)] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+            for face in touched {
+                connections[Self::slot(face)] |= touched;
+            }
+        }
+        Self(connections)
+    }
+
+    pub fn connected(&self, from: Directions, to: Directions) -> bool {
+        self.0[Self::slot(from)].contains(to)
+    }
+
+    fn slot(face: Directions) -> usize {
+        face.bits().trailing_zeros() as usize
+    }
+}
+
+/// Chunks cameras can see: a breadth-first search from the camera's chunk
+/// through chunks in the frustum, only passing through faces connected inside the chunk
+/// and never turning back towards the camera
+#[derive(Resource, Default)]
+pub struct ChunkVisibility {
+    connections: HashMap<ChunkPosition, FaceConnections>,
+    visible: HashSet<ChunkPosition>,
+}
+
+impl ChunkVisibility {
+    pub fn set_connections(&mut self, pos: ChunkPosition, connections: FaceConnections) {
+        self.connections.insert(pos, connections);
+    }
+
+    /// Forgets connections of chunks matching the predicate
+    pub fn forget(&mut self, mut predicate: impl FnMut(&ChunkPosition) -> bool) {
+        self.connections.retain(|pos, _| !predicate(pos));
+    }
+
+    pub fn is_visible(&self, pos: &ChunkPosition) -> bool {
+        self.visible.contains(pos)
+    }
+
+    /// Recomputes chunks seen from the given camera chunks and frusta, at most `radius` chunks away
+    pub fn update<const N: usize>(
+        &mut self,
+        views: impl IntoIterator<Item = (ChunkPosition, Frustum)>,
+        radius: usize,
+    ) {
+        self.visible.clear();
+        for (camera, frustum) in views {
+            // each camera walks on its own, chunks another camera reached may still lead further
+            let mut visited = HashSet::from([camera]);
+            // chunk, directions travelled to reach it and the face it was entered through
+            let mut queue = VecDeque::from([(camera, Directions::empty(), None)]);
+            while let Some((pos, travelled, entered)) = queue.pop_front() {
+                let connections = self
+                    .connections
+                    .get(&pos)
+                    .copied()
+                    .unwrap_or(FaceConnections::ALL);
+                for dir in Directions::all() {
+                    if travelled.contains(dir.invert()) {
+                        continue;
+                    }
+                    if entered.is_some_and(|face| !connections.connected(face, dir)) {
+                        continue;
+                    }
+                    let next = ChunkPosition::new(pos.pos + dir.to_ivec());
+                    if (next.pos - camera.pos).as_vec3().length() as usize > radius
+                        || visited.contains(&next)
+                        || !chunk_in_frustum::<N>(&frustum, &next)
+                    {
+                        continue;
+                    }
+                    visited.insert(next);
+                    queue.push_back((next, travelled | dir, Some(dir.invert())));
+                }
+            }
+            self.visible.extend(visited);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::{Transform, Vec3};
+
+    const N: usize = 16;
+
+    /// Frustum of a camera in the middle of the chunk looking along `direction`
+    fn looking(chunk: ChunkPosition, direction: Vec3) -> Frustum {
+        let projection = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1., 0.1, 1000.);
+        let eye = (chunk.pos * N as i32).as_vec3() + N as f32 / 2.;
+        let view = Transform::from_translation(eye).looking_to(direction, Vec3::Y);
+        Frustum::from_view_projection(&(projection * view.compute_matrix().inverse()))
+    }
+
+    fn looking_north() -> Frustum {
+        looking(ChunkPosition::default(), Vec3::NEG_Z)
+    }
+
+    #[test]
+    fn tunnel_connects_its_ends() {
+        let connections = FaceConnections::from_transparent::<N>(|pos| pos.y == 1 && pos.z == 1);
+
+        assert!(connections.connected(Directions::WEST, Directions::EAST));
+        assert!(connections.connected(Directions::EAST, Directions::WEST));
+        assert!(!connections.connected(Directions::WEST, Directions::UP));
+        assert!(!connections.connected(Directions::UP, Directions::DOWN));
+    }
+
+    #[test]
+    fn solid_and_empty_chunks() {
+        let solid = FaceConnections::from_transparent::<N>(|_| false);
+        let empty = FaceConnections::from_transparent::<N>(|_| true);
+
+        assert!(Directions::all()
+            .into_iter()
+            .all(|dir| !solid.connected(dir, dir.invert())));
+        assert_eq!(empty, FaceConnections::ALL);
+    }
+
+    #[test]
+    fn chunks_behind_walls_hidden() {
+        let mut visibility = ChunkVisibility::default();
+        let chunk = |x, y, z| ChunkPosition::new(IVec3::new(x, y, z));
+        let wall = FaceConnections::from_transparent::<N>(|_| false);
+        for x in -3..=3 {
+            for y in -3..=3 {
+                visibility.set_connections(chunk(x, y, -2), wall);
+            }
+        }
+
+        visibility.update::<N>([(chunk(0, 0, 0), looking_north())], 8);
+
+        assert!(visibility.is_visible(&chunk(0, 0, -1)));
+        assert!(visibility.is_visible(&chunk(0, 0, -2)));
+        assert!(!visibility.is_visible(&chunk(0, 0, -3)));
+        // behind the camera
+        assert!(!visibility.is_visible(&chunk(0, 0, 2)));
+
+        visibility.forget(|pos| pos.pos.z == -2);
+        visibility.update::<N>([(chunk(0, 0, 0), looking_north())], 8);
+        assert!(visibility.is_visible(&chunk(0, 0, -3)));
+    }
+
+    #[test]
+    fn cameras_walk_independently() {
+        let mut visibility = ChunkVisibility::default();
+        let chunk = |x, y, z| ChunkPosition::new(IVec3::new(x, y, z));
+        let east_camera = (chunk(0, 0, 0), looking(chunk(0, 0, 0), Vec3::X));
+        let north_camera = (chunk(2, 0, 2), looking(chunk(2, 0, 2), Vec3::NEG_Z));
+
+        visibility.update::<N>([east_camera, north_camera], 8);
+
+        assert!(visibility.is_visible(&chunk(6, 0, 0)));
+        // only reachable through chunks the east camera sees
+        assert!(visibility.is_visible(&chunk(2, 0, -5)));
+    }
+}
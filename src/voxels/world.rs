@@ -1,40 +1,80 @@
 use super::{
+    block_registry::BlockRegistry,
     chunk::{Chunk, ChunkPosition, CHSIZE},
-    chunk_mesh::ChunkMeshData,
+    chunk_mesh::{ChunkMeshes, MeshingMode},
+    generation_stages::{self, DecorationRegion, GenerationStage, ProtoChunk},
+    light::{LightLevel, LightPropagator},
+    mesh_snapshot::MeshSnapshot,
+    region_storage::RegionStorage,
     terrain_generation::{ProceduralGenerator, VoxelGenerator},
     voxel::Voxel,
 };
-use crate::{
-    core::{ConvertVecExtension, VecExtensions},
-    directions::Directions,
-};
+use crate::{core::ConvertVecExtension, directions::Directions, error};
 use bevy::prelude::{IVec3, Resource, Vec3};
 
 use std::{
-    collections::{HashMap, VecDeque},
-    sync::Mutex,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
 };
 
 #[derive(Debug, Copy, Clone)]
 pub struct VoxChange {
     pub new_vox: Voxel,
     pub index: [usize; 3],
+    /// Made by a player, the chunk is meshed before others
+    pub by_player: bool,
 }
 
 impl VoxChange {
     pub fn new(index: [usize; 3], new_vox: Voxel) -> Self {
-        Self { new_vox, index }
+        Self {
+            new_vox,
+            index,
+            by_player: false,
+        }
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct RaycastHit {
+    pub chunk: ChunkPosition,
+    pub index: [usize; 3],
+    pub voxel: Voxel,
+    /// Face of the hit voxel the ray entered through
+    pub face: Directions,
+    pub distance: f32,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum RaycastResult {
+    Hit(RaycastHit),
+    /// Ray reached a chunk which isn't loaded before hitting anything
+    Unloaded {
+        chunk: ChunkPosition,
+        distance: f32,
+    },
+    Miss,
+}
+
 pub type VoxelWorldProcedural = VoxelWorld<ProceduralGenerator<CHSIZE>, CHSIZE>;
 
 #[derive(Resource)]
 pub struct VoxelWorld<G, const N: usize> {
     chunks: HashMap<ChunkPosition, Chunk<N>>,
+    /// Chunks in the middle of generation, not visible as loaded
+    proto_chunks: HashMap<ChunkPosition, ProtoChunk<N>>,
     chunk_changes: flurry::HashMap<ChunkPosition, Mutex<VecDeque<VoxChange>>>,
     dirty: flurry::HashSet<ChunkPosition>,
-    procedural: G,
+    /// Dirty chunks changed by player edits, meshed before others
+    edited: flurry::HashSet<ChunkPosition>,
+    /// Chunks changed since they were last saved
+    modified: HashSet<ChunkPosition>,
+    /// Chunks whose meshes are outdated because light changed
+    light_changed: HashSet<ChunkPosition>,
+    /// World positions of voxels changed by applied voxel changes
+    voxels_changed: HashSet<IVec3>,
+    procedural: Arc<G>,
+    registry: Arc<BlockRegistry>,
 }
 
 impl<G, const N: usize> VoxelWorld<G, N>
@@ -44,15 +84,25 @@ where
     const NI: i32 = N as i32;
     const NF: f32 = N as f32;
 
-    pub fn new(generator: G) -> Self {
+    pub fn new(generator: G, registry: Arc<BlockRegistry>) -> Self {
         Self {
             chunks: Default::default(),
+            proto_chunks: Default::default(),
             chunk_changes: Default::default(),
             dirty: Default::default(),
-            procedural: generator,
+            edited: Default::default(),
+            modified: Default::default(),
+            light_changed: Default::default(),
+            voxels_changed: Default::default(),
+            procedural: Arc::new(generator),
+            registry,
         }
     }
 
+    pub fn registry(&self) -> &BlockRegistry {
+        &self.registry
+    }
+
     pub fn chunks(&self) -> &HashMap<ChunkPosition, Chunk<N>> {
         &self.chunks
     }
@@ -61,6 +111,10 @@ where
         &self.dirty
     }
 
+    pub fn edited(&self) -> &flurry::HashSet<ChunkPosition> {
+        &self.edited
+    }
+
     pub fn chunk_changes(&self) -> &flurry::HashMap<ChunkPosition, Mutex<VecDeque<VoxChange>>> {
         &self.chunk_changes
     }
@@ -77,14 +131,210 @@ where
         self.chunks.get_mut(pos)
     }
 
+    /// Generates the chunk in isolation, without decorations
     pub fn gen_chunk(&self, pos: &ChunkPosition) -> Chunk<N> {
-        let mut c = Chunk::<N>::new();
-        self.procedural.fill_random(pos, c.data_mut());
-        c
+        self.procedural.generate(pos)
+    }
+
+    /// Generator shared with generation running off the world
+    pub fn generator(&self) -> Arc<G> {
+        self.procedural.clone()
+    }
+
+    /// Generation stage the chunk reached, `None` if generation hasn't started
+    pub fn stage_of(&self, pos: &ChunkPosition) -> Option<GenerationStage> {
+        if self.chunks.contains_key(pos) {
+            Some(GenerationStage::Finalized)
+        } else {
+            self.proto_chunks.get(pos).map(|proto| proto.stage)
+        }
+    }
+
+    pub fn proto_chunks(&self) -> impl Iterator<Item = &ChunkPosition> {
+        self.proto_chunks.keys()
+    }
+
+    /// Chunks whose generation must start before `pos` can be completed without generating terrain
+    pub fn terrain_missing_for(
+        &self,
+        pos: &ChunkPosition,
+    ) -> impl Iterator<Item = ChunkPosition> + '_ {
+        generation_stages::within(*pos, generation_stages::FINALIZATION_RADIUS)
+            .filter(|p| self.stage_of(p).is_none())
+    }
+
+    /// Inserts a chunk generated up to the carved stage off the world, see `VoxelGenerator::generate`.
+    /// Ignored if generation of the chunk already started
+    pub fn insert_carved(&mut self, pos: ChunkPosition, chunk: Chunk<N>) {
+        if self.stage_of(&pos).is_some() {
+            return;
+        }
+        let stage = GenerationStage::Carved;
+        self.proto_chunks.insert(pos, ProtoChunk { stage, chunk });
+        self.replay_decorations(pos);
+    }
+
+    /// Drops chunks in the middle of generation, they're regenerated when needed again
+    pub fn remove_proto_chunks(&mut self, positions: &[ChunkPosition]) {
+        for pos in positions {
+            self.proto_chunks.remove(pos);
+        }
+    }
+
+    /// Runs generation stages of the chunk and its neighbours until it's finalized and inserted.
+    /// A `stored` chunk, loaded from region files, replaces the generated one
+    pub fn complete_chunk(&mut self, pos: &ChunkPosition, stored: Option<Chunk<N>>) {
+        if self.chunks.contains_key(pos) {
+            return;
+        }
+        self.advance_to(*pos, GenerationStage::Decorated);
+        if let Some(requirement) = GenerationStage::Finalized.neighbour_requirement() {
+            for neighbour in generation_stages::neighbours(*pos) {
+                self.advance_to(neighbour, requirement);
+            }
+        }
+
+        let generated = self.proto_chunks.remove(pos).unwrap().chunk;
+        self.insert_at(pos, stored.unwrap_or(generated));
+    }
+
+    /// Runs stages of the chunk up to `stage`, which can't be `Finalized`,
+    /// advancing neighbours first as required
+    fn advance_to(&mut self, pos: ChunkPosition, stage: GenerationStage) {
+        debug_assert!(stage < GenerationStage::Finalized);
+        loop {
+            let next = match self.stage_of(&pos) {
+                Some(current) if current >= stage => return,
+                Some(current) => current.next().unwrap(),
+                None => GenerationStage::Terrain,
+            };
+            if let Some(requirement) = next.neighbour_requirement() {
+                for neighbour in generation_stages::neighbours(pos) {
+                    self.advance_to(neighbour, requirement);
+                }
+            }
+            self.run_stage(pos, next);
+        }
+    }
+
+    fn run_stage(&mut self, pos: ChunkPosition, stage: GenerationStage) {
+        match stage {
+            GenerationStage::Terrain => {
+                let mut chunk = Chunk::<N>::new();
+                self.procedural.fill_random(&pos, chunk.data_mut());
+                self.proto_chunks.insert(pos, ProtoChunk { stage, chunk });
+                return;
+            }
+            GenerationStage::Carved => {
+                let proto = self.proto_chunks.get_mut(&pos).unwrap();
+                self.procedural.carve(&pos, proto.chunk.data_mut());
+                self.replay_decorations(pos);
+            }
+            GenerationStage::Decorated => {
+                let mut region = DecorationRegion::new(pos, &mut self.proto_chunks, &self.chunks);
+                self.procedural.decorate(&mut region);
+            }
+            GenerationStage::Finalized => unreachable!("finalized chunks are inserted directly"),
+        }
+        self.proto_chunks.get_mut(&pos).unwrap().stage = stage;
     }
 
+    /// Writes decorations of neighbours decorated before the chunk was dropped and regenerated
+    fn replay_decorations(&mut self, pos: ChunkPosition) {
+        for neighbour in generation_stages::neighbours(pos) {
+            if self
+                .stage_of(&neighbour)
+                .is_some_and(|s| s >= GenerationStage::Decorated)
+            {
+                let mut region =
+                    DecorationRegion::new(neighbour, &mut self.proto_chunks, &self.chunks)
+                        .write_only(pos);
+                self.procedural.decorate(&mut region);
+            }
+        }
+    }
+
+    /// Inserts the chunk and lights it together with its loaded neighbours
     pub fn insert_at(&mut self, pos: &ChunkPosition, chunk: Chunk<N>) {
         self.chunks.insert(*pos, chunk);
+
+        let mut propagator =
+            LightPropagator::new(&mut self.chunks, &self.registry, &mut self.light_changed);
+        propagator.seed_chunk(*pos);
+        propagator.propagate();
+    }
+
+    pub fn light_at(&self, chunk: &ChunkPosition, ind: &[usize; 3]) -> Option<LightLevel> {
+        self.get_chunk_at(chunk).map(|c| c.light()[*ind])
+    }
+
+    /// Returns and forgets chunks with changed light since the last call
+    pub fn take_light_changed(&mut self) -> HashSet<ChunkPosition> {
+        std::mem::take(&mut self.light_changed)
+    }
+
+    /// Returns and forgets positions of voxels changed since the last call
+    pub fn take_voxels_changed(&mut self) -> HashSet<IVec3> {
+        std::mem::take(&mut self.voxels_changed)
+    }
+
+    /// Removes the chunk together with its pending changes and dirty mark
+    pub fn remove_chunk(&mut self, pos: &ChunkPosition) -> Option<Chunk<N>> {
+        self.dirty.pin().remove(pos);
+        self.edited.pin().remove(pos);
+        self.chunk_changes.pin().remove(pos);
+        self.modified.remove(pos);
+        self.chunks.remove(pos)
+    }
+
+    /// Removes chunks, saving modified ones to `storage` first if it's provided.
+    /// Pending changes are applied beforehand so they're saved too,
+    /// light the chunks spread into loaded neighbours is removed afterwards.
+    /// Nothing is removed if saving fails.
+    pub fn unload_chunks(
+        &mut self,
+        positions: &[ChunkPosition],
+        storage: Option<&mut RegionStorage>,
+    ) -> error::Result<()> {
+        self.apply_voxel_changes();
+        if let Some(storage) = storage {
+            let chunks = &self.chunks;
+            storage.save_chunks(
+                positions
+                    .iter()
+                    .filter(|pos| self.modified.contains(pos))
+                    .filter_map(|pos| chunks.get(pos).map(|chunk| (*pos, chunk))),
+            )?;
+        }
+        for pos in positions {
+            self.remove_chunk(pos);
+        }
+        let mut propagator =
+            LightPropagator::new(&mut self.chunks, &self.registry, &mut self.light_changed);
+        for pos in positions {
+            propagator.unseed_chunk(*pos);
+        }
+        propagator.propagate();
+        Ok(())
+    }
+
+    pub fn modified(&self) -> &HashSet<ChunkPosition> {
+        &self.modified
+    }
+
+    /// Saves chunks changed since the last save, keeping them marked as modified on failure
+    pub fn save_modified(&mut self, storage: &mut RegionStorage) -> error::Result<()> {
+        let modified = std::mem::take(&mut self.modified);
+        let chunks = &self.chunks;
+        let result = storage.save_chunks(
+            modified
+                .iter()
+                .filter_map(|pos| chunks.get(pos).map(|chunk| (*pos, chunk))),
+        );
+        if result.is_err() {
+            self.modified = modified;
+        }
+        result
     }
 
     pub fn voxel_at_pos(&self, pos: &Vec3) -> Option<Voxel> {
@@ -100,6 +350,24 @@ where
         self.set_voxel_at(&ch, &ind, new_vox)
     }
     pub fn set_voxel_at(&self, chunk: &ChunkPosition, ind: &[usize; 3], new_vox: Voxel) {
+        self.push_change(chunk, VoxChange::new(*ind, new_vox));
+    }
+
+    pub fn set_voxel_by_player_at_pos(&self, pos: &Vec3, new_vox: Voxel) {
+        let (ch, ind) = Self::to_ch_pos_index(pos);
+        self.set_voxel_by_player(&ch, &ind, new_vox)
+    }
+
+    /// Like `set_voxel_at`, but affected chunks are marked edited to be meshed first
+    pub fn set_voxel_by_player(&self, chunk: &ChunkPosition, ind: &[usize; 3], new_vox: Voxel) {
+        let change = VoxChange {
+            by_player: true,
+            ..VoxChange::new(*ind, new_vox)
+        };
+        self.push_change(chunk, change);
+    }
+
+    fn push_change(&self, chunk: &ChunkPosition, change: VoxChange) {
         let chunk_changes = self.chunk_changes.pin();
         let ch_list = match chunk_changes.get(chunk) {
             Some(change_list) => change_list,
@@ -111,50 +379,88 @@ where
             }
         };
         let mut ch_list = ch_list.lock().unwrap();
-        ch_list.push_back(VoxChange::new(*ind, new_vox));
-    }
-
-    pub fn mesh(&self, chpos: &ChunkPosition) -> ChunkMeshData {
-        let onef: Vec3 = [1., 1., 1.].into();
-
-        let chunk = self.chunk_at(chpos);
-        let mut chunk_mesh = ChunkMeshData::new();
-        for x in 0..Self::NI {
-            for y in 0..Self::NI {
-                for z in 0..Self::NI {
-                    let pos: IVec3 = [x, y, z].into();
-                    let convert_vec: [usize; 3] = (pos).to_usize();
-                    if chunk.data()[convert_vec].is_transparent() {
-                        // if current voxel is transparent
-                        continue;
-                    }
-                    // if current voxel is solid
-                    for dir in Directions::all().into_iter() {
-                        let dir_vec = dir.to_ivec();
-                        let spos = pos + dir_vec;
-                        let adj_vox = match Chunk::<N>::chunk_voxel_index_wrap(&spos) {
-                            Some(index) => {
-                                let convert_vec: [usize; 3] = index.to_usize();
-                                self.chunk_at(&ChunkPosition::new(chpos.pos + dir_vec))
-                                    .data()[convert_vec]
-                            }
-                            None => {
-                                let convert_vec: [usize; 3] = spos.to_usize();
-                                chunk.data()[convert_vec]
-                            }
-                        };
+        ch_list.push_back(change);
+    }
 
-                        if adj_vox.is_transparent() {
-                            // if adjacent voxel is transparent
-                            let convert_vec: Vec3 = pos.convert_vec();
-                            chunk_mesh.insert_quad(convert_vec + onef / 2., dir);
-                        }
-                    }
+    /// Walks voxels along the ray (Amanatides-Woo) until a rendered voxel which isn't a fluid is hit,
+    /// so plants can be targeted too
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> RaycastResult {
+        let direction = direction.normalize_or_zero();
+        if direction == Vec3::ZERO {
+            return RaycastResult::Miss;
+        }
+
+        let mut voxel = origin.floor().as_ivec3();
+        let step = IVec3::from_array(direction.to_array().map(|d| match d {
+            d if d > 0. => 1,
+            d if d < 0. => -1,
+            _ => 0,
+        }));
+        let t_delta = direction.recip().abs();
+        let mut t_max = Vec3::from_array([0, 1, 2].map(|axis| match step[axis] {
+            0 => f32::INFINITY,
+            s if s > 0 => (voxel[axis] as f32 + 1. - origin[axis]) * t_delta[axis],
+            _ => (origin[axis] - voxel[axis] as f32) * t_delta[axis],
+        }));
+
+        // if the ray starts inside a solid voxel report the face it's looking out of
+        let main_axis = min_axis(-direction.abs());
+        let mut face_vec = IVec3::ZERO;
+        face_vec[main_axis] = -step[main_axis];
+        let mut distance = 0.;
+        loop {
+            let (chunk, index) = Self::to_ch_pos_index(&(voxel.as_vec3() + Vec3::splat(0.5)));
+            match self.voxel_at(&chunk, &index) {
+                None => return RaycastResult::Unloaded { chunk, distance },
+                Some(vox)
+                    if self.registry.get(vox).is_rendered()
+                        && self.registry.fluid_level(vox).is_none() =>
+                {
+                    return RaycastResult::Hit(RaycastHit {
+                        chunk,
+                        index,
+                        voxel: vox,
+                        face: face_vec.into(),
+                        distance,
+                    })
                 }
+                Some(_) => {}
+            }
+
+            let axis = min_axis(t_max);
+            if t_max[axis] > max_distance {
+                return RaycastResult::Miss;
             }
+            distance = t_max[axis];
+            voxel[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            face_vec = IVec3::ZERO;
+            face_vec[axis] = -step[axis];
         }
+    }
+
+    /// Copy of the chunk and the layer around it for meshing off the world.
+    /// `None` if the chunk or any of its face neighbours isn't loaded
+    pub fn mesh_snapshot(&self, chpos: &ChunkPosition) -> Option<MeshSnapshot<N>> {
+        MeshSnapshot::new(chpos, |pos| self.get_chunk_at(pos), self.registry.clone())
+    }
 
-        chunk_mesh
+    pub fn mesh_with(&self, chpos: &ChunkPosition, mode: MeshingMode) -> ChunkMeshes {
+        self.loaded_snapshot(chpos).mesh_with(mode)
+    }
+
+    pub fn mesh(&self, chpos: &ChunkPosition) -> ChunkMeshes {
+        self.loaded_snapshot(chpos).mesh()
+    }
+
+    /// Meshes the chunk merging coplanar equally lit and occluded faces of the same voxel id into maximal rectangles
+    pub fn mesh_greedy(&self, chpos: &ChunkPosition) -> ChunkMeshes {
+        self.loaded_snapshot(chpos).mesh_greedy()
+    }
+
+    fn loaded_snapshot(&self, chpos: &ChunkPosition) -> MeshSnapshot<N> {
+        self.mesh_snapshot(chpos)
+            .expect("Chunk and its neighbours expected.")
     }
 
     pub fn apply_voxel_changes(&mut self) {
@@ -164,6 +470,9 @@ where
         let chunks = &mut self.chunks;
         let chunk_changes = self.chunk_changes.pin();
         let dirty = self.dirty.pin();
+        let edited = self.edited.pin();
+        let modified = &mut self.modified;
+        let mut changed_voxels = Vec::new();
 
         chunks.iter_mut().for_each(|(pos, chunk)| {
             let changes = match chunk_changes.get(pos) {
@@ -175,24 +484,53 @@ where
                 chunk.data_mut()[change.index] = change.new_vox;
 
                 dirty.insert(*pos);
+                if change.by_player {
+                    edited.insert(*pos);
+                }
+                modified.insert(*pos);
+                changed_voxels.push(Self::voxel_pos(pos, &change.index));
 
                 // if on a border
                 let border = Chunk::<N>::is_on_border(&change.index);
                 if let Some(border_dir) = border {
-                    borders_changed.insert((*pos, border_dir));
+                    borders_changed.insert((*pos, border_dir, change.by_player));
                 }
             });
             list.clear();
         });
 
-        for (chunk_pos, adj_dir) in borders_changed.iter() {
+        for (chunk_pos, adj_dir, by_player) in borders_changed.iter() {
+            // voxels on edges and corners are sampled by ambient occlusion
+            // of every chunk sharing them
             let adj_vec = adj_dir.to_ivec();
-            let next_chunk_pos = chunk_pos.pos + adj_vec;
+            for x in [0, adj_vec.x] {
+                for y in [0, adj_vec.y] {
+                    for z in [0, adj_vec.z] {
+                        let offset = IVec3::new(x, y, z);
+                        if offset != IVec3::ZERO {
+                            let pos = ChunkPosition::new(chunk_pos.pos + offset);
+                            dirty.insert(pos);
+                            if *by_player {
+                                edited.insert(pos);
+                            }
+                        }
+                    }
+                }
+            }
+        }
 
-            dirty.insert(ChunkPosition {
-                pos: next_chunk_pos,
-            });
+        let mut propagator =
+            LightPropagator::new(&mut self.chunks, &self.registry, &mut self.light_changed);
+        for pos in changed_voxels.iter() {
+            propagator.update_voxel(*pos);
         }
+        propagator.propagate();
+        self.voxels_changed.extend(changed_voxels);
+    }
+
+    /// World position of the voxel's minimum corner
+    pub fn voxel_pos(chunk: &ChunkPosition, ind: &[usize; 3]) -> IVec3 {
+        chunk.pos * Self::NI + IVec3::new(ind[0] as i32, ind[1] as i32, ind[2] as i32)
     }
 
     pub fn to_ch_pos_index(pos: &Vec3) -> (ChunkPosition, [usize; 3]) {
@@ -213,3 +551,561 @@ where
         (ChunkPosition { pos: ch_pos }, index)
     }
 }
+
+fn min_axis(v: Vec3) -> usize {
+    if v.x <= v.y && v.x <= v.z {
+        0
+    } else if v.y <= v.z {
+        1
+    } else {
+        2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::VecExtensions,
+        voxels::{
+            block_ticks::VoxelAccess,
+            light::MAX_LIGHT,
+            test_utils::{floor_world, named, world_with_floor},
+        },
+    };
+    use bevy::render::mesh::Indices;
+    use ndarray::Array3;
+    use rstest::rstest;
+
+    const SMALLCH: usize = 4;
+
+    /// Fills everything below y = 2 of the chunk at the origin with voxel id 1
+    struct SlabGenerator;
+
+    impl VoxelGenerator<SMALLCH> for SlabGenerator {
+        fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+            if pos.pos != IVec3::ZERO {
+                return;
+            }
+            arr.indexed_iter_mut()
+                .filter(|((_, y, _), _)| *y < 2)
+                .for_each(|(_, v)| *v = Voxel { id: 1 });
+        }
+    }
+
+    /// Fills everything below y = 2 of chunks at y = 0 with voxel id 1
+    struct GroundGenerator;
+
+    impl VoxelGenerator<SMALLCH> for GroundGenerator {
+        fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+            if pos.pos.y != 0 {
+                return;
+            }
+            arr.indexed_iter_mut()
+                .filter(|((_, y, _), _)| *y < 2)
+                .for_each(|(_, v)| *v = Voxel { id: 1 });
+        }
+    }
+
+    /// Fills the chunk at the origin with a 3d checkerboard, the worst case for face count
+    struct CheckerboardGenerator;
+
+    impl VoxelGenerator<CHSIZE> for CheckerboardGenerator {
+        fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+            if pos.pos != IVec3::ZERO {
+                return;
+            }
+            arr.indexed_iter_mut()
+                .filter(|((x, y, z), _)| (x + y + z) % 2 == 0)
+                .for_each(|(_, v)| *v = Voxel { id: 1 });
+        }
+    }
+
+    /// Places voxel id 1 at the listed world positions
+    struct BlocksGenerator(Vec<IVec3>);
+
+    impl VoxelGenerator<SMALLCH> for BlocksGenerator {
+        fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+            let n = SMALLCH as i32;
+            for block in &self.0 {
+                let chunk = IVec3::new(
+                    block.x.div_euclid(n),
+                    block.y.div_euclid(n),
+                    block.z.div_euclid(n),
+                );
+                if chunk == pos.pos {
+                    arr[(*block - chunk * n).to_usize()] = Voxel { id: 1 };
+                }
+            }
+        }
+    }
+
+    /// Places voxels at the listed world positions of the chunk at the origin
+    struct VoxelsGenerator(Vec<(IVec3, Voxel)>);
+
+    impl VoxelGenerator<SMALLCH> for VoxelsGenerator {
+        fn fill_random(&self, pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+            if pos.pos != IVec3::ZERO {
+                return;
+            }
+            for (block, vox) in &self.0 {
+                arr[block.to_usize()] = *vox;
+            }
+        }
+    }
+
+    /// Fills every chunk with voxel id 1
+    struct SolidGenerator;
+
+    impl VoxelGenerator<SMALLCH> for SolidGenerator {
+        fn fill_random(&self, _pos: &ChunkPosition, arr: &mut Array3<Voxel>) {
+            arr.fill(Voxel { id: 1 });
+        }
+    }
+
+    /// Marks the minimum corner of every chunk and the voxel west of it with voxel id 3
+    struct CornerMarkGenerator;
+
+    impl VoxelGenerator<SMALLCH> for CornerMarkGenerator {
+        fn fill_random(&self, _pos: &ChunkPosition, _arr: &mut Array3<Voxel>) {}
+
+        fn decorate(&self, region: &mut DecorationRegion<SMALLCH>) {
+            let origin = region.origin();
+            region.set(origin, Voxel { id: 3 });
+            region.set(origin - IVec3::X, Voxel { id: 3 });
+        }
+    }
+
+    fn world_around_origin<G, const N: usize>(generator: G) -> VoxelWorld<G, N>
+    where
+        G: VoxelGenerator<N> + Send + Sync,
+    {
+        let mut world = VoxelWorld::new(generator, Default::default());
+        for pos in
+            std::iter::once(IVec3::ZERO).chain(Directions::all().into_iter().map(|d| d.to_ivec()))
+        {
+            let pos = ChunkPosition::new(pos);
+            let chunk = world.gen_chunk(&pos);
+            world.insert_at(&pos, chunk);
+        }
+        world
+    }
+
+    #[test]
+    fn greedy_mesh_merges_slab_faces() {
+        let world = world_around_origin(SlabGenerator);
+
+        let naive = world.mesh_with(&ChunkPosition::default(), MeshingMode::Naive);
+        let greedy = world.mesh_with(&ChunkPosition::default(), MeshingMode::Greedy);
+
+        // top and bottom: 4x4 faces each, sides: 4x2 faces each
+        assert_eq!(naive.vertex_count(), (2 * 16 + 4 * 8) * 4);
+        // one quad per side
+        assert_eq!(greedy.vertex_count(), 6 * 4);
+    }
+
+    #[test]
+    fn translucent_faces_in_separate_part() {
+        let registry = BlockRegistry::from_file_ron("config/blocks.ron").unwrap();
+        let glass = Voxel {
+            id: registry.id_of("glass").unwrap(),
+        };
+        let dirt = Voxel {
+            id: registry.id_of("dirt").unwrap(),
+        };
+        let generator = VoxelsGenerator(vec![
+            (IVec3::new(1, 1, 1), glass),
+            (IVec3::new(2, 1, 1), glass),
+            (IVec3::new(1, 2, 1), dirt),
+        ]);
+        let mut world = VoxelWorld::new(generator, Arc::new(registry));
+        for pos in
+            std::iter::once(IVec3::ZERO).chain(Directions::all().into_iter().map(|d| d.to_ivec()))
+        {
+            let pos = ChunkPosition::new(pos);
+            let chunk = world.gen_chunk(&pos);
+            world.insert_at(&pos, chunk);
+        }
+
+        let mesh = world.mesh(&ChunkPosition::default());
+
+        // faces between the glass blocks and the one under dirt are culled
+        assert_eq!(mesh.translucent.vertex_count(), 9 * 4);
+        // dirt keeps its face against glass
+        assert_eq!(mesh.opaque.vertex_count(), 6 * 4);
+    }
+
+    #[rstest(
+        generator,
+        level,
+        exp_vertices,
+        // the slab fills half of every merged voxel of the bottom layer
+        case(SlabGenerator, 1, 6 * 4),
+        case(SlabGenerator, 2, 6 * 4),
+        // a single voxel isn't enough to keep a merged voxel
+        case(BlocksGenerator(vec![IVec3::new(1, 1, 1)]), 0, 6 * 4),
+        case(BlocksGenerator(vec![IVec3::new(1, 1, 1)]), 1, 0)
+    )]
+    fn downsampled_mesh(
+        generator: impl VoxelGenerator<SMALLCH> + Send + Sync,
+        level: u8,
+        exp_vertices: usize,
+    ) {
+        let world = world_around_origin(generator);
+
+        let snapshot = world.mesh_snapshot(&ChunkPosition::default()).unwrap();
+        let mesh = snapshot.with_lod(level, Directions::empty()).mesh_greedy();
+
+        assert_eq!(mesh.vertex_count(), exp_vertices);
+    }
+
+    #[rstest(
+        level,
+        seams,
+        exp_vertices,
+        case(0, Directions::empty(), 0),
+        case(0, Directions::EAST | Directions::UP, 2 * 4),
+        case(1, Directions::EAST, 4),
+        case(2, Directions::all(), 6 * 4)
+    )]
+    fn seams_mesh_covered_border_faces(level: u8, seams: Directions, exp_vertices: usize) {
+        let world = world_around_origin(SolidGenerator);
+
+        let snapshot = world.mesh_snapshot(&ChunkPosition::default()).unwrap();
+        let mesh = snapshot.with_lod(level, seams).mesh_greedy();
+
+        assert_eq!(mesh.vertex_count(), exp_vertices);
+    }
+
+    #[rstest(
+        level,
+        pos,
+        // below the lit air on top of the ground
+        case(0, IVec3::new(3, 1, 1)),
+        // under the merged air above the ground
+        case(1, IVec3::new(1, 0, 0))
+    )]
+    fn seam_faces_lit_around_covering_voxel(level: u8, pos: IVec3) {
+        let world = world_around_origin(GroundGenerator);
+
+        let snapshot = world.mesh_snapshot(&ChunkPosition::default()).unwrap();
+        let snapshot = snapshot.with_lod(level, Directions::EAST);
+        let (light, _) = snapshot
+            .visible_face(pos, Directions::EAST, Voxel { id: 1 })
+            .unwrap();
+
+        assert!(light.sky() > 0);
+    }
+
+    #[rstest(
+        blocks,
+        pos,
+        exp_ao,
+        // nothing around the top face
+        case(vec![], IVec3::new(1, 0, 1), [3, 3, 3, 3]),
+        // one side on the east
+        case(vec![IVec3::new(2, 1, 1)], IVec3::new(1, 0, 1), [3, 2, 3, 2]),
+        // corner only
+        case(vec![IVec3::new(2, 1, 2)], IVec3::new(1, 0, 1), [3, 3, 3, 2]),
+        // both sides occlude regardless of the corner
+        case(vec![IVec3::new(2, 1, 1), IVec3::new(1, 1, 2)], IVec3::new(1, 0, 1), [3, 2, 2, 0]),
+        case(
+            vec![IVec3::new(2, 1, 1), IVec3::new(1, 1, 2), IVec3::new(2, 1, 2)],
+            IVec3::new(1, 0, 1),
+            [3, 2, 2, 0]
+        ),
+        // side in the western neighbour chunk
+        case(vec![IVec3::new(-1, 1, 0)], IVec3::new(0, 0, 0), [2, 3, 2, 3]),
+        // corner in the diagonal neighbour chunk
+        case(vec![IVec3::new(-1, 1, -1)], IVec3::new(0, 0, 0), [2, 3, 3, 3])
+    )]
+    fn top_face_ao(blocks: Vec<IVec3>, pos: IVec3, exp_ao: [u8; 4]) {
+        let mut world = world_around_origin(BlocksGenerator(blocks));
+        let diagonal = ChunkPosition::new(IVec3::new(-1, 0, -1));
+        let chunk = world.gen_chunk(&diagonal);
+        world.insert_at(&diagonal, chunk);
+
+        let origin = ChunkPosition::default();
+        let snapshot = world.mesh_snapshot(&origin).unwrap();
+        let ao = snapshot.face_ao(pos, Directions::UP);
+
+        assert_eq!(ao, exp_ao);
+    }
+
+    #[test]
+    fn unloaded_chunks_dont_occlude() {
+        let world = world_around_origin(BlocksGenerator(vec![IVec3::new(-1, 1, -1)]));
+
+        let origin = ChunkPosition::default();
+        let snapshot = world.mesh_snapshot(&origin).unwrap();
+        let ao = snapshot.face_ao(IVec3::ZERO, Directions::UP);
+
+        assert_eq!(ao, [3; 4]);
+    }
+
+    #[test]
+    fn checkerboard_mesh_indices_in_range() {
+        let world = world_around_origin(CheckerboardGenerator);
+
+        let mesh = world.mesh(&ChunkPosition::default()).opaque;
+        let vertex_count = mesh.vertex_count();
+        assert!(vertex_count > u16::MAX as usize);
+
+        match mesh.indices() {
+            Indices::U32(indices) => {
+                assert!(indices.iter().all(|&i| (i as usize) < vertex_count))
+            }
+            Indices::U16(_) => panic!("u16 indices can't address {vertex_count} vertices"),
+        }
+    }
+
+    #[test]
+    fn applied_changes_saved() {
+        let mut world = world_around_origin(SlabGenerator);
+        let dir = std::env::temp_dir().join(format!("voxel_world_save_{}", std::process::id()));
+        let mut storage = RegionStorage::new(&dir);
+
+        world.set_voxel_at(&ChunkPosition::default(), &[1, 3, 2], Voxel { id: 7 });
+        world.apply_voxel_changes();
+        assert!(world.modified().contains(&ChunkPosition::default()));
+        world.save_modified(&mut storage).unwrap();
+        assert!(world.modified().is_empty());
+
+        let loaded = RegionStorage::new(&dir)
+            .load_chunk::<SMALLCH>(&ChunkPosition::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.data()[[1, 3, 2]].id, 7);
+        assert_eq!(loaded.data()[[0, 0, 0]].id, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_player_changes_mark_edited() {
+        let mut world: VoxelWorld<_, SMALLCH> = world_around_origin(SlabGenerator);
+        let origin = ChunkPosition::default();
+        let west = ChunkPosition::new(IVec3::NEG_X);
+
+        world.set_voxel_at(&origin, &[1, 1, 1], Voxel { id: 4 });
+        world.apply_voxel_changes();
+        assert!(world.dirty().pin().contains(&origin));
+        assert!(world.edited().pin().is_empty());
+
+        world.set_voxel_by_player(&origin, &[0, 1, 1], Voxel { id: 4 });
+        world.apply_voxel_changes();
+        assert!(world.edited().pin().contains(&origin));
+        assert!(world.edited().pin().contains(&west));
+    }
+
+    #[test]
+    fn unload_saves_modified_and_cleans_up() {
+        let mut world = world_around_origin(SlabGenerator);
+        let dir = std::env::temp_dir().join(format!("voxel_world_unload_{}", std::process::id()));
+        let mut storage = RegionStorage::new(&dir);
+        let origin = ChunkPosition::default();
+        let up = ChunkPosition::new(IVec3::Y);
+        let east = ChunkPosition::new(IVec3::X);
+
+        world.set_voxel_by_player(&origin, &[0, 3, 0], Voxel { id: 4 });
+        world.apply_voxel_changes();
+        world.set_voxel_at(&up, &[0, 0, 0], Voxel { id: 5 });
+        assert!(world.dirty().pin().contains(&origin));
+        assert!(world.edited().pin().contains(&origin));
+
+        world
+            .unload_chunks(&[origin, up, east], Some(&mut storage))
+            .unwrap();
+
+        assert!(world.get_chunk_at(&origin).is_none());
+        assert!(world.get_chunk_at(&up).is_none());
+        assert!(world.get_chunk_at(&east).is_none());
+        assert!(!world.dirty().pin().contains(&origin));
+        assert!(!world.edited().pin().contains(&origin));
+        assert!(world.chunk_changes().pin().get(&up).is_none());
+        assert!(world.modified().is_empty());
+
+        let mut storage = RegionStorage::new(&dir);
+        let saved = storage.load_chunk::<SMALLCH>(&origin).unwrap().unwrap();
+        assert_eq!(saved.data()[[0, 3, 0]].id, 4);
+        // changes still pending when unloading aren't lost
+        let saved = storage.load_chunk::<SMALLCH>(&up).unwrap().unwrap();
+        assert_eq!(saved.data()[[0, 0, 0]].id, 5);
+        // unmodified chunks are regenerated instead
+        assert!(storage.load_chunk::<SMALLCH>(&east).unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unload_removes_light_spread_into_neighbours() {
+        let mut world = world_with_floor("air");
+        let lava = named(world.registry(), "lava");
+        let (origin, east) = (ChunkPosition::default(), ChunkPosition::new(IVec3::X));
+        world.set_voxel(IVec3::new(16, 5, 5), lava);
+        world.apply_voxel_changes();
+        assert!(world.light_at(&origin, &[15, 5, 5]).unwrap().block() > 0);
+
+        world.unload_chunks(&[east], None).unwrap();
+
+        let chunk = world.chunk_at(&origin);
+        assert!(chunk.light().iter().all(|l| l.block() == 0));
+        assert!(chunk.light().iter().all(|l| l.sky() == MAX_LIGHT));
+    }
+
+    #[test]
+    fn decorations_reach_into_neighbours() {
+        let mut world: VoxelWorld<_, SMALLCH> =
+            VoxelWorld::new(CornerMarkGenerator, Default::default());
+        let origin = ChunkPosition::default();
+        let east = ChunkPosition::new(IVec3::X);
+
+        world.complete_chunk(&origin, None);
+
+        let chunk = world.chunk_at(&origin);
+        assert_eq!(chunk.data()[[0, 0, 0]].id, 3);
+        // written by the eastern neighbour's decoration
+        assert_eq!(chunk.data()[[SMALLCH - 1, 0, 0]].id, 3);
+        assert!(world.get_chunk_at(&east).is_none());
+        assert_eq!(world.stage_of(&east), Some(GenerationStage::Decorated));
+        assert_eq!(world.stage_of(&ChunkPosition::new(IVec3::X * 2)), None);
+    }
+
+    #[test]
+    fn decorations_replayed_into_regenerated_chunks() {
+        let mut world: VoxelWorld<_, SMALLCH> =
+            VoxelWorld::new(CornerMarkGenerator, Default::default());
+        let west = ChunkPosition::new(IVec3::NEG_X);
+        world.complete_chunk(&ChunkPosition::default(), None);
+
+        world.remove_proto_chunks(&[west]);
+        world.complete_chunk(&west, None);
+
+        // from the already finalized chunk at the origin
+        assert_eq!(world.chunk_at(&west).data()[[SMALLCH - 1, 0, 0]].id, 3);
+    }
+
+    #[test]
+    fn decorations_replayed_into_chunks_generated_later() {
+        let mut world: VoxelWorld<_, SMALLCH> =
+            VoxelWorld::new(CornerMarkGenerator, Default::default());
+        let far_west = ChunkPosition::new(IVec3::NEG_X * 2);
+        world.complete_chunk(&ChunkPosition::default(), None);
+        assert_eq!(world.stage_of(&far_west), None);
+
+        let chunk = world.gen_chunk(&far_west);
+        world.insert_carved(far_west, chunk);
+        world.complete_chunk(&far_west, None);
+
+        // from the western neighbour of the origin, decorated before the chunk was generated
+        assert_eq!(world.chunk_at(&far_west).data()[[SMALLCH - 1, 0, 0]].id, 3);
+    }
+
+    #[test]
+    fn decorations_dont_overwrite_finalized_chunks() {
+        let mut world: VoxelWorld<_, SMALLCH> =
+            VoxelWorld::new(CornerMarkGenerator, Default::default());
+        let origin = ChunkPosition::default();
+        let east = ChunkPosition::new(IVec3::X);
+        world.complete_chunk(&origin, None);
+        world.set_voxel_at(&origin, &[SMALLCH - 1, 0, 0], Voxel { id: 0 });
+        world.apply_voxel_changes();
+
+        world.remove_proto_chunks(&[east]);
+        world.complete_chunk(&east, None);
+
+        assert_eq!(world.chunk_at(&origin).data()[[SMALLCH - 1, 0, 0]].id, 0);
+        assert_eq!(world.chunk_at(&east).data()[[0, 0, 0]].id, 3);
+    }
+
+    #[test]
+    fn stored_chunk_replaces_generated() {
+        let dir = std::env::temp_dir().join(format!("voxel_world_complete_{}", std::process::id()));
+        let mut storage = RegionStorage::new(&dir);
+        let origin = ChunkPosition::default();
+
+        let mut world: VoxelWorld<_, SMALLCH> =
+            VoxelWorld::new(CornerMarkGenerator, Default::default());
+        world.complete_chunk(&origin, None);
+        world.set_voxel_at(&origin, &[0, 0, 0], Voxel { id: 5 });
+        world.apply_voxel_changes();
+        world.save_modified(&mut storage).unwrap();
+
+        let mut world: VoxelWorld<_, SMALLCH> =
+            VoxelWorld::new(CornerMarkGenerator, Default::default());
+        let stored = storage.load_chunk(&origin).unwrap();
+        world.complete_chunk(&origin, stored);
+
+        assert_eq!(world.chunk_at(&origin).data()[[0, 0, 0]].id, 5);
+        assert_eq!(world.chunk_at(&origin).data()[[SMALLCH - 1, 0, 0]].id, 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rstest(origin, direction, exp_pos, exp_face, exp_distance,
+        case(Vec3::new(1.5, 3.5, 1.5), Vec3::NEG_Y, IVec3::new(1, 1, 1), Directions::UP, 1.5),
+        case(Vec3::new(-2.5, 1.5, 1.5), Vec3::X, IVec3::new(0, 1, 1), Directions::WEST, 2.5),
+        case(Vec3::new(6.5, 0.5, 2.5), Vec3::NEG_X, IVec3::new(3, 0, 2), Directions::EAST, 2.5),
+        case(Vec3::new(0.5, 3., 0.5), Vec3::new(1., -1., 0.), IVec3::new(1, 1, 0), Directions::UP, 2f32.sqrt()),
+        case(Vec3::new(2.5, 1.5, 2.5), Vec3::Z, IVec3::new(2, 1, 2), Directions::NORTH, 0.),
+    )]
+    fn raycast_hits(
+        origin: Vec3,
+        direction: Vec3,
+        exp_pos: IVec3,
+        exp_face: Directions,
+        exp_distance: f32,
+    ) {
+        let world = world_around_origin(SlabGenerator);
+
+        let RaycastResult::Hit(hit) = world.raycast(origin, direction, 10.) else {
+            panic!("expected a hit");
+        };
+        assert_eq!(hit.chunk, ChunkPosition::default());
+        assert_eq!(hit.index, exp_pos.to_usize());
+        assert_eq!(hit.voxel.id, 1);
+        assert_eq!(hit.face, exp_face);
+        assert!((hit.distance - exp_distance).abs() < 1e-5);
+    }
+
+    #[test]
+    fn raycast_hits_plants_and_passes_fluids() {
+        let mut world = floor_world();
+        let flower = named(world.registry(), "flower");
+        world.set_voxel(IVec3::new(1, 1, 1), flower);
+        world.set_voxel(IVec3::new(2, 1, 1), named(world.registry(), "water"));
+        world.apply_voxel_changes();
+
+        let RaycastResult::Hit(hit) = world.raycast(Vec3::new(1.5, 3.5, 1.5), Vec3::NEG_Y, 10.)
+        else {
+            panic!("expected a hit");
+        };
+        assert_eq!((hit.index, hit.voxel), ([1, 1, 1], flower));
+        let RaycastResult::Hit(hit) = world.raycast(Vec3::new(2.5, 3.5, 1.5), Vec3::NEG_Y, 10.)
+        else {
+            panic!("expected a hit");
+        };
+        assert_eq!(hit.index, [2, 0, 1]);
+    }
+
+    #[test]
+    fn raycast_miss_and_unloaded() {
+        let world = world_around_origin(SlabGenerator);
+
+        assert!(matches!(
+            world.raycast(Vec3::new(1.5, 3.5, 1.5), Vec3::NEG_Y, 1.),
+            RaycastResult::Miss
+        ));
+        assert!(matches!(
+            world.raycast(Vec3::new(1.5, 3.5, 1.5), Vec3::ZERO, 10.),
+            RaycastResult::Miss
+        ));
+        match world.raycast(Vec3::new(1.5, 3.5, 1.5), Vec3::Y, 10.) {
+            RaycastResult::Unloaded { chunk, distance } => {
+                assert_eq!(chunk.pos, IVec3::new(0, 2, 0));
+                assert!((distance - 4.5).abs() < 1e-5);
+            }
+            res => panic!("expected unloaded chunk, got {res:?}"),
+        }
+    }
+}